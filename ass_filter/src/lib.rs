@@ -0,0 +1,234 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ffi::CStr;
+use std::fs;
+use std::ptr;
+
+use interval_tree::IntervalTree;
+use libc::{c_char, c_double, c_int, c_uchar, c_uint, c_void};
+
+mod render;
+
+mod script;
+use script::{Event, Script};
+
+mod tags;
+
+#[derive(Default)]
+enum ScaleType {
+    None,
+    #[default]
+    Uniform,
+    NonUniform,
+}
+
+struct Config<'a> {
+    scale_type: ScaleType,
+    ass: &'a str,
+}
+
+struct Context {
+    scale_type: ScaleType,
+    play_res: (f64, f64),
+    tree: IntervalTree<Event>,
+}
+
+/// # Safety
+///
+/// `config` must be null or a NUL-terminated string and `user_data` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void) -> c_int {
+    unsafe {
+        *user_data = ptr::null_mut();
+    }
+
+    if config.is_null() {
+        eprintln!("got null config");
+        return 1;
+    }
+
+    let config = match parse_config(config) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("invalid config: {}", e);
+            return 1;
+        }
+    };
+
+    let script = match read_ass(config.ass) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {}", config.ass, e);
+            return 1;
+        }
+    };
+
+    let ctx = Context {
+        scale_type: config.scale_type,
+        play_res: script.play_res,
+        tree: IntervalTree::new(script.events),
+    };
+
+    unsafe {
+        *user_data = Box::into_raw(Box::new(ctx)) as *mut c_void;
+    }
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn filter_frame(
+    data: *mut c_uchar,
+    data_size: c_uint,
+    width: c_int,
+    height: c_int,
+    line_size: c_int,
+    ts_millis: c_double,
+    user_data: *mut c_void,
+) -> c_int {
+    if data.is_null() || width <= 0 || height <= 0 {
+        return 0;
+    }
+
+    let ctx = if user_data.is_null() {
+        eprintln!("no user data");
+        return 1;
+    } else {
+        unsafe { &*(user_data as *const Context) }
+    };
+
+    let events = ctx.tree.search(ts_millis);
+    if events.is_empty() {
+        return 0;
+    }
+
+    let cr = match new_cairo_context(data, data_size as usize, width, height, line_size) {
+        Ok(cr) => cr,
+        Err(status) => {
+            eprintln!("could not create cairo context: {:?}", status);
+            return 1;
+        }
+    };
+
+    scale(ctx, width as f64, height as f64, &cr);
+    render::render(&events, ctx.play_res, ts_millis, &cr);
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn filter_uninit(user_data: *mut c_void) {
+    if !user_data.is_null() {
+        unsafe {
+            drop(Box::from_raw(user_data as *mut Context));
+        }
+    }
+}
+
+fn parse_config<'a>(config: *const c_char) -> anyhow::Result<Config<'a>> {
+    let cstr = unsafe { CStr::from_ptr(config) };
+    let s = cstr.to_str()?;
+    let re = regex::Regex::new(r"^(?:scale_type=(none|uniform|non_uniform),)?ass=(.+)$").unwrap();
+    if let Some(cap) = re.captures(s) {
+        let scale_type = if let Some(st) = cap.get(1) {
+            match st.as_str() {
+                "none" => ScaleType::None,
+                "uniform" => ScaleType::Uniform,
+                _ => ScaleType::NonUniform,
+            }
+        } else {
+            ScaleType::default()
+        };
+
+        Ok(Config {
+            scale_type,
+            ass: cap.get(2).unwrap().as_str(),
+        })
+    } else {
+        Err(anyhow::anyhow!(s))
+    }
+}
+
+fn read_ass(ass: &str) -> anyhow::Result<Script> {
+    let s = fs::read_to_string(ass)?;
+    script::parse_script(&s)
+}
+
+fn new_cairo_context(
+    data: *mut c_uchar,
+    _data_size: usize,
+    width: i32,
+    height: i32,
+    line_size: i32,
+) -> Result<cairo::Context, cairo::Status> {
+    let surface = unsafe {
+        let surface = cairo_sys::cairo_image_surface_create_for_data(
+            data,
+            cairo_sys::FORMAT_A_RGB32,
+            width,
+            height,
+            line_size,
+        );
+
+        cairo::ImageSurface::from_raw_full(surface)?
+    };
+
+    let cr = cairo::Context::new(&surface);
+    cr.set_antialias(cairo::Antialias::Best);
+    Ok(cr)
+}
+
+const SAFE_AR_WIDTH: f64 = 11.0;
+const SAFE_AR_HEIGHT: f64 = 10.0;
+const SAFE_AR: f64 = SAFE_AR_WIDTH / SAFE_AR_HEIGHT;
+
+// Maps PlayResX and PlayResY to the frame the same way as the srf filter.
+fn scale(ctx: &Context, width: f64, height: f64, cr: &cairo::Context) {
+    if let ScaleType::None = ctx.scale_type {
+        return;
+    }
+
+    let (res_width, res_height) = ctx.play_res;
+    if let ScaleType::NonUniform = ctx.scale_type {
+        cr.scale(width / res_width, height / res_height);
+        return;
+    }
+
+    let (sx, sy) = if width / height >= SAFE_AR {
+        // scale to height.
+        let sy = height / res_height;
+        (sy, sy)
+    } else {
+        // scale to safe AR width.
+        let sx = width * SAFE_AR_HEIGHT / SAFE_AR_WIDTH / res_height;
+        (sx, sx)
+    };
+
+    cr.scale(sx, sy);
+
+    let (res_width, res_height) = cr.user_to_device(res_width, res_height);
+    let tx = if res_width < width {
+        // translate to center.
+        (width - res_width) / 2.
+    } else if res_width > width {
+        // translate left resulting in crop to
+        // max(ratio safe, pic aspect ratio).
+        -((res_width - (SAFE_AR_HEIGHT * res_height / SAFE_AR_WIDTH)) / 2.0)
+            .min((res_width - width) / 2.0)
+    } else {
+        0.
+    };
+
+    let ty = if res_height < height {
+        // translate to bottom.
+        height - res_height
+    } else {
+        0.
+    };
+
+    let (tx, ty) = cr.device_to_user(tx, ty);
+    cr.translate(tx, ty);
+}
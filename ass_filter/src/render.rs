@@ -0,0 +1,232 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::script::{Color, Event, Style};
+use crate::tags::Run;
+
+struct LaidOutRun<'a> {
+    run: &'a Run,
+    x: f64,
+    advance: f64,
+}
+
+struct Line<'a> {
+    runs: Vec<LaidOutRun<'a>>,
+    width: f64,
+    ascent: f64,
+    descent: f64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pass {
+    Shadow,
+    Outline,
+    Fill,
+}
+
+// Renders the active events in script coordinates, i.e. the transform of
+// `cr` maps `play_res` to the frame.
+pub(crate) fn render(events: &[&Event], play_res: (f64, f64), ts_millis: f64, cr: &cairo::Context) {
+    for event in events {
+        let alpha = fade_alpha(event, ts_millis);
+        if alpha <= 0. {
+            continue;
+        }
+
+        if alpha < 1. {
+            cr.push_group();
+            render_event(event, play_res, cr);
+            cr.pop_group_to_source();
+            cr.paint_with_alpha(alpha);
+        } else {
+            render_event(event, play_res, cr);
+        }
+    }
+}
+
+fn fade_alpha(event: &Event, ts_millis: f64) -> f64 {
+    let (fade_in, fade_out) = match event.fade {
+        Some(f) => f,
+        None => return 1.,
+    };
+
+    let t = ts_millis - event.time_in as f64;
+    let mut alpha: f64 = 1.;
+    if fade_in > 0 {
+        alpha = alpha.min(t / fade_in as f64);
+    }
+
+    if let Some(time_out) = event.time_out.filter(|_| fade_out > 0) {
+        alpha = alpha.min((time_out as f64 - ts_millis) / fade_out as f64);
+    }
+
+    alpha.clamp(0., 1.)
+}
+
+fn render_event(event: &Event, play_res: (f64, f64), cr: &cairo::Context) {
+    let mut lines: Vec<Line> = event.lines.iter().map(|l| layout_line(l, cr)).collect();
+    let width = lines.iter().map(|l| l.width).fold(0., f64::max);
+    let height: f64 = lines.iter().map(|l| l.ascent + l.descent).sum();
+
+    // Numpad alignment, column 0 is left and row 0 is bottom.
+    let column = ((event.alignment - 1) % 3) as f64;
+    let row = ((event.alignment - 1) / 3) as f64;
+    let (x, y) = event.pos.unwrap_or_else(|| {
+        let m = &event.margins;
+        let x = match column as u8 {
+            0 => m.left,
+            1 => (m.left + play_res.0 - m.right) / 2.,
+            _ => play_res.0 - m.right,
+        };
+
+        let y = match row as u8 {
+            0 => play_res.1 - m.vertical,
+            1 => play_res.1 / 2.,
+            _ => m.vertical,
+        };
+
+        (x, y)
+    });
+
+    let left = x - column * width / 2.;
+    let mut top = y - (2. - row) * height / 2.;
+    let mut baselines = Vec::with_capacity(lines.len());
+    for line in &mut lines {
+        let offset = left + column * (width - line.width) / 2.;
+        for r in &mut line.runs {
+            r.x += offset;
+        }
+
+        baselines.push(top + line.ascent);
+        top += line.ascent + line.descent;
+    }
+
+    for &pass in &[Pass::Shadow, Pass::Outline, Pass::Fill] {
+        for (line, &baseline) in lines.iter().zip(&baselines) {
+            for r in &line.runs {
+                paint_run(r, line, baseline, pass, cr);
+            }
+        }
+    }
+}
+
+fn layout_line<'a>(runs: &'a [Run], cr: &cairo::Context) -> Line<'a> {
+    let mut line = Line {
+        runs: Vec::with_capacity(runs.len()),
+        width: 0.,
+        ascent: 0.,
+        descent: 0.,
+    };
+
+    for run in runs {
+        set_font(cr, &run.style);
+        let font = cr.font_extents();
+        let advance = cr.text_extents(&run.text).x_advance;
+        line.runs.push(LaidOutRun {
+            run,
+            x: line.width,
+            advance,
+        });
+
+        line.width += advance;
+        line.ascent = line.ascent.max(font.ascent);
+        line.descent = line.descent.max(font.descent);
+    }
+
+    line
+}
+
+fn paint_run(r: &LaidOutRun, line: &Line, baseline: f64, pass: Pass, cr: &cairo::Context) {
+    let style = &r.run.style;
+    let offset = if pass == Pass::Shadow {
+        if style.shadow <= 0. {
+            return;
+        }
+
+        style.shadow
+    } else {
+        0.
+    };
+
+    let (x, baseline) = (r.x + offset, baseline + offset);
+    if style.opaque_box {
+        // The box replaces the outline and is padded by its size.
+        if pass != Pass::Fill {
+            let color = match pass {
+                Pass::Shadow => style.shadow_color,
+                _ => style.outline_color,
+            };
+
+            set_color(cr, color);
+            cr.rectangle(
+                x - style.outline,
+                baseline - line.ascent - style.outline,
+                r.advance + style.outline * 2.,
+                line.ascent + line.descent + style.outline * 2.,
+            );
+            cr.fill();
+            return;
+        }
+    } else if pass == Pass::Outline && style.outline <= 0. {
+        return;
+    }
+
+    set_font(cr, style);
+    cr.move_to(x, baseline);
+    cr.text_path(&r.run.text);
+    if style.underline {
+        cr.rectangle(
+            x,
+            baseline + style.font_size / 10.,
+            r.advance,
+            style.font_size / 15.,
+        );
+    }
+
+    match pass {
+        Pass::Shadow | Pass::Outline if !style.opaque_box => {
+            let color = if pass == Pass::Shadow {
+                style.shadow_color
+            } else {
+                style.outline_color
+            };
+
+            set_color(cr, color);
+            // The outline is drawn outside of the glyphs.
+            cr.set_line_width(style.outline * 2.);
+            cr.set_line_join(cairo::LineJoin::Round);
+            if style.outline > 0. {
+                cr.stroke_preserve();
+            }
+
+            cr.fill();
+        }
+
+        _ => {
+            set_color(cr, style.primary);
+            cr.fill();
+        }
+    }
+}
+
+fn set_font(cr: &cairo::Context, style: &Style) {
+    let slant = if style.italic {
+        cairo::FontSlant::Italic
+    } else {
+        cairo::FontSlant::Normal
+    };
+
+    let weight = if style.bold {
+        cairo::FontWeight::Bold
+    } else {
+        cairo::FontWeight::Normal
+    };
+
+    cr.select_font_face(&style.font_name, slant, weight);
+    cr.set_font_size(style.font_size);
+}
+
+fn set_color(cr: &cairo::Context, color: Color) {
+    cr.set_source_rgba(color.r, color.g, color.b, color.a);
+}
@@ -0,0 +1,436 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use crate::tags::{self, Run};
+
+const DEFAULT_STYLE_FORMAT: &str = "Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, \
+    OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, \
+    BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding";
+
+const DEFAULT_EVENT_FORMAT: &str =
+    "Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Color {
+    pub(crate) r: f64,
+    pub(crate) g: f64,
+    pub(crate) b: f64,
+    pub(crate) a: f64,
+}
+
+impl Color {
+    // Parses `&HAABBGGRR` or a decimal number, the alpha is inverted.
+    pub(crate) fn parse(s: &str) -> Option<Color> {
+        let s = s.trim().trim_end_matches('&');
+        let n = match s
+            .strip_prefix("&H")
+            .or_else(|| s.strip_prefix("&h"))
+            .or_else(|| s.strip_prefix('H'))
+        {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => s.parse::<i64>().ok()? as u32,
+        };
+
+        let [a, b, g, r] = n.to_be_bytes();
+        Some(Color {
+            r: r as f64 / 255.,
+            g: g as f64 / 255.,
+            b: b as f64 / 255.,
+            a: (255 - a) as f64 / 255.,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Style {
+    pub(crate) font_name: String,
+    pub(crate) font_size: f64,
+    pub(crate) primary: Color,
+    pub(crate) outline_color: Color,
+    pub(crate) shadow_color: Color,
+    pub(crate) bold: bool,
+    pub(crate) italic: bool,
+    pub(crate) underline: bool,
+    // Draws an opaque box in the outline color instead of an outline.
+    pub(crate) opaque_box: bool,
+    pub(crate) outline: f64,
+    pub(crate) shadow: f64,
+    // Numpad alignment, 1 is bottom left and 9 is top right.
+    pub(crate) alignment: u8,
+    pub(crate) margins: Margins,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        let color = |s| Color::parse(s).unwrap();
+        Style {
+            font_name: String::from("Arial"),
+            font_size: 18.,
+            primary: color("&H00FFFFFF"),
+            outline_color: color("&H00000000"),
+            shadow_color: color("&H00000000"),
+            bold: false,
+            italic: false,
+            underline: false,
+            opaque_box: false,
+            outline: 2.,
+            shadow: 2.,
+            alignment: 2,
+            margins: Margins {
+                left: 10.,
+                right: 10.,
+                vertical: 10.,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Margins {
+    pub(crate) left: f64,
+    pub(crate) right: f64,
+    pub(crate) vertical: f64,
+}
+
+pub(crate) struct Event {
+    pub(crate) time_in: u64,
+    pub(crate) time_out: Option<u64>,
+    // Drawing order, by layer and then by order in the script.
+    pub(crate) index: usize,
+    pub(crate) alignment: u8,
+    pub(crate) margins: Margins,
+    pub(crate) pos: Option<(f64, f64)>,
+    // Fade in and fade out in milliseconds.
+    pub(crate) fade: Option<(u64, u64)>,
+    pub(crate) lines: Vec<Vec<Run>>,
+}
+
+impl interval_tree::Interval for Event {
+    fn time_in(&self) -> u64 {
+        self.time_in
+    }
+
+    fn time_out(&self) -> Option<u64> {
+        self.time_out
+    }
+
+    fn index(&self) -> usize {
+        self.index
+    }
+}
+
+pub(crate) struct Script {
+    pub(crate) play_res: (f64, f64),
+    pub(crate) events: Vec<Event>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    Info,
+    Styles { v4_plus: bool },
+    Events,
+    Other,
+}
+
+pub(crate) fn parse_script(s: &str) -> anyhow::Result<Script> {
+    let mut section = Section::Other;
+    let mut play_res = (None, None);
+    let mut styles = HashMap::new();
+    let mut style_format = split_format(DEFAULT_STYLE_FORMAT);
+    let mut event_format = split_format(DEFAULT_EVENT_FORMAT);
+    let mut dialogues = Vec::new();
+    for (i, line) in s.trim_start_matches('\u{feff}').lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = match line {
+                "[Script Info]" => Section::Info,
+                "[V4+ Styles]" => Section::Styles { v4_plus: true },
+                "[V4 Styles]" => Section::Styles { v4_plus: false },
+                "[Events]" => Section::Events,
+                _ => Section::Other,
+            };
+
+            continue;
+        }
+
+        let (key, value) = match line.find(':') {
+            Some(colon) => (&line[..colon], line[colon + 1..].trim_start()),
+            None => continue,
+        };
+
+        let error = |e| anyhow::anyhow!("line {}: {}", i + 1, e);
+        match (section, key) {
+            (Section::Info, "PlayResX") => play_res.0 = Some(value.parse::<f64>()?),
+            (Section::Info, "PlayResY") => play_res.1 = Some(value.parse::<f64>()?),
+            (Section::Styles { .. }, "Format") => style_format = split_format(value),
+            (Section::Styles { v4_plus }, "Style") => {
+                let (name, style) = parse_style(value, &style_format, v4_plus).map_err(error)?;
+                styles.insert(name, style);
+            }
+
+            (Section::Events, "Format") => event_format = split_format(value),
+            (Section::Events, "Dialogue") => dialogues.push((i + 1, value, event_format.clone())),
+            _ => {}
+        }
+    }
+
+    // The defaults of VSFilter and libass.
+    let play_res = match play_res {
+        (Some(x), Some(y)) => (x, y),
+        (Some(x), None) if x == 1280. => (x, 1024.),
+        (Some(x), None) => (x, x * 3. / 4.),
+        (None, Some(y)) if y == 1024. => (1280., y),
+        (None, Some(y)) => (y * 4. / 3., y),
+        (None, None) => (384., 288.),
+    };
+
+    if play_res.0 <= 0. || play_res.1 <= 0. {
+        return Err(anyhow::anyhow!("invalid PlayResX or PlayResY"));
+    }
+
+    let mut events = Vec::with_capacity(dialogues.len());
+    for (line, value, format) in dialogues {
+        match parse_dialogue(value, &format, &styles) {
+            Ok(Some(e)) => events.push(e),
+            Ok(None) => {}
+            Err(e) => return Err(anyhow::anyhow!("line {}: {}", line, e)),
+        }
+    }
+
+    // Events are drawn by layer and then in script order.
+    events.sort_by_key(|(layer, _)| *layer);
+    let events = events
+        .into_iter()
+        .enumerate()
+        .map(|(index, (_, e))| Event { index, ..e })
+        .collect();
+
+    Ok(Script { play_res, events })
+}
+
+fn split_format(s: &str) -> Vec<String> {
+    s.split(',').map(|f| f.trim().to_lowercase()).collect()
+}
+
+// Splits a line by the format, the last field may contain commas.
+fn fields<'a>(value: &'a str, format: &[String]) -> HashMap<String, &'a str> {
+    format
+        .iter()
+        .cloned()
+        .zip(value.splitn(format.len(), ',').map(str::trim))
+        .collect()
+}
+
+fn parse_style(value: &str, format: &[String], v4_plus: bool) -> anyhow::Result<(String, Style)> {
+    let fields = fields(value, format);
+    let mut style = Style::default();
+    let number = |name: &str, default: f64| -> anyhow::Result<f64> {
+        match fields.get(name) {
+            Some(v) => v
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid {} {}", name, v)),
+            None => Ok(default),
+        }
+    };
+
+    let color = |name: &str, default: Color| -> anyhow::Result<Color> {
+        match fields.get(name) {
+            Some(v) => Color::parse(v).ok_or_else(|| anyhow::anyhow!("invalid {} {}", name, v)),
+            None => Ok(default),
+        }
+    };
+
+    // Booleans are -1 for true and 0 for false.
+    let flag = |name: &str| number(name, 0.).map(|n| n != 0.);
+
+    if let Some(name) = fields.get("fontname") {
+        style.font_name = String::from(*name);
+    }
+
+    style.font_size = number("fontsize", style.font_size)?;
+    style.primary = color("primarycolour", style.primary)?;
+    style.outline_color = color("outlinecolour", style.outline_color)?;
+    // The outline color of V4 styles.
+    style.outline_color = color("tertiarycolour", style.outline_color)?;
+    style.shadow_color = color("backcolour", style.shadow_color)?;
+    style.bold = flag("bold")?;
+    style.italic = flag("italic")?;
+    style.underline = flag("underline")?;
+    style.opaque_box = number("borderstyle", 1.)? == 3.;
+    style.outline = number("outline", style.outline)?;
+    style.shadow = number("shadow", style.shadow)?;
+    let alignment = number("alignment", 2.)? as u8;
+    style.alignment = if v4_plus {
+        alignment
+    } else {
+        tags::legacy_alignment(alignment)
+    };
+
+    if !(1..=9).contains(&style.alignment) {
+        return Err(anyhow::anyhow!("invalid alignment {}", alignment));
+    }
+
+    style.margins = Margins {
+        left: number("marginl", style.margins.left)?,
+        right: number("marginr", style.margins.right)?,
+        vertical: number("marginv", style.margins.vertical)?,
+    };
+
+    let name = fields.get("name").unwrap_or(&"Default");
+    Ok((String::from(name.trim_start_matches('*')), style))
+}
+
+fn parse_dialogue(
+    value: &str,
+    format: &[String],
+    styles: &HashMap<String, Style>,
+) -> anyhow::Result<Option<(i32, Event)>> {
+    let fields = fields(value, format);
+    let field = |name: &str| {
+        fields
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("missing {}", name))
+    };
+
+    let time_in = parse_time(field("start")?)?;
+    let time_out = parse_time(field("end")?)?;
+    if time_out <= time_in {
+        return Ok(None);
+    }
+
+    let layer = fields
+        .get("layer")
+        .map(|l| l.parse::<i32>())
+        .transpose()?
+        .unwrap_or(0);
+
+    let style_name = fields.get("style").copied().unwrap_or("Default");
+    let style = styles
+        .get(style_name.trim_start_matches('*'))
+        .or_else(|| styles.get("Default"))
+        .cloned()
+        .unwrap_or_default();
+
+    // Margins of zero use the margins of the style.
+    let margin = |name: &str, default: f64| -> anyhow::Result<f64> {
+        match fields.get(name).map(|m| m.parse::<f64>()).transpose()? {
+            Some(m) if m != 0. => Ok(m),
+            _ => Ok(default),
+        }
+    };
+
+    let margins = Margins {
+        left: margin("marginl", style.margins.left)?,
+        right: margin("marginr", style.margins.right)?,
+        vertical: margin("marginv", style.margins.vertical)?,
+    };
+
+    let text = tags::parse_text(field("text")?, &style, styles);
+    Ok(Some((
+        layer,
+        Event {
+            time_in,
+            time_out: Some(time_out),
+            index: 0,
+            alignment: text.alignment.unwrap_or(style.alignment),
+            margins,
+            pos: text.pos,
+            fade: text.fade,
+            lines: text.lines,
+        },
+    )))
+}
+
+// Parses `H:MM:SS.cc` into milliseconds.
+fn parse_time(s: &str) -> anyhow::Result<u64> {
+    let parts: Vec<&str> = s.split(':').collect();
+    let invalid = || anyhow::anyhow!("invalid time {}", s);
+    if parts.len() != 3 {
+        return Err(invalid());
+    }
+
+    let h: u64 = parts[0].parse().map_err(|_| invalid())?;
+    let m: u64 = parts[1].parse().map_err(|_| invalid())?;
+    let secs: f64 = parts[2].parse().map_err(|_| invalid())?;
+    if !secs.is_finite() || secs < 0. {
+        return Err(invalid());
+    }
+
+    Ok((h * 60 + m) * 60_000 + (secs * 1000.).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "\u{feff}[Script Info]
+; A comment
+ScriptType: v4.00+
+PlayResX: 1920
+PlayResY: 1080
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Default,Arial,60,&H00FFFFFF,&H000000FF,&H00000000,&H80000000,-1,0,0,0,100,100,0,0,1,3,1,2,40,40,50,1
+Style: Top,Arial,48,&H0000FFFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,3,2,0,8,10,10,20,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+Dialogue: 1,0:00:01.00,0:00:03.50,Default,,0,0,0,,Hello, {\\b0}world\\Nsecond line
+Dialogue: 0,0:00:02.00,0:00:04.00,Top,,0,0,80,,{\\pos(100,200)\\fad(200,300)}Top
+Comment: 0,0:00:02.00,0:00:04.00,Top,,0,0,0,,Not shown
+Dialogue: 0,0:00:05.00,0:00:05.00,Top,,0,0,0,,Empty
+";
+
+    #[test]
+    fn parse() {
+        let script = parse_script(SCRIPT).unwrap();
+        assert_eq!((1920., 1080.), script.play_res);
+        assert_eq!(2, script.events.len());
+
+        let top = &script.events[0];
+        assert_eq!((2000, Some(4000)), (top.time_in, top.time_out));
+        assert_eq!(0, top.index);
+        assert_eq!(8, top.alignment);
+        assert_eq!(80., top.margins.vertical);
+        assert_eq!(Some((100., 200.)), top.pos);
+        assert_eq!(Some((200, 300)), top.fade);
+        assert!(top.lines[0][0].style.opaque_box);
+
+        let hello = &script.events[1];
+        assert_eq!((1000, Some(3500)), (hello.time_in, hello.time_out));
+        assert_eq!(1, hello.index);
+        assert_eq!(40., hello.margins.left);
+        assert_eq!(2, hello.lines.len());
+        assert_eq!("Hello, ", hello.lines[0][0].text);
+        assert!(hello.lines[0][0].style.bold);
+        assert_eq!("world", hello.lines[0][1].text);
+        assert!(!hello.lines[0][1].style.bold);
+        assert_eq!(
+            0.5,
+            (hello.lines[0][0].style.shadow_color.a * 100.).round() / 100.
+        );
+
+        assert!(parse_script("[Events]\nDialogue: 0,1:00,0:00:01.00,,,0,0,0,,x").is_err());
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(
+            Some(Color {
+                r: 1.,
+                g: 0.,
+                b: 0.,
+                a: 1.
+            }),
+            Color::parse("&H000000FF")
+        );
+        assert_eq!(Some(0.), Color::parse("&HFF000000").map(|c| c.a));
+        assert_eq!(Some(1.), Color::parse("16777215").map(|c| c.r));
+        assert_eq!(None, Color::parse("&Hxyz"));
+    }
+}
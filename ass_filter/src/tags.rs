@@ -0,0 +1,320 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Override tags in the text of dialogue events, e.g. `{\pos(10,20)\b1}`.
+
+use std::collections::HashMap;
+
+use crate::script::{Color, Style};
+
+// Longer names first since tags are matched by prefix.
+const TAGS: &[&str] = &[
+    "alpha", "bord", "shad", "fade", "fad", "pos", "move", "clip", "blur", "fscx", "fscy", "fsp",
+    "frx", "fry", "frz", "fax", "fay", "org", "fr", "fe", "fn", "fs", "an", "be", "kf", "ko", "1c",
+    "2c", "3c", "4c", "1a", "2a", "3a", "4a", "a", "b", "c", "i", "k", "K", "p", "q", "r", "s",
+    "t", "u",
+];
+
+// A run of text with the same style.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Run {
+    pub(crate) text: String,
+    pub(crate) style: Style,
+}
+
+pub(crate) struct Text {
+    pub(crate) lines: Vec<Vec<Run>>,
+    pub(crate) alignment: Option<u8>,
+    pub(crate) pos: Option<(f64, f64)>,
+    pub(crate) fade: Option<(u64, u64)>,
+}
+
+// Converts the alignment of SSA, where 1-3 is bottom, 5-7 top and 9-11
+// middle, to numpad alignment.
+pub(crate) fn legacy_alignment(a: u8) -> u8 {
+    let h = a & 3;
+    match (h, a & 4 != 0, a & 8 != 0) {
+        (0, _, _) => 0,
+        (_, true, _) => h + 6,
+        (_, _, true) => h + 3,
+        _ => h,
+    }
+}
+
+// Parses the text of a dialogue event, `\N` is a line break and `\h` a
+// non-breaking space. Unsupported tags are ignored.
+pub(crate) fn parse_text(s: &str, base: &Style, styles: &HashMap<String, Style>) -> Text {
+    let mut text = Text {
+        lines: vec![vec![]],
+        alignment: None,
+        pos: None,
+        fade: None,
+    };
+
+    let mut style = base.clone();
+    let mut drawing = false;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let (plain, block) = match rest.find('{') {
+            Some(i) => match rest[i..].find('}') {
+                Some(j) => (&rest[..i], Some(&rest[i + 1..i + j])),
+                None => (rest, None),
+            },
+            None => (rest, None),
+        };
+
+        if !drawing {
+            push_text(&mut text.lines, plain, &style);
+        }
+
+        let block = match block {
+            Some(b) => b,
+            None => break,
+        };
+
+        rest = &rest[plain.len() + block.len() + 2..];
+        for tag in split_tags(block) {
+            apply_tag(tag, &mut text, &mut style, &mut drawing, base, styles);
+        }
+    }
+
+    // Empty lines keep a run to get a height.
+    for line in &mut text.lines {
+        if line.is_empty() {
+            line.push(Run {
+                text: String::new(),
+                style: style.clone(),
+            });
+        }
+    }
+
+    text
+}
+
+fn push_text(lines: &mut Vec<Vec<Run>>, s: &str, style: &Style) {
+    for (i, part) in s.split("\\N").enumerate() {
+        if i > 0 {
+            lines.push(vec![]);
+        }
+
+        let part = part.replace("\\n", " ").replace("\\h", "\u{a0}");
+        if !part.is_empty() {
+            let line = lines.last_mut().unwrap();
+            match line.last_mut() {
+                Some(run) if &run.style == style => run.text.push_str(&part),
+                _ => line.push(Run {
+                    text: part,
+                    style: style.clone(),
+                }),
+            }
+        }
+    }
+}
+
+// Splits an override block in tags without the leading backslash, the
+// arguments in parentheses may contain backslashes.
+fn split_tags(block: &str) -> Vec<&str> {
+    let mut tags = vec![];
+    let mut depth = 0;
+    let mut start = None;
+    for (i, c) in block.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '\\' if depth <= 0 => {
+                if let Some(s) = start {
+                    tags.push(block[s..i].trim());
+                }
+
+                start = Some(i + 1);
+            }
+
+            _ => {}
+        }
+    }
+
+    if let Some(s) = start {
+        tags.push(block[s..].trim());
+    }
+
+    tags
+}
+
+fn apply_tag(
+    tag: &str,
+    text: &mut Text,
+    style: &mut Style,
+    drawing: &mut bool,
+    base: &Style,
+    styles: &HashMap<String, Style>,
+) {
+    let name = match TAGS.iter().find(|t| tag.starts_with(*t)) {
+        Some(name) => *name,
+        None => return,
+    };
+
+    let arg = tag[name.len()..].trim();
+    let number = || arg.parse::<f64>().ok();
+    let args = || -> Vec<f64> {
+        arg.trim_start_matches('(')
+            .trim_end_matches(')')
+            .split(',')
+            .filter_map(|a| a.trim().parse().ok())
+            .collect()
+    };
+
+    // An empty argument resets the property to the style's.
+    let flag = |current: bool, of_style: bool| match number() {
+        Some(n) => n == 1. || n >= 700.,
+        None if arg.is_empty() => of_style,
+        None => current,
+    };
+
+    match name {
+        "b" => style.bold = flag(style.bold, base.bold),
+        "i" => style.italic = flag(style.italic, base.italic),
+        "u" => style.underline = flag(style.underline, base.underline),
+        "bord" => style.outline = number().unwrap_or(base.outline).max(0.),
+        "shad" => style.shadow = number().unwrap_or(base.shadow).max(0.),
+        "fs" => style.font_size = number().filter(|&s| s > 0.).unwrap_or(base.font_size),
+        "fn" => {
+            style.font_name = if arg.is_empty() {
+                base.font_name.clone()
+            } else {
+                String::from(arg)
+            }
+        }
+
+        "c" | "1c" => style.primary = color(arg, style.primary, base.primary),
+        "3c" => style.outline_color = color(arg, style.outline_color, base.outline_color),
+        "4c" => style.shadow_color = color(arg, style.shadow_color, base.shadow_color),
+        "alpha" => {
+            style.primary.a = alpha(arg, base.primary.a);
+            style.outline_color.a = alpha(arg, base.outline_color.a);
+            style.shadow_color.a = alpha(arg, base.shadow_color.a);
+        }
+
+        "1a" => style.primary.a = alpha(arg, base.primary.a),
+        "3a" => style.outline_color.a = alpha(arg, base.outline_color.a),
+        "4a" => style.shadow_color.a = alpha(arg, base.shadow_color.a),
+        "r" => {
+            *style = styles
+                .get(arg)
+                .filter(|_| !arg.is_empty())
+                .unwrap_or(base)
+                .clone()
+        }
+
+        // The first alignment and position of the event are used.
+        "an" => match number() {
+            Some(a) if text.alignment.is_none() && (1. ..=9.).contains(&a) => {
+                text.alignment = Some(a as u8)
+            }
+            _ => {}
+        },
+
+        "a" => match number() {
+            Some(a) if text.alignment.is_none() && (1. ..=11.).contains(&a) => {
+                text.alignment = Some(legacy_alignment(a as u8)).filter(|&a| a != 0)
+            }
+            _ => {}
+        },
+
+        "pos" => {
+            if let [x, y] = args()[..] {
+                text.pos.get_or_insert((x, y));
+            }
+        }
+
+        "fad" => {
+            if let [fade_in, fade_out] = args()[..] {
+                text.fade = Some((fade_in.max(0.) as u64, fade_out.max(0.) as u64));
+            }
+        }
+
+        "p" => *drawing = number().map(|p| p > 0.).unwrap_or(false),
+        // Karaoke, transforms, rotation and the like aren't supported.
+        _ => {}
+    }
+}
+
+// Parses `&HBBGGRR&`, keeping the alpha of the current color.
+fn color(arg: &str, current: Color, of_style: Color) -> Color {
+    if arg.is_empty() {
+        return of_style;
+    }
+
+    match Color::parse(arg) {
+        Some(c) => Color { a: current.a, ..c },
+        None => current,
+    }
+}
+
+// Parses `&HAA&`, where 00 is opaque.
+fn alpha(arg: &str, of_style: f64) -> f64 {
+    let hex = arg
+        .trim_start_matches("&H")
+        .trim_start_matches("&h")
+        .trim_end_matches('&');
+    match u8::from_str_radix(hex, 16) {
+        Ok(a) => (255 - a) as f64 / 255.,
+        Err(_) => of_style,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text() {
+        let base = Style::default();
+        let mut styles = HashMap::new();
+        styles.insert(
+            String::from("Alt"),
+            Style {
+                italic: true,
+                ..Style::default()
+            },
+        );
+
+        let text = parse_text(
+            r"{\an8\pos(10.5,20)\an1\pos(1,2)}A{\b1\c&H0000FF&\1a&H80&}b\Nc{\rAlt}d{\r}e\N{\p1}m 0 0 l 1 1{\p0}\h",
+            &base,
+            &styles,
+        );
+
+        assert_eq!(Some(8), text.alignment);
+        assert_eq!(Some((10.5, 20.)), text.pos);
+        assert_eq!(3, text.lines.len());
+
+        let line: Vec<&str> = text.lines[0].iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(vec!["A", "b"], line);
+        let b = &text.lines[0][1].style;
+        assert!(b.bold);
+        assert_eq!((1., 0., 0.), (b.primary.r, b.primary.g, b.primary.b));
+        assert!((b.primary.a - 127. / 255.).abs() < 1e-9);
+
+        let line: Vec<&str> = text.lines[1].iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(vec!["c", "d", "e"], line);
+        assert!(text.lines[1][0].style.bold);
+        assert!(text.lines[1][1].style.italic);
+        assert_eq!(base, text.lines[1][2].style);
+        assert_eq!("\u{a0}", text.lines[2][0].text);
+
+        let text = parse_text(r"x\N\N{\fad(100,200)}", &base, &styles);
+        assert_eq!(3, text.lines.len());
+        assert_eq!("", text.lines[1][0].text);
+        assert_eq!(Some((100, 200)), text.fade);
+
+        assert_eq!(vec!["t(\\b1)", "b0"], split_tags(r"\t(\b1)\b0"));
+        assert_eq!(
+            vec![1, 2, 3, 7, 8, 9, 4, 5, 6],
+            [1, 2, 3, 5, 6, 7, 9, 10, 11]
+                .iter()
+                .map(|&a| legacy_alignment(a))
+                .collect::<Vec<u8>>()
+        );
+    }
+}
@@ -0,0 +1,233 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// An interval tree of timed items, shared by the filters that search
+// transitions, subtitles or bitmaps by frame timestamp.
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+// An item shown from time-in until time-out, in milliseconds. Without a
+// time-out it's shown until the end.
+pub trait Interval {
+    fn time_in(&self) -> u64;
+    fn time_out(&self) -> Option<u64>;
+    // The order of items shown at the same time, usually the position in
+    // the source.
+    fn index(&self) -> usize;
+}
+
+// Tree implementation inspired by https://github.com/main--/rust-intervaltree
+
+pub struct IntervalTree<T> {
+    nodes: Vec<TreeNode<T>>,
+}
+
+struct TreeNode<T> {
+    item: T,
+    max: Option<u64>,
+}
+
+impl<T: Interval> IntervalTree<T> {
+    pub fn new(items: Vec<T>) -> IntervalTree<T> {
+        let mut nodes: Vec<TreeNode<T>> = items
+            .into_iter()
+            .map(|item| {
+                let max = item.time_out();
+                TreeNode { item, max }
+            })
+            .collect();
+
+        nodes.sort_by(|a, b| cmp_items(&a.item, &b.item));
+        if !nodes.is_empty() {
+            update_max(&mut nodes);
+        }
+
+        IntervalTree { nodes }
+    }
+
+    // Inserts items, e.g. as they arrive in live mode. They usually arrive
+    // in order, so only updating the max values is linear.
+    pub fn extend<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        let mut inserted = false;
+        for item in items {
+            let idx = self
+                .nodes
+                .binary_search_by(|n| match cmp_items(&n.item, &item) {
+                    Ordering::Greater => Ordering::Greater,
+                    _ => Ordering::Less,
+                })
+                .unwrap_or_else(|i| i);
+
+            let max = item.time_out();
+            self.nodes.insert(idx, TreeNode { item, max });
+            inserted = true;
+        }
+
+        // Called on every frame in live mode, mostly without items.
+        if !inserted {
+            return;
+        }
+
+        for node in &mut self.nodes {
+            node.max = node.item.time_out();
+        }
+
+        update_max(&mut self.nodes);
+    }
+
+    // Returns the items shown at the timestamp, in index order.
+    pub fn search(&self, ts_millis: f64) -> Vec<&T> {
+        let mut result = vec![];
+        let mut stack = VecDeque::new();
+        if !self.nodes.is_empty() {
+            stack.push_back((0, self.nodes.len()));
+        }
+
+        while let Some((s, l)) = stack.pop_back() {
+            let idx = s + l / 2;
+            let node = &self.nodes[idx];
+            if node.max.map(|m| ts_millis < m as f64).unwrap_or(true) {
+                let ls = idx - s;
+                if ls > 0 {
+                    stack.push_back((s, ls));
+                }
+
+                if ts_millis >= node.item.time_in() as f64 {
+                    let rs = l + s - idx - 1;
+                    if rs > 0 {
+                        stack.push_back((idx + 1, rs));
+                    }
+
+                    if node
+                        .item
+                        .time_out()
+                        .map(|o| ts_millis < o as f64)
+                        .unwrap_or(true)
+                    {
+                        result.push(&node.item);
+                    }
+                }
+            }
+        }
+
+        result.sort_by_key(|t| t.index());
+        result
+    }
+
+    // Returns the items starting after the given timestamp, in order.
+    pub fn upcoming(&self, ts_millis: f64) -> impl Iterator<Item = &T> {
+        let start = self
+            .nodes
+            .binary_search_by(|n| {
+                if n.item.time_in() as f64 <= ts_millis {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|i| i);
+
+        self.nodes[start..].iter().map(|n| &n.item)
+    }
+}
+
+fn update_max<T>(nodes: &mut [TreeNode<T>]) -> Option<u64> {
+    let mid = nodes.len() / 2;
+    if nodes.len() > 1 {
+        let (left, rest) = nodes.split_at_mut(mid);
+        if !left.is_empty() {
+            let left_max = update_max(left);
+            rest[0].max = max(rest[0].max, left_max);
+        }
+
+        let (rest, right) = nodes.split_at_mut(mid + 1);
+        if !right.is_empty() {
+            let right_max = update_max(right);
+            rest[mid].max = max(rest[mid].max, right_max);
+        }
+    }
+
+    nodes[mid].max
+}
+
+fn cmp_items<T: Interval>(a: &T, b: &T) -> Ordering {
+    let ordering = a.time_in().cmp(&b.time_in());
+    if ordering == Ordering::Equal {
+        match (a.time_out(), b.time_out()) {
+            (None, None) => Ordering::Equal,
+            (None, _) => Ordering::Greater,
+            (_, None) => Ordering::Less,
+            (Some(a), Some(b)) => a.cmp(&b),
+        }
+    } else {
+        ordering
+    }
+}
+
+fn max(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (None, _) => None,
+        (_, None) => None,
+        (Some(a), Some(b)) => Some(a.max(b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Item(u64, Option<u64>, usize);
+
+    impl Interval for Item {
+        fn time_in(&self) -> u64 {
+            self.0
+        }
+
+        fn time_out(&self) -> Option<u64> {
+            self.1
+        }
+
+        fn index(&self) -> usize {
+            self.2
+        }
+    }
+
+    fn indexes(tree: &IntervalTree<Item>, ts_millis: f64) -> Vec<usize> {
+        tree.search(ts_millis).iter().map(|i| i.2).collect()
+    }
+
+    #[test]
+    fn search() {
+        let tree = IntervalTree::new(vec![
+            Item(4, Some(8), 0),
+            Item(2, Some(10), 1),
+            Item(10, None, 2),
+            Item(10, Some(12), 3),
+        ]);
+
+        assert!(tree.search(0.0).is_empty());
+        assert_eq!(vec![1], indexes(&tree, 3.0));
+        assert_eq!(vec![0, 1], indexes(&tree, 5.0));
+        assert_eq!(vec![2, 3], indexes(&tree, 10.0));
+        assert_eq!(vec![2], indexes(&tree, 1000.0));
+        assert!(IntervalTree::<Item>::new(vec![]).search(0.0).is_empty());
+    }
+
+    #[test]
+    fn extend() {
+        let mut tree = IntervalTree::new(Vec::new());
+        tree.extend(vec![Item(0, Some(100), 0), Item(50, Some(60), 1)]);
+        tree.extend(vec![Item(20, None, 2)]);
+        tree.extend(vec![Item(90, Some(95), 3)]);
+
+        assert_eq!(vec![0], indexes(&tree, 10.0));
+        assert_eq!(vec![0, 1, 2], indexes(&tree, 55.0));
+        assert_eq!(vec![0, 2, 3], indexes(&tree, 90.0));
+        assert_eq!(vec![2], indexes(&tree, 1000.0));
+
+        let upcoming: Vec<u64> = tree.upcoming(30.0).map(|i| i.0).collect();
+        assert_eq!(vec![50, 90], upcoming);
+    }
+}
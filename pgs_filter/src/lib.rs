@@ -0,0 +1,206 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ffi::CStr;
+use std::fs;
+use std::ptr;
+
+use interval_tree::IntervalTree;
+use libc::{c_char, c_double, c_int, c_uchar, c_uint, c_void};
+
+mod rle;
+
+mod sup;
+use sup::DisplaySet;
+
+struct Config<'a> {
+    sup: &'a str,
+}
+
+struct Context {
+    tree: IntervalTree<DisplaySet>,
+    // The bitmaps of each display set by index.
+    surfaces: Vec<Vec<cairo::ImageSurface>>,
+}
+
+/// # Safety
+///
+/// `config` must be null or a NUL-terminated string and `user_data` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void) -> c_int {
+    unsafe {
+        *user_data = ptr::null_mut();
+    }
+
+    if config.is_null() {
+        eprintln!("got null config");
+        return 1;
+    }
+
+    let config = match parse_config(config) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("invalid config: {}", e);
+            return 1;
+        }
+    };
+
+    let mut display_sets = match read_sup(config.sup) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("{}: {}", config.sup, e);
+            return 1;
+        }
+    };
+
+    let surfaces = match create_surfaces(&mut display_sets) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {}", config.sup, e);
+            return 1;
+        }
+    };
+
+    let ctx = Context {
+        tree: IntervalTree::new(display_sets),
+        surfaces,
+    };
+
+    unsafe {
+        *user_data = Box::into_raw(Box::new(ctx)) as *mut c_void;
+    }
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn filter_frame(
+    data: *mut c_uchar,
+    data_size: c_uint,
+    width: c_int,
+    height: c_int,
+    line_size: c_int,
+    ts_millis: c_double,
+    user_data: *mut c_void,
+) -> c_int {
+    if data.is_null() || width <= 0 || height <= 0 {
+        return 0;
+    }
+
+    let ctx = if user_data.is_null() {
+        eprintln!("no user data");
+        return 1;
+    } else {
+        unsafe { &*(user_data as *const Context) }
+    };
+
+    let display_sets = ctx.tree.search(ts_millis);
+    if display_sets.is_empty() {
+        return 0;
+    }
+
+    let cr = match new_cairo_context(data, data_size as usize, width, height, line_size) {
+        Ok(cr) => cr,
+        Err(status) => {
+            eprintln!("could not create cairo context: {:?}", status);
+            return 1;
+        }
+    };
+
+    for d in display_sets {
+        cr.save();
+        // Positions are relative to the video size of the display set.
+        let (video_width, video_height) = (d.video_size.0 as f64, d.video_size.1 as f64);
+        if video_width > 0. && video_height > 0. {
+            cr.scale(width as f64 / video_width, height as f64 / video_height);
+        }
+
+        for (bitmap, surface) in d.bitmaps.iter().zip(&ctx.surfaces[d.index]) {
+            cr.set_source_surface(surface, bitmap.x as f64, bitmap.y as f64);
+            cr.paint();
+        }
+
+        cr.restore();
+    }
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn filter_uninit(user_data: *mut c_void) {
+    if !user_data.is_null() {
+        unsafe {
+            drop(Box::from_raw(user_data as *mut Context));
+        }
+    }
+}
+
+fn parse_config<'a>(config: *const c_char) -> anyhow::Result<Config<'a>> {
+    let cstr = unsafe { CStr::from_ptr(config) };
+    let s = cstr.to_str()?;
+    let re = regex::Regex::new(r"^sup=(.+)$").unwrap();
+    if let Some(cap) = re.captures(s) {
+        Ok(Config {
+            sup: cap.get(1).unwrap().as_str(),
+        })
+    } else {
+        Err(anyhow::anyhow!(s))
+    }
+}
+
+fn read_sup(sup: &str) -> anyhow::Result<Vec<DisplaySet>> {
+    let data = fs::read(sup)?;
+    sup::parse_sup(&data)
+}
+
+// Moves the pixel data of the bitmaps to cairo surfaces.
+fn create_surfaces(
+    display_sets: &mut [DisplaySet],
+) -> anyhow::Result<Vec<Vec<cairo::ImageSurface>>> {
+    let mut surfaces = Vec::with_capacity(display_sets.len());
+    for d in display_sets {
+        let mut s = Vec::with_capacity(d.bitmaps.len());
+        for bitmap in &mut d.bitmaps {
+            let surface = cairo::ImageSurface::create_for_data(
+                std::mem::take(&mut bitmap.data),
+                cairo::Format::ARgb32,
+                bitmap.width,
+                bitmap.height,
+                bitmap.width * 4,
+            )
+            .map_err(|s| anyhow::anyhow!("could not create surface: {:?}", s))?;
+
+            s.push(surface);
+        }
+
+        surfaces.push(s);
+    }
+
+    Ok(surfaces)
+}
+
+fn new_cairo_context(
+    data: *mut c_uchar,
+    _data_size: usize,
+    width: i32,
+    height: i32,
+    line_size: i32,
+) -> Result<cairo::Context, cairo::Status> {
+    let surface = unsafe {
+        let surface = cairo_sys::cairo_image_surface_create_for_data(
+            data,
+            cairo_sys::FORMAT_A_RGB32,
+            width,
+            height,
+            line_size,
+        );
+
+        cairo::ImageSurface::from_raw_full(surface)?
+    };
+
+    let cr = cairo::Context::new(&surface);
+    cr.set_antialias(cairo::Antialias::Best);
+    Ok(cr)
+}
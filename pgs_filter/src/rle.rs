@@ -0,0 +1,70 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Decodes the run-length encoded palette indexes of an object, line by
+// line where every line ends with `00 00`.
+pub(crate) fn decode(data: &[u8], width: usize, height: usize) -> anyhow::Result<Vec<u8>> {
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut line = 0;
+    let mut bytes = data.iter().copied();
+    let mut next = || {
+        bytes
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("truncated RLE data"))
+    };
+
+    while line < height {
+        let b = next()?;
+        let (len, color) = if b != 0 {
+            (1, b)
+        } else {
+            let b = next()?;
+            match b >> 6 {
+                _ if b == 0 => {
+                    // End of line, short lines are padded.
+                    line += 1;
+                    pixels.resize(line * width, 0);
+                    continue;
+                }
+
+                0 => ((b & 0x3f) as usize, 0),
+                1 => ((((b & 0x3f) as usize) << 8) | next()? as usize, 0),
+                2 => ((b & 0x3f) as usize, next()?),
+                _ => ((((b & 0x3f) as usize) << 8) | next()? as usize, next()?),
+            }
+        };
+
+        // Runs past the end of the line are cut.
+        let end = (line + 1) * width;
+        let len = len.min(end - pixels.len().min(end));
+        pixels.resize(pixels.len() + len, color);
+    }
+
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs() {
+        // 1, 2 x 0, 3 x 5, end of line.
+        let mut data = vec![0x01, 0x00, 0x02, 0x00, 0x83, 0x05, 0x00, 0x00];
+        // 260 x 0 cut to 6, end of line.
+        data.extend_from_slice(&[0x00, 0x41, 0x04, 0x00, 0x00]);
+        // 260 x 7 cut to 6, end of line.
+        data.extend_from_slice(&[0x00, 0xc1, 0x04, 0x07, 0x00, 0x00]);
+
+        let pixels = decode(&data, 6, 3).unwrap();
+        assert_eq!(
+            vec![1, 0, 0, 5, 5, 5, 0, 0, 0, 0, 0, 0, 7, 7, 7, 7, 7, 7],
+            pixels
+        );
+
+        // A short line is padded.
+        assert_eq!(vec![4, 0, 0], decode(&[0x04, 0x00, 0x00], 3, 1).unwrap());
+        assert!(decode(&[0x00, 0x83], 6, 1).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use crate::rle;
+
+const PALETTE: u8 = 0x14;
+const OBJECT: u8 = 0x15;
+const PRESENTATION_COMPOSITION: u8 = 0x16;
+const WINDOW: u8 = 0x17;
+const END: u8 = 0x80;
+
+const EPOCH_START: u8 = 0x80;
+
+// A decoded bitmap in premultiplied ARGB32, clipped to its crop rectangle
+// and window.
+pub(crate) struct Bitmap {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) width: i32,
+    pub(crate) height: i32,
+    pub(crate) data: Vec<u8>,
+}
+
+pub(crate) struct DisplaySet {
+    pub(crate) time_in: u64,
+    pub(crate) time_out: Option<u64>,
+    pub(crate) index: usize,
+    // The video size the positions are relative to.
+    pub(crate) video_size: (u16, u16),
+    pub(crate) bitmaps: Vec<Bitmap>,
+}
+
+impl interval_tree::Interval for DisplaySet {
+    fn time_in(&self) -> u64 {
+        self.time_in
+    }
+
+    fn time_out(&self) -> Option<u64> {
+        self.time_out
+    }
+
+    fn index(&self) -> usize {
+        self.index
+    }
+}
+
+struct CompositionObject {
+    object_id: u16,
+    window_id: u8,
+    x: u16,
+    y: u16,
+    crop: Option<(u16, u16, u16, u16)>,
+}
+
+struct Composition {
+    time: u64,
+    video_size: (u16, u16),
+    palette_id: u8,
+    objects: Vec<CompositionObject>,
+}
+
+struct Object {
+    width: u16,
+    height: u16,
+    rle: Vec<u8>,
+    // Palette indexes once the last fragment has been read.
+    pixels: Option<Vec<u8>>,
+}
+
+// Y, Cr, Cb and alpha.
+type Palette = [[u8; 4]; 256];
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(anyhow::anyhow!(
+                "offset {}: unexpected end of data",
+                self.pos
+            ));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+// Parses the segments of a `.sup` file into display sets, each shown until
+// the next one starts.
+pub(crate) fn parse_sup(data: &[u8]) -> anyhow::Result<Vec<DisplaySet>> {
+    let mut reader = Reader { data, pos: 0 };
+    let mut composition = None;
+    let mut windows = HashMap::new();
+    let mut palettes = HashMap::new();
+    let mut objects = HashMap::new();
+    let mut display_sets: Vec<DisplaySet> = Vec::new();
+    while !reader.is_empty() {
+        let offset = reader.pos;
+        if reader.bytes(2)? != b"PG" {
+            return Err(anyhow::anyhow!("offset {}: invalid segment", offset));
+        }
+
+        // Presentation time stamps are in 90 kHz.
+        let pts = reader.u32()?;
+        let _dts = reader.u32()?;
+        let kind = reader.u8()?;
+        let size = reader.u16()? as usize;
+        let mut segment = Reader {
+            data: reader.bytes(size)?,
+            pos: 0,
+        };
+
+        let error = |e: anyhow::Error| anyhow::anyhow!("segment at offset {}: {}", offset, e);
+        match kind {
+            PRESENTATION_COMPOSITION => {
+                let c = parse_composition(&mut segment, pts).map_err(error)?;
+                if c.0 == EPOCH_START {
+                    windows.clear();
+                    palettes.clear();
+                    objects.clear();
+                }
+
+                composition = Some(c.1);
+            }
+
+            WINDOW => {
+                for _ in 0..segment.u8().map_err(error)? {
+                    let w = (|| -> anyhow::Result<(u8, [u16; 4])> {
+                        let id = segment.u8()?;
+                        Ok((
+                            id,
+                            [
+                                segment.u16()?,
+                                segment.u16()?,
+                                segment.u16()?,
+                                segment.u16()?,
+                            ],
+                        ))
+                    })()
+                    .map_err(error)?;
+
+                    windows.insert(w.0, w.1);
+                }
+            }
+
+            PALETTE => {
+                let id = segment.u8().map_err(error)?;
+                let _version = segment.u8().map_err(error)?;
+                let palette = palettes.entry(id).or_insert([[0; 4]; 256]);
+                while !segment.is_empty() {
+                    let entry = segment.bytes(5).map_err(error)?;
+                    palette[entry[0] as usize] = [entry[1], entry[2], entry[3], entry[4]];
+                }
+            }
+
+            OBJECT => parse_object(&mut segment, &mut objects).map_err(error)?,
+            END => {
+                if let Some(c) = composition.take() {
+                    if let Some(last) = display_sets.last_mut() {
+                        last.time_out = Some(c.time);
+                    }
+
+                    display_sets.push(compose(&c, &windows, &palettes, &objects));
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    // Display sets without any bitmaps only end the previous one.
+    display_sets.retain(|d| !d.bitmaps.is_empty());
+    for (index, d) in display_sets.iter_mut().enumerate() {
+        d.index = index;
+    }
+
+    Ok(display_sets)
+}
+
+// Returns the composition state and the composition.
+fn parse_composition(segment: &mut Reader, pts: u32) -> anyhow::Result<(u8, Composition)> {
+    let video_size = (segment.u16()?, segment.u16()?);
+    let _frame_rate = segment.u8()?;
+    let _number = segment.u16()?;
+    let state = segment.u8()?;
+    let _palette_update = segment.u8()?;
+    let palette_id = segment.u8()?;
+    let count = segment.u8()?;
+    let mut objects = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let object_id = segment.u16()?;
+        let window_id = segment.u8()?;
+        let cropped = segment.u8()? & 0x40 != 0;
+        let (x, y) = (segment.u16()?, segment.u16()?);
+        let crop = if cropped {
+            Some((
+                segment.u16()?,
+                segment.u16()?,
+                segment.u16()?,
+                segment.u16()?,
+            ))
+        } else {
+            None
+        };
+
+        objects.push(CompositionObject {
+            object_id,
+            window_id,
+            x,
+            y,
+            crop,
+        });
+    }
+
+    Ok((
+        state & 0xc0,
+        Composition {
+            time: pts as u64 / 90,
+            video_size,
+            palette_id,
+            objects,
+        },
+    ))
+}
+
+// Objects larger than a segment are split in fragments.
+fn parse_object(segment: &mut Reader, objects: &mut HashMap<u16, Object>) -> anyhow::Result<()> {
+    let id = segment.u16()?;
+    let _version = segment.u8()?;
+    let sequence = segment.u8()?;
+    if sequence & 0x80 != 0 {
+        let _length = segment.bytes(3)?;
+        let (width, height) = (segment.u16()?, segment.u16()?);
+        objects.insert(
+            id,
+            Object {
+                width,
+                height,
+                rle: Vec::new(),
+                pixels: None,
+            },
+        );
+    }
+
+    let object = objects
+        .get_mut(&id)
+        .ok_or_else(|| anyhow::anyhow!("object {} without a first fragment", id))?;
+
+    object
+        .rle
+        .extend_from_slice(segment.bytes(segment.data.len() - segment.pos)?);
+    if sequence & 0x40 != 0 {
+        let pixels = rle::decode(&object.rle, object.width as usize, object.height as usize)?;
+        object.pixels = Some(pixels);
+        object.rle = Vec::new();
+    }
+
+    Ok(())
+}
+
+// Objects or palettes that haven't been defined are skipped.
+fn compose(
+    c: &Composition,
+    windows: &HashMap<u8, [u16; 4]>,
+    palettes: &HashMap<u8, Palette>,
+    objects: &HashMap<u16, Object>,
+) -> DisplaySet {
+    let mut bitmaps = Vec::new();
+    let palette = palettes.get(&c.palette_id);
+    for co in &c.objects {
+        let (object, pixels, palette) = match (objects.get(&co.object_id), palette) {
+            (Some(o), Some(p)) => match &o.pixels {
+                Some(pixels) => (o, pixels, p),
+                None => continue,
+            },
+
+            _ => continue,
+        };
+
+        let (w, h) = (object.width as i32, object.height as i32);
+        let (cx, cy, cw, ch) = match co.crop {
+            Some((cx, cy, cw, ch)) => (cx as i32, cy as i32, cw as i32, ch as i32),
+            None => (0, 0, w, h),
+        };
+
+        // The visible rectangle in video coordinates.
+        let (x, y) = (co.x as i32, co.y as i32);
+        let (origin_x, origin_y) = (x - cx, y - cy);
+        let mut rect = [
+            x.max(origin_x),
+            y.max(origin_y),
+            (x + cw).min(origin_x + w),
+            (y + ch).min(origin_y + h),
+        ];
+
+        if let Some(&[wx, wy, ww, wh]) = windows.get(&co.window_id) {
+            let (wx, wy) = (wx as i32, wy as i32);
+            rect = [
+                rect[0].max(wx),
+                rect[1].max(wy),
+                rect[2].min(wx + ww as i32),
+                rect[3].min(wy + wh as i32),
+            ];
+        }
+
+        let (width, height) = (rect[2] - rect[0], rect[3] - rect[1]);
+        if width <= 0 || height <= 0 {
+            continue;
+        }
+
+        let colors: Vec<u32> = palette.iter().map(|&e| argb(e, c.video_size.1)).collect();
+
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for row in rect[1]..rect[3] {
+            let start = ((row - origin_y) * w + rect[0] - origin_x) as usize;
+            for &p in &pixels[start..start + width as usize] {
+                data.extend_from_slice(&colors[p as usize].to_ne_bytes());
+            }
+        }
+
+        bitmaps.push(Bitmap {
+            x: rect[0],
+            y: rect[1],
+            width,
+            height,
+            data,
+        });
+    }
+
+    DisplaySet {
+        time_in: c.time,
+        time_out: None,
+        index: 0,
+        video_size: c.video_size,
+        bitmaps,
+    }
+}
+
+// Converts limited range YCbCr to premultiplied ARGB, using BT.709 for HD
+// video and BT.601 otherwise.
+fn argb([y, cr, cb, a]: [u8; 4], video_height: u16) -> u32 {
+    let (y, cr, cb) = (1.164 * (y as f64 - 16.), cr as f64 - 128., cb as f64 - 128.);
+
+    let (r, g, b) = if video_height > 576 {
+        (y + 1.793 * cr, y - 0.213 * cb - 0.533 * cr, y + 2.112 * cb)
+    } else {
+        (y + 1.596 * cr, y - 0.392 * cb - 0.813 * cr, y + 2.017 * cb)
+    };
+
+    let alpha = a as f64 / 255.;
+    let c = |v: f64| (v.clamp(0., 255.) * alpha).round() as u32;
+    (a as u32) << 24 | c(r) << 16 | c(g) << 8 | c(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(pts: u32, kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut s = b"PG".to_vec();
+        s.extend_from_slice(&pts.to_be_bytes());
+        s.extend_from_slice(&0u32.to_be_bytes());
+        s.push(kind);
+        s.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        s.extend_from_slice(data);
+        s
+    }
+
+    #[test]
+    fn display_sets() {
+        let mut sup = Vec::new();
+        // 1920x1080, epoch start, palette 0, object 0 in window 0 at (100, 200)
+        // cropped to (1, 0, 3, 2).
+        sup.extend(segment(
+            90_000,
+            PRESENTATION_COMPOSITION,
+            &[
+                0x07, 0x80, 0x04, 0x38, 0x10, 0x00, 0x01, 0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+                0x40, 0x00, 0x64, 0x00, 0xc8, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x00, 0x02,
+            ],
+        ));
+
+        // Window 0 at (100, 201) 10x10.
+        sup.extend(segment(
+            90_000,
+            WINDOW,
+            &[0x01, 0x00, 0x00, 0x64, 0x00, 0xc9, 0x00, 0x0a, 0x00, 0x0a],
+        ));
+
+        // Entry 1 white and 2 transparent.
+        sup.extend(segment(
+            90_000,
+            PALETTE,
+            &[0x00, 0x00, 0x01, 235, 128, 128, 255, 0x02, 16, 128, 128, 0],
+        ));
+
+        // A 4x2 object in two fragments: 1 2 1 2 / 4 x 1.
+        sup.extend(segment(
+            90_000,
+            OBJECT,
+            &[
+                0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x0f, 0x00, 0x04, 0x00, 0x02, 0x01, 0x02,
+            ],
+        ));
+
+        sup.extend(segment(
+            90_000,
+            OBJECT,
+            &[
+                0x00, 0x00, 0x00, 0x40, 0x01, 0x02, 0x00, 0x00, 0x00, 0x84, 0x01, 0x00, 0x00,
+            ],
+        ));
+
+        sup.extend(segment(90_000, END, &[]));
+
+        // An empty display set at 2 seconds.
+        sup.extend(segment(
+            180_000,
+            PRESENTATION_COMPOSITION,
+            &[
+                0x07, 0x80, 0x04, 0x38, 0x10, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            ],
+        ));
+
+        sup.extend(segment(180_000, END, &[]));
+
+        let display_sets = parse_sup(&sup).unwrap();
+        assert_eq!(1, display_sets.len());
+        let d = &display_sets[0];
+        assert_eq!((1000, Some(2000)), (d.time_in, d.time_out));
+        assert_eq!((1920, 1080), d.video_size);
+        assert_eq!(1, d.bitmaps.len());
+
+        let b = &d.bitmaps[0];
+        assert_eq!((100, 201, 3, 1), (b.x, b.y, b.width, b.height));
+        let pixels: Vec<u32> = b
+            .data
+            .chunks(4)
+            .map(|p| u32::from_ne_bytes([p[0], p[1], p[2], p[3]]))
+            .collect();
+        assert_eq!(vec![0xffff_ffff; 3], pixels);
+
+        assert!(parse_sup(b"PG").is_err());
+        assert!(parse_sup(b"XX\0\0\0\0\0\0\0\0\x80\0\0").is_err());
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(0xffff_ffff, argb([235, 128, 128, 255], 1080));
+        assert_eq!(0xff00_0000, argb([16, 128, 128, 255], 576));
+        assert_eq!(0x8080_8080, argb([235, 128, 128, 128], 1080));
+        assert_eq!(0, argb([235, 128, 128, 0], 1080));
+    }
+}
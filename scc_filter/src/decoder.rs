@@ -0,0 +1,488 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// A CEA-608 decoder for one data channel of field 1.
+
+use crate::scc::Packet;
+
+pub(crate) const ROWS: usize = 15;
+pub(crate) const COLUMNS: usize = 32;
+
+const SPECIAL_CHARS: [char; 16] = [
+    '®', '°', '½', '¿', '™', '¢', '£', '♪', 'à', ' ', 'è', 'â', 'ê', 'î', 'ô', 'û',
+];
+
+const EXTENDED_CHARS: [[char; 32]; 2] = [
+    [
+        'Á', 'É', 'Ó', 'Ú', 'Ü', 'ü', '‘', '¡', '*', '\'', '—', '©', '℠', '•', '“', '”', 'À', 'Â',
+        'Ç', 'È', 'Ê', 'Ë', 'ë', 'Î', 'Ï', 'ï', 'Ô', 'Ù', 'ù', 'Û', '«', '»',
+    ],
+    [
+        'Ã', 'ã', 'Í', 'Ì', 'ì', 'Ò', 'ò', 'Õ', 'õ', '{', '}', '\\', '^', '_', '|', '~', 'Ä', 'ä',
+        'Ö', 'ö', 'ß', '¥', '¤', '¦', 'Å', 'å', 'Ø', 'ø', '┌', '┐', '└', '┘',
+    ],
+];
+
+// The rows of the preamble address codes by the first byte, the second
+// byte selects the upper or lower row of the pair.
+const PAC_ROWS: [(usize, usize); 8] = [
+    (10, 10),
+    (0, 1),
+    (2, 3),
+    (11, 12),
+    (13, 14),
+    (4, 5),
+    (6, 7),
+    (8, 9),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Color {
+    White,
+    Green,
+    Blue,
+    Cyan,
+    Red,
+    Yellow,
+    Magenta,
+}
+
+impl Color {
+    pub(crate) fn rgb(self) -> (f64, f64, f64) {
+        match self {
+            Color::White => (1., 1., 1.),
+            Color::Green => (0., 1., 0.),
+            Color::Blue => (0., 0., 1.),
+            Color::Cyan => (0., 1., 1.),
+            Color::Red => (1., 0., 0.),
+            Color::Yellow => (1., 1., 0.),
+            Color::Magenta => (1., 0., 1.),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CharStyle {
+    pub(crate) color: Color,
+    pub(crate) italic: bool,
+    pub(crate) underline: bool,
+}
+
+impl Default for CharStyle {
+    fn default() -> CharStyle {
+        CharStyle {
+            color: Color::White,
+            italic: false,
+            underline: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Cell {
+    pub(crate) ch: char,
+    pub(crate) style: CharStyle,
+}
+
+// Empty cells are transparent.
+pub(crate) type Grid = [[Option<Cell>; COLUMNS]; ROWS];
+
+// The displayed memory from `time_in` to `time_out`.
+pub(crate) struct Caption {
+    pub(crate) time_in: u64,
+    pub(crate) time_out: Option<u64>,
+    pub(crate) grid: Grid,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    PopOn,
+    RollUp(usize),
+    PaintOn,
+    // Text service data is skipped.
+    Text,
+}
+
+struct Decoder {
+    mode: Mode,
+    displayed: Grid,
+    non_displayed: Grid,
+    row: usize,
+    column: usize,
+    style: CharStyle,
+    // The data channel of the last control code.
+    channel: u8,
+    // Control codes are usually sent twice and the repetition is ignored.
+    last_control: Option<(u8, u8)>,
+}
+
+// Decodes the captions of data channel 1 or 2.
+pub(crate) fn decode(packets: &[Packet], channel: u8) -> Vec<Caption> {
+    let mut decoder = Decoder {
+        mode: Mode::PopOn,
+        displayed: [[None; COLUMNS]; ROWS],
+        non_displayed: [[None; COLUMNS]; ROWS],
+        row: ROWS - 1,
+        column: 0,
+        style: CharStyle::default(),
+        channel: 1,
+        last_control: None,
+    };
+
+    let mut captions: Vec<Caption> = Vec::new();
+    for packet in packets {
+        // The parity bits aren't checked.
+        let (b1, b2) = (packet.data.0 & 0x7f, packet.data.1 & 0x7f);
+        if b1 == 0 && b2 == 0 {
+            continue;
+        }
+
+        decoder.decode(b1, b2, channel);
+
+        let changed = match captions.last() {
+            Some(c) => c.grid != decoder.displayed,
+            None => !is_empty(&decoder.displayed),
+        };
+
+        if changed {
+            match captions.last_mut() {
+                Some(c) if c.time_in == packet.time => c.grid = decoder.displayed,
+                _ => {
+                    if let Some(c) = captions.last_mut() {
+                        c.time_out = Some(packet.time);
+                    }
+
+                    captions.push(Caption {
+                        time_in: packet.time,
+                        time_out: None,
+                        grid: decoder.displayed,
+                    });
+                }
+            }
+        }
+    }
+
+    captions.retain(|c| !is_empty(&c.grid));
+    captions
+}
+
+fn is_empty(grid: &Grid) -> bool {
+    grid.iter().flatten().all(Option::is_none)
+}
+
+impl Decoder {
+    fn decode(&mut self, b1: u8, b2: u8, channel: u8) {
+        if (0x10..=0x1f).contains(&b1) {
+            let repeated = self.last_control == Some((b1, b2));
+            self.last_control = if repeated { None } else { Some((b1, b2)) };
+            if repeated {
+                return;
+            }
+
+            self.channel = if b1 & 0x08 == 0 { 1 } else { 2 };
+            if self.channel == channel {
+                self.control(b1 & 0x77, b2);
+            }
+
+            return;
+        }
+
+        self.last_control = None;
+        if self.channel != channel || self.mode == Mode::Text || b1 < 0x20 {
+            return;
+        }
+
+        self.put(standard_char(b1));
+        if b2 >= 0x20 {
+            self.put(standard_char(b2));
+        }
+    }
+
+    fn control(&mut self, b1: u8, b2: u8) {
+        match (b1, b2) {
+            (0x14, 0x20..=0x2f) | (0x15, 0x20..=0x2f) => self.command(b2),
+            (0x17, 0x21..=0x23) => {
+                self.column = (self.column + (b2 - 0x20) as usize).min(COLUMNS - 1)
+            }
+
+            (0x11, 0x20..=0x2f) => {
+                // Mid-row codes are shown as a space.
+                self.style = match b2 & 0x0e {
+                    0x0e => CharStyle {
+                        italic: true,
+                        ..self.style
+                    },
+
+                    c => CharStyle {
+                        color: color(c >> 1),
+                        italic: false,
+                        ..self.style
+                    },
+                };
+
+                self.style.underline = b2 & 1 == 1;
+                self.put(' ');
+            }
+
+            (0x11, 0x30..=0x3f) if b2 == 0x39 => {
+                // A transparent space.
+                self.put(' ');
+                let (row, column) = (self.row, self.column - 1);
+                self.memory()[row][column] = None;
+            }
+
+            (0x11, 0x30..=0x3f) => self.put(SPECIAL_CHARS[(b2 - 0x30) as usize]),
+            (0x12, 0x20..=0x3f) | (0x13, 0x20..=0x3f) => {
+                // Replaces the standard character sent before for older
+                // decoders.
+                self.backspace();
+                self.put(EXTENDED_CHARS[(b1 - 0x12) as usize][(b2 - 0x20) as usize]);
+            }
+
+            (0x10..=0x17, 0x40..=0x7f) => self.preamble(b1, b2),
+            // Background attributes and the like aren't supported.
+            _ => {}
+        }
+    }
+
+    fn command(&mut self, b2: u8) {
+        match b2 {
+            // Resume caption loading.
+            0x20 => self.mode = Mode::PopOn,
+            0x21 => self.backspace(),
+            // Delete to end of row.
+            0x24 => {
+                let (row, column) = (self.row, self.column);
+                for cell in &mut self.memory()[row][column..] {
+                    *cell = None;
+                }
+            }
+
+            0x25..=0x27 => self.roll_up((b2 - 0x23) as usize),
+            // Resume direct captioning.
+            0x29 => self.mode = Mode::PaintOn,
+            // Text restart and resume text display.
+            0x2a | 0x2b => self.mode = Mode::Text,
+            // Erase displayed memory.
+            0x2c => self.displayed = [[None; COLUMNS]; ROWS],
+            0x2d => self.carriage_return(),
+            // Erase non-displayed memory.
+            0x2e => self.non_displayed = [[None; COLUMNS]; ROWS],
+            // End of caption.
+            0x2f => {
+                std::mem::swap(&mut self.displayed, &mut self.non_displayed);
+                self.mode = Mode::PopOn;
+            }
+
+            _ => {}
+        }
+    }
+
+    fn preamble(&mut self, b1: u8, b2: u8) {
+        let rows = PAC_ROWS[(b1 - 0x10) as usize];
+        let row = if b2 & 0x20 == 0 { rows.0 } else { rows.1 };
+        if let Mode::RollUp(n) = self.mode {
+            // Moves the roll-up window to the new base row.
+            let n = n.min(row + 1);
+            if row != self.row {
+                let mut window = [[None; COLUMNS]; ROWS];
+                for i in 0..n {
+                    if i <= self.row {
+                        window[row - i] = self.displayed[self.row - i];
+                    }
+                }
+
+                self.displayed = window;
+            }
+        }
+
+        self.row = row;
+        let attr = b2 & 0x1f;
+        self.style = CharStyle {
+            color: Color::White,
+            italic: false,
+            underline: attr & 1 == 1,
+        };
+
+        if attr < 0x10 {
+            self.column = 0;
+            match attr >> 1 {
+                7 => self.style.italic = true,
+                c => self.style.color = color(c),
+            }
+        } else {
+            self.column = ((attr - 0x10) >> 1) as usize * 4;
+        }
+    }
+
+    fn roll_up(&mut self, n: usize) {
+        if let Mode::RollUp(_) = self.mode {
+            // Rows above a smaller window are erased.
+            for i in n..ROWS {
+                if i <= self.row {
+                    self.displayed[self.row - i] = [None; COLUMNS];
+                }
+            }
+        } else {
+            self.displayed = [[None; COLUMNS]; ROWS];
+            self.non_displayed = [[None; COLUMNS]; ROWS];
+            self.row = ROWS - 1;
+        }
+
+        self.mode = Mode::RollUp(n);
+        self.column = 0;
+        self.style = CharStyle::default();
+    }
+
+    fn carriage_return(&mut self) {
+        match self.mode {
+            Mode::RollUp(n) => {
+                let top = (self.row + 1).saturating_sub(n);
+                for row in top..self.row {
+                    self.displayed[row] = self.displayed[row + 1];
+                }
+
+                if top > 0 {
+                    self.displayed[top - 1] = [None; COLUMNS];
+                }
+
+                self.displayed[self.row] = [None; COLUMNS];
+            }
+
+            Mode::PaintOn | Mode::PopOn => {
+                if self.row < ROWS - 1 {
+                    self.row += 1;
+                }
+            }
+
+            Mode::Text => {}
+        }
+
+        self.column = 0;
+        self.style = CharStyle::default();
+    }
+
+    fn backspace(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+            let (row, column) = (self.row, self.column);
+            self.memory()[row][column] = None;
+        }
+    }
+
+    // Characters past the last column replace the last character.
+    fn put(&mut self, ch: char) {
+        let (row, column, style) = (self.row, self.column.min(COLUMNS - 1), self.style);
+        self.memory()[row][column] = Some(Cell { ch, style });
+        self.column = (column + 1).min(COLUMNS);
+    }
+
+    fn memory(&mut self) -> &mut Grid {
+        match self.mode {
+            Mode::PopOn | Mode::Text => &mut self.non_displayed,
+            Mode::RollUp(_) | Mode::PaintOn => &mut self.displayed,
+        }
+    }
+}
+
+fn color(c: u8) -> Color {
+    match c {
+        1 => Color::Green,
+        2 => Color::Blue,
+        3 => Color::Cyan,
+        4 => Color::Red,
+        5 => Color::Yellow,
+        6 => Color::Magenta,
+        _ => Color::White,
+    }
+}
+
+// The characters that differ from ASCII.
+fn standard_char(b: u8) -> char {
+    match b {
+        0x2a => 'á',
+        0x5c => 'é',
+        0x5e => 'í',
+        0x5f => 'ó',
+        0x60 => 'ú',
+        0x7b => 'ç',
+        0x7c => '÷',
+        0x7d => 'Ñ',
+        0x7e => 'ñ',
+        0x7f => '█',
+        b => b as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packets(words: &[u16]) -> Vec<Packet> {
+        words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| Packet {
+                time: i as u64 * 10,
+                data: ((w >> 8) as u8, *w as u8),
+            })
+            .collect()
+    }
+
+    fn text(grid: &Grid, row: usize) -> String {
+        grid[row]
+            .iter()
+            .map(|c| c.map(|c| c.ch).unwrap_or('.'))
+            .collect::<String>()
+            .trim_end_matches('.')
+            .to_string()
+    }
+
+    #[test]
+    fn pop_on() {
+        // RCL RCL ENM PAC(row 15, indent 4) "Hi" mid-row(yellow) "!" EOC EOC EDM
+        let captions = decode(
+            &packets(&[
+                0x9420, 0x9420, 0x94ae, 0x94f2, 0xc869, 0x91aa, 0x2180, 0x942f, 0x942f, 0x942c,
+            ]),
+            1,
+        );
+
+        assert_eq!(1, captions.len());
+        let c = &captions[0];
+        assert_eq!((70, Some(90)), (c.time_in, c.time_out));
+        assert_eq!("....Hi !", text(&c.grid, 14));
+        assert_eq!(Color::White, c.grid[14][4].unwrap().style.color);
+        assert_eq!(Color::Yellow, c.grid[14][7].unwrap().style.color);
+    }
+
+    #[test]
+    fn roll_up() {
+        // RU2 CR "a" CR "b" CR "c", then channel 2 "x"
+        let captions = decode(
+            &packets(&[
+                0x9425, 0x94ad, 0x6180, 0x94ad, 0x6280, 0x94ad, 0xe380, 0x1c2d, 0xf880,
+            ]),
+            1,
+        );
+
+        let last = captions.last().unwrap();
+        assert_eq!(None, last.time_out);
+        assert_eq!("b", text(&last.grid, 13));
+        assert_eq!("c", text(&last.grid, 14));
+        assert_eq!("", text(&last.grid, 12));
+    }
+
+    #[test]
+    fn paint_on() {
+        // RDC PAC(row 1, italics) "e" extended("Á" for "e") transparent space "x"
+        let captions = decode(
+            &packets(&[0x9429, 0x91ce, 0x6580, 0x9220, 0x91b9, 0xf880]),
+            1,
+        );
+        let last = captions.last().unwrap();
+        assert_eq!("Á.x", text(&last.grid, 0));
+        assert!(last.grid[0][0].unwrap().style.italic);
+    }
+}
@@ -0,0 +1,181 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ffi::CStr;
+use std::fs;
+use std::ptr;
+
+use libc::{c_char, c_double, c_int, c_uchar, c_uint, c_void};
+
+mod decoder;
+use decoder::Caption;
+
+mod render;
+
+mod scc;
+
+struct Config<'a> {
+    channel: u8,
+    scc: &'a str,
+}
+
+struct Context {
+    captions: Vec<Caption>,
+}
+
+/// # Safety
+///
+/// `config` must be null or a NUL-terminated string and `user_data` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void) -> c_int {
+    unsafe {
+        *user_data = ptr::null_mut();
+    }
+
+    if config.is_null() {
+        eprintln!("got null config");
+        return 1;
+    }
+
+    let config = match parse_config(config) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("invalid config: {}", e);
+            return 1;
+        }
+    };
+
+    let captions = match read_scc(config.scc, config.channel) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}: {}", config.scc, e);
+            return 1;
+        }
+    };
+
+    let ctx = Context { captions };
+    unsafe {
+        *user_data = Box::into_raw(Box::new(ctx)) as *mut c_void;
+    }
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn filter_frame(
+    data: *mut c_uchar,
+    data_size: c_uint,
+    width: c_int,
+    height: c_int,
+    line_size: c_int,
+    ts_millis: c_double,
+    user_data: *mut c_void,
+) -> c_int {
+    if data.is_null() || width <= 0 || height <= 0 {
+        return 0;
+    }
+
+    let ctx = if user_data.is_null() {
+        eprintln!("no user data");
+        return 1;
+    } else {
+        unsafe { &*(user_data as *const Context) }
+    };
+
+    let caption = match find_caption(&ctx.captions, ts_millis) {
+        Some(idx) => &ctx.captions[idx],
+        None => {
+            return 0;
+        }
+    };
+
+    let cr = match new_cairo_context(data, data_size as usize, width, height, line_size) {
+        Ok(cr) => cr,
+        Err(status) => {
+            eprintln!("could not create cairo context: {:?}", status);
+            return 1;
+        }
+    };
+
+    render::render(caption, width as f64, height as f64, &cr);
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn filter_uninit(user_data: *mut c_void) {
+    if !user_data.is_null() {
+        unsafe {
+            drop(Box::from_raw(user_data as *mut Context));
+        }
+    }
+}
+
+fn parse_config<'a>(config: *const c_char) -> anyhow::Result<Config<'a>> {
+    let cstr = unsafe { CStr::from_ptr(config) };
+    let s = cstr.to_str()?;
+    let re = regex::Regex::new(r"^(?:channel=(1|2),)?scc=(.+)$").unwrap();
+    if let Some(cap) = re.captures(s) {
+        let channel = match cap.get(1) {
+            Some(c) if c.as_str() == "2" => 2,
+            _ => 1,
+        };
+
+        Ok(Config {
+            channel,
+            scc: cap.get(2).unwrap().as_str(),
+        })
+    } else {
+        Err(anyhow::anyhow!(s))
+    }
+}
+
+fn read_scc(scc: &str, channel: u8) -> anyhow::Result<Vec<Caption>> {
+    let s = fs::read_to_string(scc)?;
+    let packets = scc::parse_scc(&s)?;
+    Ok(decoder::decode(&packets, channel))
+}
+
+// The captions don't overlap since they are snapshots of the displayed
+// memory.
+fn find_caption(captions: &[Caption], ts: f64) -> Option<usize> {
+    captions
+        .binary_search_by(|c| {
+            let time_in = c.time_in as f64;
+            let time_out = c.time_out.map(|t| t as f64).unwrap_or(f64::INFINITY);
+            if ts >= time_in && ts < time_out {
+                std::cmp::Ordering::Equal
+            } else if time_in < ts {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Greater
+            }
+        })
+        .ok()
+}
+
+fn new_cairo_context(
+    data: *mut c_uchar,
+    _data_size: usize,
+    width: i32,
+    height: i32,
+    line_size: i32,
+) -> Result<cairo::Context, cairo::Status> {
+    let surface = unsafe {
+        let surface = cairo_sys::cairo_image_surface_create_for_data(
+            data,
+            cairo_sys::FORMAT_A_RGB32,
+            width,
+            height,
+            line_size,
+        );
+
+        cairo::ImageSurface::from_raw_full(surface)?
+    };
+
+    let cr = cairo::Context::new(&surface);
+    cr.set_antialias(cairo::Antialias::Best);
+    Ok(cr)
+}
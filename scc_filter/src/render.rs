@@ -0,0 +1,84 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::decoder::{Caption, CharStyle, COLUMNS, ROWS};
+
+// The grid covers the safe caption area, 80% of the 4:3 center of the
+// frame.
+const SAFE_AREA: f64 = 0.8;
+
+// Renders the grid with the classic look, i.e. monospace characters on an
+// opaque black background.
+pub(crate) fn render(caption: &Caption, width: f64, height: f64, cr: &cairo::Context) {
+    let area_width = width.min(height * 4. / 3.) * SAFE_AREA;
+    let area_height = height.min(width * 3. / 4.) * SAFE_AREA;
+    let left = (width - area_width) / 2.;
+    let top = (height - area_height) / 2.;
+    let cell_width = area_width / COLUMNS as f64;
+    let cell_height = area_height / ROWS as f64;
+
+    let mut size = cell_height * 0.8;
+    set_font(cr, &CharStyle::default(), size);
+    let advance = cr.text_extents("M").x_advance;
+    if advance > cell_width {
+        size *= cell_width / advance;
+    }
+
+    for (i, row) in caption.grid.iter().enumerate() {
+        let y = top + i as f64 * cell_height;
+        let mut start = None;
+        for (j, cell) in row.iter().chain(&[None]).enumerate() {
+            match (cell, start) {
+                (Some(_), None) => start = Some(j),
+                (None, Some(s)) => {
+                    cr.set_source_rgb(0., 0., 0.);
+                    cr.rectangle(
+                        left + s as f64 * cell_width,
+                        y,
+                        (j - s) as f64 * cell_width,
+                        cell_height,
+                    );
+                    cr.fill();
+                    start = None;
+                }
+
+                _ => {}
+            }
+        }
+
+        for (j, cell) in row.iter().enumerate() {
+            let cell = match cell {
+                Some(c) if c.ch != ' ' || c.style.underline => c,
+                _ => continue,
+            };
+
+            let x = left + j as f64 * cell_width;
+            set_font(cr, &cell.style, size);
+            let font = cr.font_extents();
+            let baseline = y + (cell_height - font.ascent - font.descent) / 2. + font.ascent;
+            let s = cell.ch.to_string();
+            let advance = cr.text_extents(&s).x_advance;
+            cr.move_to(x + (cell_width - advance) / 2., baseline);
+            cr.text_path(&s);
+            if cell.style.underline {
+                cr.rectangle(x, baseline + size / 10., cell_width, size / 15.);
+            }
+
+            let (r, g, b) = cell.style.color.rgb();
+            cr.set_source_rgb(r, g, b);
+            cr.fill();
+        }
+    }
+}
+
+fn set_font(cr: &cairo::Context, style: &CharStyle, size: f64) {
+    let slant = if style.italic {
+        cairo::FontSlant::Italic
+    } else {
+        cairo::FontSlant::Normal
+    };
+
+    cr.select_font_face("monospace", slant, cairo::FontWeight::Normal);
+    cr.set_font_size(size);
+}
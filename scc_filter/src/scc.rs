@@ -0,0 +1,112 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+const HEADER: &str = "Scenarist_SCC V1.0";
+
+// A byte pair of line 21 field 1 data, with the parity bits kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Packet {
+    pub(crate) time: u64,
+    pub(crate) data: (u8, u8),
+}
+
+// Parses a Scenarist SCC file. Every line has a timecode followed by byte
+// pairs in hex, one pair is sent per frame at 29.97 fps.
+pub(crate) fn parse_scc(s: &str) -> anyhow::Result<Vec<Packet>> {
+    let mut lines = s.trim_start_matches('\u{feff}').lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == HEADER => {}
+        _ => return Err(anyhow::anyhow!("missing {} header", HEADER)),
+    }
+
+    let mut packets = Vec::new();
+    for (i, line) in lines {
+        let error = |e| anyhow::anyhow!("line {}: {}", i + 1, e);
+        let mut words = line.split_whitespace();
+        let frames = match words.next() {
+            Some(tc) => parse_timecode(tc).map_err(error)?,
+            None => continue,
+        };
+
+        for (n, word) in words.enumerate() {
+            let data = match u16::from_str_radix(word, 16) {
+                Ok(w) if word.len() == 4 => ((w >> 8) as u8, w as u8),
+                _ => return Err(error(anyhow::anyhow!("invalid byte pair {}", word))),
+            };
+
+            packets.push(Packet {
+                time: frames_to_millis(frames + n as u64),
+                data,
+            });
+        }
+    }
+
+    Ok(packets)
+}
+
+// Parses `HH:MM:SS:FF`, or `HH:MM:SS;FF` for drop frame, into a frame count
+// at 29.97 fps.
+fn parse_timecode(s: &str) -> anyhow::Result<u64> {
+    let invalid = || anyhow::anyhow!("invalid timecode {}", s);
+    let drop_frame = s.contains(';');
+    let parts = s
+        .split(&[':', ';', '.'][..])
+        .map(|p| p.parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>()
+        .map_err(|_| invalid())?;
+
+    let (h, m, secs, f) = match parts[..] {
+        [h, m, secs, f] if m < 60 && secs < 60 && f < 30 => (h, m, secs, f),
+        _ => return Err(invalid()),
+    };
+
+    let frames = ((h * 60 + m) * 60 + secs) * 30 + f;
+    if !drop_frame {
+        return Ok(frames);
+    }
+
+    // Frames 0 and 1 are dropped every minute except every tenth.
+    if f < 2 && secs == 0 && m % 10 != 0 {
+        return Err(invalid());
+    }
+
+    let minutes = h * 60 + m;
+    Ok(frames - 2 * (minutes - minutes / 10))
+}
+
+fn frames_to_millis(frames: u64) -> u64 {
+    (frames as f64 * 1001. / 30.).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timecodes() {
+        assert_eq!(30, parse_timecode("00:00:01:00").unwrap());
+        assert_eq!(1800, parse_timecode("00:01:00:00").unwrap());
+        assert_eq!(1800, parse_timecode("00:01:00;02").unwrap());
+        assert_eq!(17982, parse_timecode("00:10:00;00").unwrap());
+        assert_eq!(107_892, parse_timecode("01:00:00;00").unwrap());
+        assert!(parse_timecode("00:01:00;00").is_err());
+        assert!(parse_timecode("00:00:00:30").is_err());
+        assert!(parse_timecode("00:00:00").is_err());
+
+        // Drop frame timecodes are close to the wall clock.
+        assert_eq!(3_599_996, frames_to_millis(107_892));
+    }
+
+    #[test]
+    fn parse() {
+        let scc = "Scenarist_SCC V1.0\n\n00:00:01:00\t9420 9420 94ae\n\n00:00:02;00\t942f\n";
+        let packets = parse_scc(scc).unwrap();
+        let times: Vec<u64> = packets.iter().map(|p| p.time).collect();
+        assert_eq!(vec![1001, 1034, 1068, 2002], times);
+        assert_eq!((0x94, 0x2f), packets[3].data);
+
+        assert!(parse_scc("00:00:01:00\t9420").is_err());
+        assert!(parse_scc("Scenarist_SCC V1.0\n00:00:01:00\t942").is_err());
+    }
+}
@@ -9,7 +9,7 @@ use std::ptr;
 use libc::{c_char, c_double, c_int, c_uchar, c_uint, c_void};
 
 mod subtitle_rendering_data;
-use subtitle_rendering_data::{Point, RenderingData, SegmentType, Transition};
+use subtitle_rendering_data::{Path, Point, RenderingData, SegmentType, Transition};
 
 enum ScaleType {
     None,
@@ -31,6 +31,7 @@ struct Config<'a> {
 struct Context {
     scale_type: ScaleType,
     rendering_data: RenderingData,
+    paths: Vec<cairo::Path>,
 }
 
 #[no_mangle]
@@ -52,7 +53,7 @@ pub extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void
         }
     };
 
-    let rendering_data = match read_srf(config.srf) {
+    let mut rendering_data = match read_srf(config.srf) {
         Ok(rd) => rd,
         Err(e) => {
             eprintln!("{}: {:?}", config.srf, e);
@@ -60,9 +61,21 @@ pub extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void
         }
     };
 
+    let paths = match compile_paths(&rendering_data) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}: {:?}", config.srf, e);
+            return 1;
+        }
+    };
+
+    // The segments are not needed once the paths have been compiled.
+    rendering_data.clear_paths();
+
     let ctx = Context {
         scale_type: config.scale_type,
         rendering_data,
+        paths,
     };
 
     unsafe {
@@ -160,6 +173,88 @@ fn read_srf(srf: &str) -> anyhow::Result<RenderingData> {
     Ok(rendering_data)
 }
 
+// Builds a cairo path for each entry in `RenderingData.paths` so that
+// rendering a shape only has to append an already converted path.
+fn compile_paths(rendering_data: &RenderingData) -> anyhow::Result<Vec<cairo::Path>> {
+    let paths = rendering_data.get_paths();
+    for transition in rendering_data.get_transitions() {
+        for shape in transition.get_shapes() {
+            if shape.get_path_index() as usize >= paths.len() {
+                return Err(anyhow::anyhow!(
+                    "invalid path index {}",
+                    shape.get_path_index()
+                ));
+            }
+        }
+    }
+
+    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 1, 1)
+        .map_err(|s| anyhow::anyhow!("could not create surface: {:?}", s))?;
+    let cr = cairo::Context::new(&surface);
+
+    let mut compiled = Vec::with_capacity(paths.len());
+    for path in paths {
+        cr.new_path();
+        build_path(&cr, path);
+        compiled.push(cr.copy_path());
+    }
+
+    Ok(compiled)
+}
+
+fn build_path(cr: &cairo::Context, path: &Path) {
+    for seg in path.get_segments() {
+        match seg.get_field_type() {
+            SegmentType::MOVE => {
+                let p = &seg.get_points()[0];
+                cr.move_to(unfix(p.get_x()), unfix(p.get_y()));
+            }
+            SegmentType::LINE => {
+                let p = &seg.get_points()[0];
+                cr.line_to(unfix(p.get_x()), unfix(p.get_y()));
+            }
+            SegmentType::QUAD => {
+                let c = &seg.get_points()[0];
+                let p = &seg.get_points()[1];
+                quad_to_curve(cr, c, p);
+            }
+            SegmentType::CUBIC => {
+                let c1 = &seg.get_points()[0];
+                let c2 = &seg.get_points()[1];
+                let p = &seg.get_points()[2];
+                cr.curve_to(
+                    unfix(c1.get_x()),
+                    unfix(c1.get_y()),
+                    unfix(c2.get_x()),
+                    unfix(c2.get_y()),
+                    unfix(p.get_x()),
+                    unfix(p.get_y()),
+                );
+            }
+            SegmentType::CLOSE => {
+                cr.close_path();
+            }
+        };
+    }
+}
+
+fn quad_to_curve(cr: &cairo::Context, c: &Point, p: &Point) {
+    let (x1, y1) = cr.get_current_point();
+
+    let x2 = unfix(c.get_x());
+    let y2 = unfix(c.get_y());
+    let x3 = unfix(p.get_x());
+    let y3 = unfix(p.get_y());
+    cr.curve_to(
+        x1 + (2. / 3.) * (x2 - x1),
+        y1 + (2. / 3.) * (y2 - y1),
+        x3 + (2. / 3.) * (x2 - x3),
+        y3 + (2. / 3.) * (y2 - y3),
+        x3,
+        y3,
+    );
+}
+
 fn find_transition(transitions: &[Transition], ts: f64) -> Option<usize> {
     transitions
         .binary_search_by(|t| {
@@ -265,40 +360,8 @@ impl<'a> RenderContext<'a> {
             self.cr
                 .translate(unfix(shape.get_x()), unfix(shape.get_y()));
 
-            let path = &self.ctx.rendering_data.get_paths()[shape.get_path_index() as usize];
-            for seg in path.get_segments() {
-                match seg.get_field_type() {
-                    SegmentType::MOVE => {
-                        let p = &seg.get_points()[0];
-                        self.cr.move_to(unfix(p.get_x()), unfix(p.get_y()));
-                    }
-                    SegmentType::LINE => {
-                        let p = &seg.get_points()[0];
-                        self.cr.line_to(unfix(p.get_x()), unfix(p.get_y()));
-                    }
-                    SegmentType::QUAD => {
-                        let c = &seg.get_points()[0];
-                        let p = &seg.get_points()[1];
-                        self.quad_to_curve(c, p);
-                    }
-                    SegmentType::CUBIC => {
-                        let c1 = &seg.get_points()[0];
-                        let c2 = &seg.get_points()[1];
-                        let p = &seg.get_points()[2];
-                        self.cr.curve_to(
-                            unfix(c1.get_x()),
-                            unfix(c1.get_y()),
-                            unfix(c2.get_x()),
-                            unfix(c2.get_y()),
-                            unfix(p.get_x()),
-                            unfix(p.get_y()),
-                        );
-                    }
-                    SegmentType::CLOSE => {
-                        self.cr.close_path();
-                    }
-                };
-            }
+            let path = &self.ctx.paths[shape.get_path_index() as usize];
+            self.cr.append_path(path);
 
             self.set_color(shape.get_argb());
             if shape.get_fill() {
@@ -314,23 +377,6 @@ impl<'a> RenderContext<'a> {
         }
     }
 
-    fn quad_to_curve(&self, c: &Point, p: &Point) {
-        let (x1, y1) = self.cr.get_current_point();
-
-        let x2 = unfix(c.get_x());
-        let y2 = unfix(c.get_y());
-        let x3 = unfix(p.get_x());
-        let y3 = unfix(p.get_y());
-        self.cr.curve_to(
-            x1 + (2. / 3.) * (x2 - x1),
-            y1 + (2. / 3.) * (y2 - y1),
-            x3 + (2. / 3.) * (x2 - x3),
-            y3 + (2. / 3.) * (y2 - y3),
-            x3,
-            y3,
-        );
-    }
-
     fn set_color(&self, argb: u32) {
         let r = ((argb >> 16) & 0xFF) as f64 / 255.;
         let g = ((argb >> 8) & 0xFF) as f64 / 255.;
@@ -0,0 +1,1400 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Evaluation of SMIL (`<animate>`, `<set>`, `<animateTransform>`) and CSS
+// keyframe animations at a given time. resvg only renders the static
+// state of a document so the animated values are written back into the
+// SVG source, which is then parsed as usual.
+
+use std::ops::Range;
+
+use roxmltree::{Document, Node};
+
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+// Properties that are written to the `style` attribute so that they
+// override any other styling of the element, like animations do.
+const STYLE_PROPERTIES: &[&str] = &[
+    "clip-rule",
+    "color",
+    "display",
+    "fill",
+    "fill-opacity",
+    "fill-rule",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-weight",
+    "letter-spacing",
+    "opacity",
+    "stop-color",
+    "stop-opacity",
+    "stroke",
+    "stroke-dasharray",
+    "stroke-dashoffset",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-miterlimit",
+    "stroke-opacity",
+    "stroke-width",
+    "visibility",
+    "word-spacing",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    CubicBezier(f64, f64, f64, f64),
+    // Number of steps and whether the jump happens at the start of a step.
+    Steps(u32, bool),
+}
+
+impl Easing {
+    pub fn parse(s: &str) -> Option<Easing> {
+        let easing = match s.trim() {
+            "linear" => Easing::Linear,
+            "ease" => Easing::CubicBezier(0.25, 0.1, 0.25, 1.),
+            "ease-in" => Easing::CubicBezier(0.42, 0., 1., 1.),
+            "ease-out" => Easing::CubicBezier(0., 0., 0.58, 1.),
+            "ease-in-out" => Easing::CubicBezier(0.42, 0., 0.58, 1.),
+            "step-start" => Easing::Steps(1, true),
+            "step-end" => Easing::Steps(1, false),
+            s => {
+                if let Some(args) = function_args(s, "cubic-bezier") {
+                    let n = parse_numbers(args)?;
+                    if n.len() != 4 || !(0. ..=1.).contains(&n[0]) || !(0. ..=1.).contains(&n[2]) {
+                        return None;
+                    }
+
+                    Easing::CubicBezier(n[0], n[1], n[2], n[3])
+                } else if let Some(args) = function_args(s, "steps") {
+                    let mut args = args.split(',').map(str::trim);
+                    let steps = args.next()?.parse().ok().filter(|&n| n > 0)?;
+                    let start = match args.next() {
+                        None | Some("end") | Some("jump-end") => false,
+                        Some("start") | Some("jump-start") => true,
+                        _ => return None,
+                    };
+
+                    Easing::Steps(steps, start)
+                } else {
+                    return None;
+                }
+            }
+        };
+
+        Some(easing)
+    }
+
+    pub fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::CubicBezier(x1, y1, x2, y2) => {
+                let u = solve_bezier(t, x1, x2);
+                bezier(u, y1, y2)
+            }
+
+            Easing::Steps(n, start) => {
+                let n = n as f64;
+                let step = (t * n).floor() + if start { 1. } else { 0. };
+                (step / n).min(1.)
+            }
+        }
+    }
+}
+
+// One dimensional cubic bezier from 0 to 1 with the control points p1 and p2.
+fn bezier(u: f64, p1: f64, p2: f64) -> f64 {
+    let v = 1. - u;
+    3. * v * v * u * p1 + 3. * v * u * u * p2 + u * u * u
+}
+
+// Finds u such that bezier(u, x1, x2) == x.
+fn solve_bezier(x: f64, x1: f64, x2: f64) -> f64 {
+    let (mut lo, mut hi) = (0., 1.);
+    let mut u = x;
+    for _ in 0..32 {
+        let d = bezier(u, x1, x2) - x;
+        if d.abs() < 1e-7 {
+            break;
+        }
+
+        if d > 0. {
+            hi = u;
+        } else {
+            lo = u;
+        }
+
+        u = (lo + hi) / 2.;
+    }
+
+    u
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Normal,
+    Reverse,
+    Alternate,
+    AlternateReverse,
+}
+
+// When an animation is active, all times in seconds.
+#[derive(Debug, Clone, Copy)]
+struct Timing {
+    begin: f64,
+    // Simple duration, infinite if indefinite.
+    dur: f64,
+    // Number of iterations, may be fractional or infinite.
+    repeat: f64,
+    // Keep the last value after the end.
+    freeze: bool,
+    // Apply the first value before the beginning.
+    backwards: bool,
+    direction: Direction,
+}
+
+impl Timing {
+    // Returns the progress within the current iteration, or None if the
+    // animation has no effect at time t.
+    fn progress(&self, t: f64) -> Option<f64> {
+        if t < self.begin {
+            return if self.backwards {
+                Some(self.directed(0., 0.))
+            } else {
+                None
+            };
+        }
+
+        if !self.dur.is_finite() {
+            return Some(0.);
+        }
+
+        let elapsed = (t - self.begin) / self.dur;
+        if elapsed >= self.repeat {
+            if !self.freeze {
+                return None;
+            }
+
+            return Some(if self.repeat.fract() == 0. {
+                self.directed(self.repeat - 1., 1.)
+            } else {
+                self.directed(self.repeat.floor(), self.repeat.fract())
+            });
+        }
+
+        let iteration = elapsed.floor();
+        Some(self.directed(iteration, elapsed - iteration))
+    }
+
+    // Returns the time when the animation stops changing, infinite if it
+    // repeats forever. Indefinite animations keep their first value.
+    fn end(&self) -> f64 {
+        if self.dur.is_finite() {
+            self.begin + self.dur * self.repeat
+        } else {
+            self.begin
+        }
+    }
+
+    fn directed(&self, iteration: f64, p: f64) -> f64 {
+        let odd = iteration % 2. == 1.;
+        let reverse = match self.direction {
+            Direction::Normal => false,
+            Direction::Reverse => true,
+            Direction::Alternate => odd,
+            Direction::AlternateReverse => !odd,
+        };
+
+        if reverse {
+            1. - p
+        } else {
+            p
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Property {
+    Attribute(String),
+    Style(String),
+}
+
+impl Property {
+    fn new(name: &str) -> Property {
+        if STYLE_PROPERTIES.contains(&name) {
+            Property::Style(String::from(name))
+        } else {
+            Property::Attribute(String::from(name))
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Animation {
+    property: Property,
+    timing: Timing,
+    discrete: bool,
+    values: Vec<String>,
+    key_times: Vec<f64>,
+    // Easing per interval between values, or a single one for all.
+    easings: Vec<Easing>,
+    // The `animateTransform` type, e.g. `rotate`.
+    transform: Option<String>,
+    additive: bool,
+}
+
+impl Animation {
+    fn value_at(&self, t: f64) -> Option<String> {
+        let p = self.timing.progress(t)?;
+        let n = self.values.len();
+        let i = self.key_times.iter().rposition(|&kt| kt <= p).unwrap_or(0);
+
+        let value = if n == 1 || self.discrete {
+            self.values[i].clone()
+        } else {
+            let i = i.min(n - 2);
+            let span = self.key_times[i + 1] - self.key_times[i];
+            let local = if span > 0. {
+                ((p - self.key_times[i]) / span).clamp(0., 1.)
+            } else {
+                1.
+            };
+
+            let easing = self.easings.get(i).or_else(|| self.easings.last());
+            let eased = easing.map(|e| e.apply(local)).unwrap_or(local);
+            interpolate(&self.values[i], &self.values[i + 1], eased)
+        };
+
+        Some(match &self.transform {
+            Some(ty) => format!("{}({})", ty, value),
+            None => value,
+        })
+    }
+}
+
+// An element with animated attributes.
+#[derive(Debug)]
+struct Target {
+    // The range of the start tag in the SVG source.
+    tag: Range<usize>,
+    name: String,
+    // Attribute names, raw values and quote characters from the source.
+    attrs: Vec<(String, String, char)>,
+    self_closing: bool,
+    animations: Vec<Animation>,
+}
+
+impl Target {
+    fn render(&self, t: f64) -> String {
+        let mut attrs = self.attrs.clone();
+        let mut style = Vec::new();
+        for anim in &self.animations {
+            let value = match anim.value_at(t) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            match &anim.property {
+                Property::Attribute(name) => {
+                    let value = escape(&value);
+                    match attrs.iter_mut().find(|a| &a.0 == name) {
+                        Some(a) if anim.additive => a.1 = format!("{} {}", a.1, value),
+                        Some(a) => {
+                            a.1 = value;
+                            a.2 = '"';
+                        }
+
+                        None => attrs.push((name.clone(), value, '"')),
+                    }
+                }
+
+                Property::Style(name) => style.push(format!("{}:{}", name, escape(&value))),
+            }
+        }
+
+        if !style.is_empty() {
+            let style = style.join(";");
+            match attrs.iter_mut().find(|a| a.0 == "style") {
+                Some(a) => a.1 = format!("{};{}", a.1, style),
+                None => attrs.push((String::from("style"), style, '"')),
+            }
+        }
+
+        let mut tag = format!("<{}", self.name);
+        for (name, value, quote) in &attrs {
+            tag.push_str(&format!(" {}={}{}{}", name, quote, value, quote));
+        }
+
+        tag.push_str(if self.self_closing { "/>" } else { ">" });
+        tag
+    }
+}
+
+// The animations in an SVG document.
+pub struct Animations {
+    svg: String,
+    targets: Vec<Target>,
+}
+
+impl Animations {
+    // Returns None if the document has no supported animations.
+    pub fn parse(svg: &str) -> Result<Option<Animations>, roxmltree::Error> {
+        if !svg.contains("<animate") && !svg.contains("<set") && !svg.contains("@keyframes") {
+            return Ok(None);
+        }
+
+        let doc = Document::parse(svg)?;
+        let mut targets: Vec<Target> = Vec::new();
+        let mut add = |start: usize, anim: Animation| {
+            if let Some(target) = targets.iter_mut().find(|t| t.tag.start == start) {
+                target.animations.push(anim);
+                return;
+            }
+
+            let tag = start_tag(svg, start);
+            if let Some((name, attrs, self_closing)) = parse_start_tag(tag) {
+                targets.push(Target {
+                    tag: start..start + tag.len(),
+                    name,
+                    attrs,
+                    self_closing,
+                    animations: vec![anim],
+                });
+            }
+        };
+
+        for node in doc.descendants().filter(|n| n.is_element()) {
+            if let Some((target, anim)) = parse_smil(&doc, node) {
+                add(target, anim);
+            }
+        }
+
+        let css: String = doc
+            .descendants()
+            .filter(|n| n.tag_name().name() == "style")
+            .filter_map(|n| n.text())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let (keyframes, rules) = parse_css(&css);
+        if !keyframes.is_empty() {
+            for node in doc.descendants().filter(|n| n.is_element()) {
+                for anim in css_animations(node, &keyframes, &rules) {
+                    add(node.range().start, anim);
+                }
+            }
+        }
+
+        if targets.is_empty() {
+            return Ok(None);
+        }
+
+        targets.sort_by_key(|t| t.tag.start);
+        Ok(Some(Animations {
+            svg: String::from(svg),
+            targets,
+        }))
+    }
+
+    // Returns the SVG source with the animated values at time t, in
+    // seconds, applied.
+    pub fn render(&self, t: f64) -> String {
+        let mut svg = String::with_capacity(self.svg.len());
+        let mut pos = 0;
+        for target in &self.targets {
+            svg.push_str(&self.svg[pos..target.tag.start]);
+            svg.push_str(&target.render(t));
+            pos = target.tag.end;
+        }
+
+        svg.push_str(&self.svg[pos..]);
+        svg
+    }
+
+    // Returns the time in seconds from which the rendered SVG no longer
+    // changes, infinite if some animation repeats forever.
+    pub fn end(&self) -> f64 {
+        self.targets
+            .iter()
+            .flat_map(|t| &t.animations)
+            .map(|a| a.timing.end())
+            .fold(0., f64::max)
+    }
+}
+
+// Returns the position of the target element and the animation.
+fn parse_smil(doc: &Document, node: Node) -> Option<(usize, Animation)> {
+    let kind = node.tag_name().name();
+    if !["animate", "animateColor", "animateTransform", "set"].contains(&kind) {
+        return None;
+    }
+
+    let target = match node
+        .attribute("href")
+        .or_else(|| node.attribute((XLINK_NS, "href")))
+    {
+        Some(href) => {
+            let id = href.strip_prefix('#')?;
+            doc.descendants().find(|n| n.attribute("id") == Some(id))?
+        }
+
+        None => node.parent_element()?,
+    };
+
+    let attribute = node.attribute("attributeName")?;
+    let transform = if kind == "animateTransform" {
+        Some(String::from(node.attribute("type").unwrap_or("translate")))
+    } else {
+        None
+    };
+
+    let base = target.attribute(attribute).filter(|_| transform.is_none());
+    let values = if kind == "set" {
+        vec![String::from(node.attribute("to")?)]
+    } else if let Some(values) = node.attribute("values") {
+        values
+            .split(';')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(String::from)
+            .collect()
+    } else {
+        let from = node.attribute("from").or(base);
+        match (from, node.attribute("to"), node.attribute("by")) {
+            (Some(from), Some(to), _) => vec![String::from(from), String::from(to)],
+            (None, Some(to), _) => vec![String::from(to)],
+            (Some(from), None, Some(by)) => vec![String::from(from), add(from, by)?],
+            _ => return None,
+        }
+    };
+
+    if values.is_empty() {
+        return None;
+    }
+
+    let calc_mode =
+        node.attribute("calcMode")
+            .unwrap_or(if kind == "set" { "discrete" } else { "linear" });
+
+    let discrete = calc_mode == "discrete";
+    let key_times = node
+        .attribute("keyTimes")
+        .and_then(parse_list)
+        .filter(|kt| kt.len() == values.len())
+        .unwrap_or_else(|| default_key_times(values.len(), discrete));
+
+    let easings = if calc_mode == "spline" {
+        node.attribute("keySplines")?
+            .split(';')
+            .map(|s| {
+                let n = parse_numbers(s)?;
+                if n.len() == 4 {
+                    Some(Easing::CubicBezier(n[0], n[1], n[2], n[3]))
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<Vec<_>>>()?
+    } else {
+        vec![Easing::Linear]
+    };
+
+    let dur = match node.attribute("dur") {
+        None | Some("indefinite") => f64::INFINITY,
+        Some(d) => parse_clock(d)?,
+    };
+
+    let mut repeat = match node.attribute("repeatCount") {
+        None => 1.,
+        Some("indefinite") => f64::INFINITY,
+        Some(r) => r.trim().parse().ok()?,
+    };
+
+    if let Some(rd) = node.attribute("repeatDur") {
+        let rd = if rd == "indefinite" {
+            f64::INFINITY
+        } else {
+            parse_clock(rd)?
+        };
+
+        if node.attribute("repeatCount").is_none() {
+            repeat = f64::INFINITY;
+        }
+
+        repeat = repeat.min(rd / dur);
+    }
+
+    // Only offset values are supported, event based begin values never
+    // start the animation.
+    let begin = match node.attribute("begin") {
+        None => 0.,
+        Some(b) => parse_clock(b.split(';').next().unwrap())?,
+    };
+
+    if dur <= 0. || repeat <= 0. {
+        return None;
+    }
+
+    let timing = Timing {
+        begin,
+        dur,
+        repeat,
+        freeze: node.attribute("fill") == Some("freeze"),
+        backwards: false,
+        direction: Direction::Normal,
+    };
+
+    let animation = Animation {
+        property: if transform.is_some() {
+            Property::Attribute(String::from("transform"))
+        } else {
+            Property::new(attribute)
+        },
+        timing,
+        discrete,
+        values,
+        key_times,
+        easings,
+        additive: node.attribute("additive") == Some("sum"),
+        transform,
+    };
+
+    Some((target.range().start, animation))
+}
+
+fn default_key_times(n: usize, discrete: bool) -> Vec<f64> {
+    if n == 1 {
+        return vec![0.];
+    }
+
+    let d = if discrete { n } else { n - 1 } as f64;
+    (0..n).map(|i| i as f64 / d).collect()
+}
+
+// Parses a SMIL clock value, `02:30:03`, `50:00.10`, `10s`, `1.5min`,
+// `500ms` or a plain number of seconds.
+fn parse_clock(s: &str) -> Option<f64> {
+    let s = s.trim();
+    if s.contains(':') {
+        let mut secs = 0.;
+        for part in s.split(':') {
+            secs = secs * 60. + part.parse::<f64>().ok()?;
+        }
+
+        return Some(secs);
+    }
+
+    let units = [("ms", 0.001), ("min", 60.), ("h", 3600.), ("s", 1.)];
+    for (unit, scale) in units.iter() {
+        if let Some(n) = s.strip_suffix(unit) {
+            return n.parse::<f64>().ok().map(|n| n * scale);
+        }
+    }
+
+    s.parse().ok()
+}
+
+fn parse_list(s: &str) -> Option<Vec<f64>> {
+    s.split(';').map(|v| v.trim().parse().ok()).collect()
+}
+
+fn parse_numbers(s: &str) -> Option<Vec<f64>> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse().ok())
+        .collect()
+}
+
+// Returns the arguments of a function value like `steps(4, end)`.
+fn function_args<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    s.strip_prefix(name)?
+        .trim_start()
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+// Splits a value into its numbers and the text between them.
+fn tokenize(s: &str) -> (Vec<&str>, Vec<f64>) {
+    let s = s.trim();
+    let bytes = s.as_bytes();
+    let mut text = Vec::new();
+    let mut numbers = Vec::new();
+    let (mut pos, mut i) = (0, 0);
+    while i < bytes.len() {
+        let c = bytes[i];
+        let starts_number = c.is_ascii_digit()
+            || ((c == b'-' || c == b'+' || c == b'.')
+                && bytes
+                    .get(i + 1)
+                    .map(|n| n.is_ascii_digit())
+                    .unwrap_or(false));
+
+        // Digits that are part of a word, e.g. `h1`, are not numbers.
+        let in_word = i > 0 && (bytes[i - 1].is_ascii_alphabetic() || bytes[i - 1] == b'#');
+        if !starts_number || in_word {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        i += 1;
+        while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+            if bytes[i] == b'.' && s[start..i].contains('.') {
+                break;
+            }
+
+            i += 1;
+        }
+
+        if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+            let mut j = i + 1;
+            if j < bytes.len() && (bytes[j] == b'-' || bytes[j] == b'+') {
+                j += 1;
+            }
+
+            if j < bytes.len() && bytes[j].is_ascii_digit() {
+                i = j;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+        }
+
+        match s[start..i].parse() {
+            Ok(n) => {
+                text.push(&s[pos..start]);
+                numbers.push(n);
+                pos = i;
+            }
+
+            Err(_) => continue,
+        }
+    }
+
+    text.push(&s[pos..]);
+    (text, numbers)
+}
+
+fn format_number(n: f64) -> String {
+    let s = format!("{:.4}", n);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" {
+        String::from("0")
+    } else {
+        String::from(s)
+    }
+}
+
+// Rewrites hex colors as `rgb()` so that they can be interpolated.
+fn normalize_color(s: &str) -> String {
+    let s = s.trim();
+    let hex = match s.strip_prefix('#') {
+        Some(h) if h.chars().all(|c| c.is_ascii_hexdigit()) => h,
+        _ => return String::from(s),
+    };
+
+    let channel = |h: &str| u8::from_str_radix(h, 16).unwrap();
+    let (r, g, b) = match hex.len() {
+        3 => (
+            channel(&hex[0..1]) * 17,
+            channel(&hex[1..2]) * 17,
+            channel(&hex[2..3]) * 17,
+        ),
+        6 => (
+            channel(&hex[0..2]),
+            channel(&hex[2..4]),
+            channel(&hex[4..6]),
+        ),
+        _ => return String::from(s),
+    };
+
+    format!("rgb({},{},{})", r, g, b)
+}
+
+// Interpolates between two values with the same structure, e.g. lengths,
+// colors, number lists or path data with the same commands. Other values
+// switch half way through.
+fn interpolate(a: &str, b: &str, t: f64) -> String {
+    let (a, b) = (normalize_color(a), normalize_color(b));
+    let (a_text, a_numbers) = tokenize(&a);
+    let (b_text, b_numbers) = tokenize(&b);
+    if a_text != b_text || a_numbers.len() != b_numbers.len() {
+        return if t < 0.5 { a } else { b };
+    }
+
+    let is_color = a.starts_with("rgb(");
+    let mut s = String::from(a_text[0]);
+    for (i, (x, y)) in a_numbers.iter().zip(b_numbers.iter()).enumerate() {
+        let n = x + (y - x) * t;
+        if is_color {
+            s.push_str(&format!("{}", n.round().clamp(0., 255.)));
+        } else {
+            s.push_str(&format_number(n));
+        }
+
+        s.push_str(a_text[i + 1]);
+    }
+
+    s
+}
+
+// Adds `by` to `from` for by animations, both must have the same structure.
+fn add(from: &str, by: &str) -> Option<String> {
+    let (from_text, from_numbers) = tokenize(from);
+    let (_, by_numbers) = tokenize(by);
+    if from_numbers.len() != by_numbers.len() || from_numbers.is_empty() {
+        return None;
+    }
+
+    let mut s = String::from(from_text[0]);
+    for (i, (x, y)) in from_numbers.iter().zip(by_numbers.iter()).enumerate() {
+        s.push_str(&format_number(x + y));
+        s.push_str(from_text[i + 1]);
+    }
+
+    Some(s)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+}
+
+// Returns the start tag of the element at the given position.
+fn start_tag(svg: &str, start: usize) -> &str {
+    let mut quote = None;
+    for (i, c) in svg[start..].char_indices() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('>', None) => return &svg[start..=start + i],
+            _ => {}
+        }
+    }
+
+    &svg[start..]
+}
+
+// Splits a start tag into its qualified name, its attributes and whether
+// the element is self closing.
+#[allow(clippy::type_complexity)]
+fn parse_start_tag(tag: &str) -> Option<(String, Vec<(String, String, char)>, bool)> {
+    let inner = tag.strip_prefix('<')?.strip_suffix('>')?;
+    let (inner, self_closing) = match inner.strip_suffix('/') {
+        Some(i) => (i, true),
+        None => (inner, false),
+    };
+
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let name = String::from(&inner[..name_end]);
+
+    let mut attrs = Vec::new();
+    let mut rest = inner[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let attr = rest[..eq].trim();
+        let after = rest[eq + 1..].trim_start();
+        let quote = after.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let end = after[1..].find(quote)? + 1;
+        attrs.push((String::from(attr), String::from(&after[1..end]), quote));
+        rest = after[end + 1..].trim_start();
+    }
+
+    Some((name, attrs, self_closing))
+}
+
+// The offset, declarations and timing function of a keyframe.
+type Keyframe = (f64, Vec<(String, String)>, Option<Easing>);
+
+struct Keyframes {
+    name: String,
+    frames: Vec<Keyframe>,
+}
+
+struct Rule {
+    selectors: Vec<String>,
+    declarations: Vec<(String, String)>,
+}
+
+// Parses the keyframes and the rules with animation properties of a
+// style sheet. Only simple selectors are supported.
+fn parse_css(css: &str) -> (Vec<Keyframes>, Vec<Rule>) {
+    let css = strip_comments(css);
+    let mut keyframes = Vec::new();
+    let mut rules = Vec::new();
+    let mut rest = css.as_str();
+    while let Some(open) = rest.find('{') {
+        let prelude = rest[..open].trim();
+        let close = match block_end(rest, open) {
+            Some(c) => c,
+            None => break,
+        };
+
+        let block = &rest[open + 1..close];
+        rest = &rest[close + 1..];
+        if let Some(name) = prelude
+            .strip_prefix("@keyframes")
+            .or_else(|| prelude.strip_prefix("@-webkit-keyframes"))
+        {
+            keyframes.push(Keyframes {
+                name: String::from(name.trim().trim_matches('"')),
+                frames: parse_keyframes(block),
+            });
+        } else if !prelude.starts_with('@') {
+            let declarations = parse_declarations(block);
+            if declarations.iter().any(|(k, _)| k.starts_with("animation")) {
+                rules.push(Rule {
+                    selectors: prelude.split(',').map(|s| String::from(s.trim())).collect(),
+                    declarations,
+                });
+            }
+        }
+    }
+
+    (keyframes, rules)
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut s = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        s.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+
+    s.push_str(rest);
+    s
+}
+
+// Returns the position of the brace closing the block opened at `open`.
+fn block_end(s: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn parse_keyframes(block: &str) -> Vec<Keyframe> {
+    let mut frames = Vec::new();
+    let mut rest = block;
+    while let Some(open) = rest.find('{') {
+        let selectors = &rest[..open];
+        let close = match rest[open..].find('}') {
+            Some(c) => open + c,
+            None => break,
+        };
+
+        let mut declarations = parse_declarations(&rest[open + 1..close]);
+        rest = &rest[close + 1..];
+
+        let easing = declarations
+            .iter()
+            .position(|(k, _)| k == "animation-timing-function")
+            .map(|i| declarations.remove(i).1)
+            .and_then(|e| Easing::parse(&e));
+
+        for selector in selectors.split(',') {
+            let offset = match selector.trim() {
+                "from" => Some(0.),
+                "to" => Some(1.),
+                s => s
+                    .strip_suffix('%')
+                    .and_then(|p| p.trim().parse::<f64>().ok())
+                    .map(|p| p / 100.),
+            };
+
+            if let Some(offset) = offset {
+                frames.push((offset, declarations.clone(), easing));
+            }
+        }
+    }
+
+    frames.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    frames
+}
+
+fn parse_declarations(block: &str) -> Vec<(String, String)> {
+    split_top_level(block, ';')
+        .into_iter()
+        .filter_map(|d| {
+            let colon = d.find(':')?;
+            let value = d[colon + 1..].trim().trim_end_matches("!important").trim();
+            Some((String::from(d[..colon].trim()), String::from(value)))
+        })
+        .collect()
+}
+
+// Splits at separators that are not within parentheses.
+fn split_top_level(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if depth == 0 && (c == sep || (sep == ' ' && c.is_whitespace())) => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(&s[start..]);
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn matches_selector(node: Node, selector: &str) -> bool {
+    if selector == "*" {
+        return true;
+    }
+
+    if selector.contains(|c: char| c.is_whitespace() || ">+~[:".contains(c)) {
+        return false;
+    }
+
+    let tag_end = selector.find(&['#', '.'][..]).unwrap_or(selector.len());
+    let tag = &selector[..tag_end];
+    if !tag.is_empty() && tag != "*" && tag != node.tag_name().name() {
+        return false;
+    }
+
+    let classes: Vec<&str> = node
+        .attribute("class")
+        .map(|c| c.split_whitespace().collect())
+        .unwrap_or_default();
+
+    let mut rest = &selector[tag_end..];
+    while !rest.is_empty() {
+        let end = rest[1..]
+            .find(&['#', '.'][..])
+            .map(|e| e + 1)
+            .unwrap_or_else(|| rest.len());
+
+        let matched = match &rest[..1] {
+            "#" => node.attribute("id") == Some(&rest[1..end]),
+            _ => classes.contains(&&rest[1..end]),
+        };
+
+        if !matched {
+            return false;
+        }
+
+        rest = &rest[end..];
+    }
+
+    true
+}
+
+// The CSS animation properties of an element.
+struct CssAnimation<'a> {
+    name: &'a str,
+    duration: f64,
+    delay: f64,
+    iterations: f64,
+    direction: Direction,
+    fill: &'a str,
+    easing: Easing,
+}
+
+impl<'a> Default for CssAnimation<'a> {
+    fn default() -> Self {
+        CssAnimation {
+            name: "none",
+            duration: 0.,
+            delay: 0.,
+            iterations: 1.,
+            direction: Direction::Normal,
+            fill: "none",
+            easing: Easing::CubicBezier(0.25, 0.1, 0.25, 1.),
+        }
+    }
+}
+
+fn parse_direction(s: &str) -> Option<Direction> {
+    match s {
+        "normal" => Some(Direction::Normal),
+        "reverse" => Some(Direction::Reverse),
+        "alternate" => Some(Direction::Alternate),
+        "alternate-reverse" => Some(Direction::AlternateReverse),
+        _ => None,
+    }
+}
+
+fn parse_css_time(s: &str) -> Option<f64> {
+    if let Some(ms) = s.strip_suffix("ms") {
+        ms.parse::<f64>().ok().map(|ms| ms / 1000.)
+    } else {
+        s.strip_suffix('s')?.parse().ok()
+    }
+}
+
+fn parse_iterations(s: &str) -> Option<f64> {
+    if s == "infinite" {
+        Some(f64::INFINITY)
+    } else {
+        s.parse().ok()
+    }
+}
+
+// Collects the CSS animations of an element from the style sheet rules
+// and its style attribute.
+fn css_animations(node: Node, keyframes: &[Keyframes], rules: &[Rule]) -> Vec<Animation> {
+    let style = node.attribute("style").map(parse_declarations);
+    let declarations = rules
+        .iter()
+        .filter(|r| r.selectors.iter().any(|s| matches_selector(node, s)))
+        .flat_map(|r| r.declarations.iter().cloned())
+        .chain(style.into_iter().flatten())
+        .filter(|(k, _)| k.starts_with("animation"));
+
+    let mut specs: Vec<CssAnimation> = Vec::new();
+    let declarations: Vec<(String, String)> = declarations.collect();
+    for (key, value) in &declarations {
+        let values = split_top_level(value, ',');
+        if key == "animation" {
+            specs.clear();
+        }
+
+        for (i, v) in values.iter().enumerate() {
+            if specs.len() <= i {
+                specs.push(CssAnimation::default());
+            }
+
+            let a = &mut specs[i];
+            match key.as_str() {
+                "animation" => {
+                    let mut times = 0;
+                    for token in split_top_level(v, ' ') {
+                        if let Some(t) = parse_css_time(token) {
+                            if times == 0 {
+                                a.duration = t;
+                            } else {
+                                a.delay = t;
+                            }
+
+                            times += 1;
+                        } else if let Some(n) = parse_iterations(token) {
+                            a.iterations = n;
+                        } else if let Some(d) = parse_direction(token) {
+                            a.direction = d;
+                        } else if let Some(e) = Easing::parse(token) {
+                            a.easing = e;
+                        } else if ["forwards", "backwards", "both"].contains(&token) {
+                            a.fill = token;
+                        } else if !["running", "paused", "none"].contains(&token) {
+                            a.name = token;
+                        }
+                    }
+                }
+
+                "animation-name" => a.name = v,
+                "animation-duration" => a.duration = parse_css_time(v).unwrap_or(0.),
+                "animation-delay" => a.delay = parse_css_time(v).unwrap_or(0.),
+                "animation-iteration-count" => a.iterations = parse_iterations(v).unwrap_or(1.),
+                "animation-direction" => {
+                    a.direction = parse_direction(v).unwrap_or(Direction::Normal)
+                }
+                "animation-fill-mode" => a.fill = v,
+                "animation-timing-function" => a.easing = Easing::parse(v).unwrap_or(a.easing),
+                _ => {}
+            }
+        }
+    }
+
+    let mut animations = Vec::new();
+    for spec in specs {
+        let kf = match keyframes.iter().rev().find(|k| k.name == spec.name) {
+            Some(kf) => kf,
+            None => continue,
+        };
+
+        if spec.duration <= 0. || spec.iterations <= 0. {
+            continue;
+        }
+
+        let timing = Timing {
+            begin: spec.delay,
+            dur: spec.duration,
+            repeat: spec.iterations,
+            freeze: spec.fill == "forwards" || spec.fill == "both",
+            backwards: spec.fill == "backwards" || spec.fill == "both",
+            direction: spec.direction,
+        };
+
+        let mut properties: Vec<&str> = Vec::new();
+        for (_, declarations, _) in &kf.frames {
+            for (k, _) in declarations {
+                if !properties.contains(&k.as_str()) {
+                    properties.push(k);
+                }
+            }
+        }
+
+        for property in properties {
+            let mut frames: Vec<(f64, String, Easing)> = kf
+                .frames
+                .iter()
+                .filter_map(|(offset, declarations, easing)| {
+                    let (_, value) = declarations.iter().rev().find(|(k, _)| k == property)?;
+                    Some((*offset, value.clone(), easing.unwrap_or(spec.easing)))
+                })
+                .collect();
+
+            // Missing start and end keyframes use the value of the element.
+            let base = node.attribute(property).map(String::from).or_else(|| {
+                if property == "transform" {
+                    Some(identity_transform(&frames[0].1))
+                } else {
+                    None
+                }
+            });
+            if frames[0].0 > 0. {
+                let value = base.clone().unwrap_or_else(|| frames[0].1.clone());
+                frames.insert(0, (0., value, spec.easing));
+            }
+
+            if frames[frames.len() - 1].0 < 1. {
+                let value = base.unwrap_or_else(|| frames[frames.len() - 1].1.clone());
+                frames.push((1., value, spec.easing));
+            }
+
+            let (property, values) = if property == "transform" {
+                let values = frames.iter().map(|f| css_transform(&f.1)).collect();
+                (Property::Attribute(String::from(property)), values)
+            } else {
+                let values = frames.iter().map(|f| f.1.clone()).collect();
+                (Property::Style(String::from(property)), values)
+            };
+
+            animations.push(Animation {
+                property,
+                timing,
+                discrete: false,
+                values,
+                key_times: frames.iter().map(|f| f.0).collect(),
+                easings: frames.iter().map(|f| f.2).collect(),
+                transform: None,
+                additive: false,
+            });
+        }
+    }
+
+    animations
+}
+
+// Returns a transform with the same functions as the given one that
+// leaves the element as is.
+fn identity_transform(s: &str) -> String {
+    let mut out = Vec::new();
+    for f in split_top_level(s, ' ') {
+        let (text, numbers) = tokenize(f);
+        let n = if f.starts_with("scale") { "1" } else { "0" };
+        let mut f = String::from(text[0]);
+        for t in &text[1..=numbers.len()] {
+            f.push_str(n);
+            f.push_str(t);
+        }
+
+        out.push(f);
+    }
+
+    out.join(" ")
+}
+
+// Converts a CSS transform to the SVG transform syntax.
+fn css_transform(s: &str) -> String {
+    let mut out = Vec::new();
+    for f in split_top_level(s, ' ') {
+        let open = match f.find('(') {
+            Some(o) => o,
+            None => continue,
+        };
+
+        let name = &f[..open];
+        let args: Vec<String> = f[open + 1..f.len() - 1]
+            .split(',')
+            .map(|a| {
+                let a = a.trim();
+                String::from(
+                    a.strip_suffix("px")
+                        .or_else(|| a.strip_suffix("deg"))
+                        .unwrap_or(a),
+                )
+            })
+            .collect();
+
+        let args = args.join(" ");
+        out.push(match name {
+            "translateX" => format!("translate({} 0)", args),
+            "translateY" => format!("translate(0 {})", args),
+            "scaleX" => format!("scale({} 1)", args),
+            "scaleY" => format!("scale(1 {})", args),
+            _ => format!("{}({})", name, args),
+        });
+    }
+
+    out.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn easing() {
+        assert_eq!(0.5, Easing::Linear.apply(0.5));
+        let ease_in_out = Easing::parse("ease-in-out").unwrap();
+        assert!(ease_in_out.apply(0.25) < 0.25);
+        assert!((ease_in_out.apply(0.5) - 0.5).abs() < 1e-6);
+        assert!(ease_in_out.apply(0.75) > 0.75);
+        assert_eq!(
+            Some(Easing::CubicBezier(0.1, 0.7, 1., 0.1)),
+            Easing::parse("cubic-bezier(0.1, 0.7, 1.0, 0.1)")
+        );
+        assert_eq!(0.5, Easing::parse("steps(4)").unwrap().apply(0.6));
+        assert_eq!(0.75, Easing::parse("steps(4, start)").unwrap().apply(0.6));
+        assert_eq!(None, Easing::parse("bounce"));
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!("5", interpolate("0", "10", 0.5));
+        assert_eq!(
+            "translate(5 2.5)",
+            interpolate("translate(0 0)", "translate(10 5)", 0.5)
+        );
+        assert_eq!("rgb(128,0,128)", interpolate("#f00", "#0000ff", 0.5));
+        assert_eq!(
+            "M 5 5 L 15 5",
+            interpolate("M 0 0 L 10 0", "M 10 10 L 20 10", 0.5)
+        );
+        assert_eq!("red", interpolate("red", "blue", 0.4));
+        assert_eq!("blue", interpolate("red", "blue", 0.6));
+        assert_eq!("1e-3", interpolate("1e-3", "x", 0.));
+        assert_eq!(Some(String::from("15 25")), add("10 20", "5 5"));
+        assert_eq!(Some(1.5), parse_clock("1.5s"));
+        assert_eq!(Some(90.), parse_clock("01:30"));
+        assert_eq!(Some(0.25), parse_clock("250ms"));
+        assert_eq!(Some(2.), parse_clock("2"));
+        assert_eq!(None, parse_clock("click"));
+    }
+
+    #[test]
+    fn smil() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink">
+<rect id="r" x='0' width="10" height="10" style="fill:red">
+<animate attributeName="x" from="0" to="100" begin="1s" dur="2s" fill="freeze"/>
+<animate attributeName="opacity" values="0;1;0" dur="1s" repeatCount="indefinite"/>
+</rect>
+<set xlink:href="#r" attributeName="height" to="20" begin="2s"/>
+<g><animateTransform attributeName="transform" type="rotate" from="0 5 5" to="90 5 5" dur="1s"/></g>
+</svg>"##;
+
+        let anims = Animations::parse(svg).unwrap().unwrap();
+        let at = |t| {
+            let svg = anims.render(t);
+            let doc = Document::parse(&svg).unwrap();
+            let rect = doc.descendants().find(|n| n.has_tag_name("rect")).unwrap();
+            let g = doc.descendants().find(|n| n.has_tag_name("g")).unwrap();
+            (
+                rect.attribute("x").map(String::from),
+                rect.attribute("height").map(String::from),
+                rect.attribute("style").map(String::from),
+                g.attribute("transform").map(String::from),
+            )
+        };
+
+        let (x, height, style, transform) = at(0.5);
+        assert_eq!(Some("0"), x.as_deref());
+        assert_eq!(Some("10"), height.as_deref());
+        assert_eq!(Some("fill:red;opacity:1"), style.as_deref());
+        assert_eq!(Some("rotate(45 5 5)"), transform.as_deref());
+
+        let (x, height, _, transform) = at(2.);
+        assert_eq!(Some("50"), x.as_deref());
+        assert_eq!(Some("20"), height.as_deref());
+        assert_eq!(None, transform);
+
+        let (x, _, style, _) = at(10.);
+        assert_eq!(Some("100"), x.as_deref());
+        assert_eq!(Some("fill:red;opacity:0"), style.as_deref());
+
+        assert_eq!(f64::INFINITY, anims.end());
+        assert!(Animations::parse("<svg/>").unwrap().is_none());
+    }
+
+    #[test]
+    fn end() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg">
+<rect width="10" height="10">
+<animate attributeName="x" from="0" to="100" begin="1s" dur="1s" repeatCount="2" fill="freeze"/>
+<set attributeName="height" to="20" begin="2s"/>
+<animate attributeName="width" from="0" to="10" dur="2s"/>
+</rect>
+</svg>"#;
+
+        let anims = Animations::parse(svg).unwrap().unwrap();
+        assert_eq!(3., anims.end());
+        assert_ne!(anims.render(2.5), anims.render(3.));
+        assert_eq!(anims.render(3.), anims.render(100.));
+    }
+
+    #[test]
+    fn css() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg">
+<style>
+/* fade in */
+@keyframes fade { from { opacity: 0 } to { opacity: 1 } }
+@keyframes slide { 50% { transform: translateX(100px) } }
+.logo { animation: fade 2s linear 1s forwards }
+</style>
+<rect class="logo big" width="10" height="10"/>
+<circle r="5" style="animation: slide 1s linear infinite alternate"/>
+</svg>"#;
+
+        let anims = Animations::parse(svg).unwrap().unwrap();
+        let at = |t| {
+            let svg = anims.render(t);
+            let doc = Document::parse(&svg).unwrap();
+            let rect = doc.descendants().find(|n| n.has_tag_name("rect")).unwrap();
+            let circle = doc
+                .descendants()
+                .find(|n| n.has_tag_name("circle"))
+                .unwrap();
+            (
+                rect.attribute("style").map(String::from),
+                circle.attribute("transform").map(String::from),
+            )
+        };
+
+        assert_eq!((None, Some(String::from("translate(0 0)"))), at(0.));
+        let (style, transform) = at(2.);
+        assert_eq!(Some("opacity:0.5"), style.as_deref());
+        assert_eq!(Some("translate(0 0)"), transform.as_deref());
+        let (style, transform) = at(3.75);
+        assert_eq!(Some("opacity:1"), style.as_deref());
+        assert_eq!(Some("translate(50 0)"), transform.as_deref());
+    }
+}
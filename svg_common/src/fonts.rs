@@ -0,0 +1,655 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Font configuration for SVG text. usvg 0.9 has no font family fallbacks,
+// so they are applied by rewriting the `font-family` properties of the SVG
+// source. It loads fonts from the system font directories and
+// `$HOME/.local/share/fonts` only, other font directories and files are
+// linked from a directory that HOME is pointed at.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::os::unix;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use roxmltree::{Document, Node};
+
+const GENERIC_FAMILIES: &[&str] = &["serif", "sans-serif", "cursive", "fantasy", "monospace"];
+
+// The family usvg uses for text without one.
+const USVG_DEFAULT_FAMILY: &str = "Times New Roman";
+
+lazy_static::lazy_static! {
+    static ref CSS_FAMILY: regex::Regex = regex::Regex::new(
+        r#"font-family\s*:\s*((?:&[a-z]+;|[^;}"'<>&]|"[^"<>]*"|'[^'<>]*')+)"#,
+    )
+    .unwrap();
+
+    // Listed once, every filter instance in strict mode needs them.
+    static ref SYSTEM_FAMILIES: HashSet<String> = SYSTEM_FONT_DIRS
+        .iter()
+        .flat_map(|dir| list_families(Path::new(dir)))
+        .collect();
+
+    static ref FONT_HOME: Mutex<Option<FontHome>> = Mutex::new(None);
+}
+
+// Searched by usvg in addition to `$HOME/.local/share/fonts`.
+const SYSTEM_FONT_DIRS: &[&str] = &["/usr/share/fonts", "/usr/local/share/fonts"];
+
+// The extensions of the font files that usvg loads.
+const FONT_EXTENSIONS: &[&str] = &["ttf", "ttc", "otf", "otc"];
+
+// The directory HOME points at once fonts are loaded from other
+// directories.
+struct FontHome {
+    // `.local/share/fonts` in the directory.
+    fonts: PathBuf,
+    // The fonts directory of the original HOME.
+    user: Option<PathBuf>,
+    linked: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FontConfig {
+    // Used for text without a font family.
+    pub family: Option<String>,
+    // Appended to every font family list.
+    pub fallback: Vec<String>,
+    // Fail if a referenced font family isn't installed.
+    pub strict: bool,
+    // Font directories and files loaded in addition to the installed fonts.
+    pub paths: Vec<PathBuf>,
+}
+
+impl FontConfig {
+    // Handles `font_family`, `font_fallback`, `font_strict`, `font_dir` and
+    // `font_file`, returns false for other keys.
+    pub fn parse_option(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "font_family" => self.family = Some(String::from(value.trim())),
+            "font_fallback" => {
+                self.fallback = value
+                    .split('|')
+                    .map(str::trim)
+                    .filter(|f| !f.is_empty())
+                    .map(String::from)
+                    .collect()
+            }
+
+            "font_strict" => {
+                self.strict = match value {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(format!("invalid font_strict: {}", value)),
+                }
+            }
+
+            "font_dir" | "font_file" => {
+                for path in value.split('|').map(str::trim).filter(|p| !p.is_empty()) {
+                    let path = PathBuf::from(path);
+                    let valid = if key == "font_dir" {
+                        path.is_dir()
+                    } else {
+                        path.is_file() && is_font_file(&path)
+                    };
+
+                    if !valid {
+                        return Err(format!("invalid {}: {}", key, path.display()));
+                    }
+
+                    self.paths.push(path);
+                }
+            }
+
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub fn load(&self) -> Result<Fonts, String> {
+        if !self.paths.is_empty() {
+            link_fonts(&self.paths)?;
+        }
+
+        let installed = if self.strict {
+            Some(Arc::new(installed_families()))
+        } else {
+            None
+        };
+
+        let fonts = Fonts {
+            family: self.family.clone(),
+            fallback: self.fallback.clone(),
+            installed,
+        };
+
+        for family in self.family.iter().chain(self.fallback.iter()) {
+            fonts.check(family)?;
+        }
+
+        Ok(fonts)
+    }
+}
+
+#[derive(Clone)]
+pub struct Fonts {
+    family: Option<String>,
+    fallback: Vec<String>,
+    // Lower case names of the installed font families, only in strict mode.
+    installed: Option<Arc<HashSet<String>>>,
+}
+
+impl Fonts {
+    fn check(&self, family: &str) -> Result<(), String> {
+        match &self.installed {
+            Some(installed)
+                if !GENERIC_FAMILIES.contains(&family)
+                    && !installed.contains(&family.to_lowercase()) =>
+            {
+                Err(format!("font family not found: {}", family))
+            }
+
+            _ => Ok(()),
+        }
+    }
+
+    // Returns the font family list with the fallbacks appended.
+    fn resolve(&self, list: &str) -> Result<String, String> {
+        let families: Vec<&str> = list
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .collect();
+
+        for family in &families {
+            self.check(unquote(family))?;
+        }
+
+        Ok(self.with_fallbacks(families))
+    }
+
+    fn with_fallbacks<'a>(&'a self, mut families: Vec<&'a str>) -> String {
+        for fallback in &self.fallback {
+            if !families
+                .iter()
+                .any(|f| unquote(f).eq_ignore_ascii_case(fallback))
+            {
+                families.push(fallback);
+            }
+        }
+
+        families.join(", ")
+    }
+
+    // Returns the family with the fallbacks for `usvg::Options::font_family`,
+    // which usvg uses for text without a font family and splits like a
+    // `font-family` list.
+    pub fn default_family(&self) -> Option<String> {
+        let family = self.family.as_deref()?;
+        Some(self.with_fallbacks(vec![family]))
+    }
+
+    // Returns the CSS with the fallbacks appended to all font family lists.
+    fn resolve_css(&self, css: &str) -> Result<String, String> {
+        let mut error = None;
+        let css = CSS_FAMILY.replace_all(css, |caps: &regex::Captures| {
+            let list = caps[1].trim_end();
+            let list = self.resolve(list).unwrap_or_else(|e| {
+                error.get_or_insert(e);
+                String::from(list)
+            });
+
+            format!("font-family:{}", list)
+        });
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(css.into_owned()),
+        }
+    }
+
+    // Returns the SVG with the fallbacks appended to all font family lists.
+    // The default family is set with `default_family`.
+    pub fn apply(&self, svg: &str) -> Result<String, String> {
+        if self.fallback.is_empty() && self.installed.is_none() {
+            return Ok(String::from(svg));
+        }
+
+        let doc = Document::parse(svg).map_err(|e| format!("invalid SVG: {}", e))?;
+        let edits = self.edits(svg, doc.root_element())?;
+        if self.family.is_none() && self.installed.is_some() && has_text_without_family(&doc) {
+            self.check(USVG_DEFAULT_FAMILY)?;
+        }
+
+        Ok(splice(svg, 0..svg.len(), &edits))
+    }
+
+    // Returns the source of an element, e.g. shared defs, with the
+    // fallbacks appended to all font family lists.
+    pub fn apply_fallbacks(&self, source: &str, node: Node) -> Result<String, String> {
+        let edits = self.edits(source, node)?;
+        Ok(splice(source, node.range(), &edits))
+    }
+
+    fn edits(&self, source: &str, node: Node) -> Result<Vec<(Range<usize>, String)>, String> {
+        spans(source, node)
+            .into_iter()
+            .map(|span| match span {
+                Span::List(r) => Ok((r.clone(), self.resolve(&source[r])?)),
+                Span::Css(r) => Ok((r.clone(), self.resolve_css(&source[r])?)),
+            })
+            .collect()
+    }
+}
+
+// Where font family lists are found in the source.
+enum Span {
+    // The value of a `font-family` attribute.
+    List(Range<usize>),
+    // The value of a `style` attribute or the content of a `style` element.
+    Css(Range<usize>),
+}
+
+// Returns the spans of an element and its descendants in document order,
+// text content is never rewritten.
+fn spans(source: &str, node: Node) -> Vec<Span> {
+    let mut spans = Vec::new();
+    for n in node.descendants().filter(|n| n.is_element()) {
+        for (name, range) in attribute_values(source, n.range().start) {
+            match name {
+                "font-family" => spans.push(Span::List(range)),
+                "style" => spans.push(Span::Css(range)),
+                _ => {}
+            }
+        }
+
+        if n.tag_name().name() == "style" {
+            if let (Some(first), Some(last)) = (n.first_child(), n.last_child()) {
+                spans.push(Span::Css(first.range().start..last.range().end));
+            }
+        }
+    }
+
+    spans
+}
+
+// Returns the names and the ranges of the raw values of the attributes in
+// the start tag at `start`, which is known to be well-formed.
+fn attribute_values(source: &str, start: usize) -> Vec<(&str, Range<usize>)> {
+    let mut attrs = Vec::new();
+    let tag = &source[start..];
+    let mut pos = tag
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(tag.len());
+
+    loop {
+        pos += tag[pos..].len() - tag[pos..].trim_start().len();
+        let eq = match tag[pos..].find('=') {
+            Some(eq) if !tag[pos..].starts_with(&['/', '>'][..]) => pos + eq,
+            _ => break,
+        };
+
+        let name = tag[pos..eq].trim_end();
+        let value_start = eq + 1 + tag[eq + 1..].find(&['"', '\''][..]).unwrap();
+        let quote = &tag[value_start..=value_start];
+        let value_end = value_start + 1 + tag[value_start + 1..].find(quote).unwrap();
+        attrs.push((name, start + value_start + 1..start + value_end));
+        pos = value_end + 1;
+    }
+
+    attrs
+}
+
+// Returns the source in the range with the edits, in order, applied.
+fn splice(source: &str, range: Range<usize>, edits: &[(Range<usize>, String)]) -> String {
+    let mut s = String::with_capacity(range.len());
+    let mut pos = range.start;
+    for (r, text) in edits {
+        s.push_str(&source[pos..r.start]);
+        s.push_str(text);
+        pos = r.end;
+    }
+
+    s.push_str(&source[pos..range.end]);
+    s
+}
+
+// Whether some text element has no font family set on it or inherited.
+// Style sheets are assumed to set one, since their selectors aren't
+// matched.
+fn has_text_without_family(doc: &Document) -> bool {
+    let style_sheet = doc.descendants().any(|n| {
+        n.tag_name().name() == "style"
+            && n.text().map(|t| t.contains("font-family")).unwrap_or(false)
+    });
+
+    if style_sheet {
+        return false;
+    }
+
+    let has_family = |n: Node| {
+        n.attribute("font-family").is_some()
+            || n.attribute("style")
+                .map(|s| s.contains("font-family"))
+                .unwrap_or(false)
+    };
+
+    doc.descendants()
+        .filter(|n| n.tag_name().name() == "text")
+        .any(|t| !t.ancestors().any(has_family))
+}
+
+// Returns the font families referenced by the SVG, except the generic ones,
+// and usvg's default family if some text has none.
+pub fn families(svg: &str) -> Vec<String> {
+    let doc = match Document::parse(svg) {
+        Ok(doc) => doc,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut lists: Vec<&str> = Vec::new();
+    for span in spans(svg, doc.root_element()) {
+        match span {
+            Span::List(r) => lists.push(&svg[r]),
+            Span::Css(r) => lists.extend(
+                CSS_FAMILY
+                    .captures_iter(&svg[r])
+                    .map(|caps| caps.get(1).unwrap().as_str()),
+            ),
+        }
+    }
+
+    if has_text_without_family(&doc) {
+        lists.push(USVG_DEFAULT_FAMILY);
+    }
+
+    let mut families: Vec<String> = Vec::new();
+    for family in lists
+        .iter()
+        .flat_map(|l| l.split(','))
+        .map(|f| unquote(f.trim()))
+    {
+        if !family.is_empty()
+            && !GENERIC_FAMILIES.contains(&family)
+            && !families.iter().any(|f| f == family)
+        {
+            families.push(String::from(family));
+        }
+    }
+
+    families
+}
+
+fn unquote(family: &str) -> &str {
+    for q in &["\"", "'", "&quot;", "&apos;"] {
+        if let Some(f) = family.strip_prefix(q).and_then(|f| f.strip_suffix(q)) {
+            return f.trim();
+        }
+    }
+
+    family
+}
+
+// Returns the lower case names of the font families usvg loads, from the
+// same directories and with the same font parser.
+pub fn installed_families() -> HashSet<String> {
+    let mut families = SYSTEM_FAMILIES.clone();
+    if let Some(home) = env::var_os("HOME") {
+        families.extend(list_families(&Path::new(&home).join(".local/share/fonts")));
+    }
+
+    families
+}
+
+fn list_families(dir: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut families = Vec::new();
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.is_dir() {
+            families.extend(list_families(&path));
+        } else if path.is_file() && is_font_file(&path) {
+            let data = match fs::read(&path) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+
+            let count = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+            families.extend(
+                (0..count)
+                    .filter_map(|i| ttf_parser::Font::from_data(&data, i)?.family_name())
+                    .map(|f| f.to_lowercase()),
+            );
+        }
+    }
+
+    families
+}
+
+fn is_font_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| FONT_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+// Links the font directories and files from the fonts directory of a
+// directory that HOME is pointed at, together with the user's own fonts.
+// usvg reads HOME every time it loads the fonts.
+fn link_fonts(paths: &[PathBuf]) -> Result<(), String> {
+    let mut font_home = FONT_HOME.lock().unwrap();
+    if font_home.is_none() {
+        let home = env::temp_dir().join(format!("svg-fonts-{}", std::process::id()));
+        let fonts = home.join(".local/share/fonts");
+        fs::create_dir_all(&fonts)
+            .map_err(|e| format!("could not create {}: {}", fonts.display(), e))?;
+
+        let user = env::var_os("HOME").map(|h| Path::new(&h).join(".local/share/fonts"));
+        if let Some(user) = &user {
+            if user.is_dir() {
+                link(user, &fonts.join("user"))?;
+            }
+        }
+
+        env::set_var("HOME", &home);
+        *font_home = Some(FontHome {
+            fonts,
+            user,
+            linked: Vec::new(),
+        });
+    }
+
+    let font_home = font_home.as_mut().unwrap();
+    for path in paths {
+        let path = fs::canonicalize(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if font_home.user.as_ref() == Some(&path) || font_home.linked.contains(&path) {
+            continue;
+        }
+
+        // Files keep their extension, usvg only loads font files.
+        let name = path.file_name().unwrap().to_string_lossy();
+        let name = format!("{}-{}", font_home.linked.len(), name);
+        link(&path, &font_home.fonts.join(name))?;
+        font_home.linked.push(path);
+    }
+
+    Ok(())
+}
+
+fn link(target: &Path, link: &Path) -> Result<(), String> {
+    // Left from an earlier process with the same id.
+    let _ = fs::remove_file(link);
+    unix::fs::symlink(target, link)
+        .map_err(|e| format!("could not link {}: {}", target.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn installed(families: &[&str]) -> Option<Arc<HashSet<String>>> {
+        Some(Arc::new(
+            families.iter().map(|f| f.to_lowercase()).collect(),
+        ))
+    }
+
+    #[test]
+    fn apply() {
+        let fonts = Fonts {
+            family: Some(String::from("Noto Sans")),
+            fallback: vec![String::from("DejaVu Sans")],
+            installed: None,
+        };
+
+        assert_eq!(
+            Some("Noto Sans, DejaVu Sans"),
+            fonts.default_family().as_deref()
+        );
+        assert_eq!(
+            r#"<svg><text font-family="Arial, DejaVu Sans">a</text><text style="font-family:'Open Sans', DejaVu Sans;fill:red">b</text></svg>"#,
+            fonts
+                .apply(r#"<svg><text font-family="Arial">a</text><text style="font-family: 'Open Sans';fill:red">b</text></svg>"#)
+                .unwrap()
+        );
+
+        assert_eq!(
+            r#"<svg font-family='Arial, DejaVu Sans'><style>text{font-family:"Open Sans", DejaVu Sans}</style></svg>"#,
+            fonts
+                .apply(r#"<svg font-family='Arial'><style>text{font-family: "Open Sans"}</style></svg>"#)
+                .unwrap()
+        );
+
+        // Text content is left as is.
+        assert_eq!(
+            r#"<svg font-family="A, DejaVu Sans"><text>font-family: B</text><desc><![CDATA[font-family="C"]]></desc></svg>"#,
+            fonts
+                .apply(r#"<svg font-family="A"><text>font-family: B</text><desc><![CDATA[font-family="C"]]></desc></svg>"#)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn apply_fallbacks() {
+        let fonts = Fonts {
+            family: None,
+            fallback: vec![String::from("B")],
+            installed: None,
+        };
+
+        let source = r#"<transitions><defs><text font-family="A">font-family="A"</text></defs></transitions>"#;
+        let doc = Document::parse(source).unwrap();
+        let defs = doc.root_element().first_child().unwrap();
+        assert_eq!(
+            r#"<defs><text font-family="A, B">font-family="A"</text></defs>"#,
+            fonts.apply_fallbacks(source, defs).unwrap()
+        );
+    }
+
+    #[test]
+    fn strict() {
+        let fonts = Fonts {
+            family: None,
+            fallback: vec![],
+            installed: installed(&["Arial"]),
+        };
+
+        assert!(fonts.apply(r#"<svg font-family="Arial, serif"/>"#).is_ok());
+        assert_eq!(
+            Err(String::from("font family not found: Comic Sans")),
+            fonts.apply(r#"<svg><text style="font-family:Arial,'Comic Sans'">a</text></svg>"#)
+        );
+
+        // Text without a family uses the default family of usvg.
+        assert_eq!(
+            Err(String::from("font family not found: Times New Roman")),
+            fonts.apply(r#"<svg><g font-family="Arial"><text>a</text></g><text>b</text></svg>"#)
+        );
+
+        let fonts = Fonts {
+            installed: installed(&["Arial", "Times New Roman"]),
+            ..fonts
+        };
+        assert!(fonts.apply(r#"<svg><text>b</text></svg>"#).is_ok());
+    }
+
+    #[test]
+    fn families() {
+        assert_eq!(
+            vec!["Arial", "Comic Sans", "Open Sans"],
+            super::families(
+                r#"<svg font-family="Arial, serif"><text style="font-family:'Comic Sans',Arial">a</text><style>text{font-family: "Open Sans"}</style></svg>"#
+            )
+        );
+
+        assert_eq!(
+            vec!["Arial", "Times New Roman"],
+            super::families(r#"<svg><text font-family="Arial">a</text><text>b</text></svg>"#)
+        );
+    }
+
+    #[test]
+    fn options() {
+        let mut config = FontConfig::default();
+        assert_eq!(Ok(true), config.parse_option("font_fallback", "A | B C|"));
+        assert_eq!(vec!["A", "B C"], config.fallback);
+        assert_eq!(Ok(false), config.parse_option("svg", "a.svg"));
+
+        let dir = env::temp_dir();
+        assert_eq!(
+            Ok(true),
+            config.parse_option("font_dir", dir.to_str().unwrap())
+        );
+        assert_eq!(vec![dir.clone()], config.paths);
+        assert!(config.parse_option("font_dir", "no-such-dir").is_err());
+        assert!(config.parse_option("font_file", "Cargo.toml").is_err());
+    }
+
+    #[test]
+    fn font_dir() {
+        // Any installed font will do.
+        let font = SYSTEM_FONT_DIRS
+            .iter()
+            .filter_map(|dir| find_font(Path::new(dir)))
+            .next();
+        let font = match font {
+            Some(font) => font,
+            None => return,
+        };
+
+        let dir = env::temp_dir().join(format!("svg-fonts-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy(&font, dir.join(font.file_name().unwrap())).unwrap();
+        let families = list_families(&dir);
+        assert!(!families.is_empty());
+
+        let mut config = FontConfig::default();
+        config
+            .parse_option("font_dir", dir.to_str().unwrap())
+            .unwrap();
+        config.load().unwrap();
+
+        // Where usvg looks for the user's fonts.
+        let home = Path::new(&env::var_os("HOME").unwrap()).join(".local/share/fonts");
+        let linked = list_families(&home);
+        assert!(families.iter().all(|f| linked.contains(f)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn find_font(dir: &Path) -> Option<PathBuf> {
+        fs::read_dir(dir)
+            .ok()?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter_map(|p| if p.is_dir() { find_font(&p) } else { Some(p) })
+            .find(|p| is_font_file(p))
+    }
+}
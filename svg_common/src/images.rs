@@ -0,0 +1,100 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// usvg silently drops `<image>` elements it can't load, so the image
+// references are checked up front to be able to warn about them.
+
+use std::fs;
+use std::path::Path;
+
+use roxmltree::Document;
+
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+// Returns a warning for every image in the SVG that can't be loaded.
+// Relative paths are resolved against the directory of `svg_path`.
+pub fn check(svg: &str, svg_path: Option<&Path>) -> Vec<String> {
+    if !svg.contains("<image") {
+        return Vec::new();
+    }
+
+    let doc = match Document::parse(svg) {
+        Ok(doc) => doc,
+        Err(_) => return Vec::new(),
+    };
+
+    let dir = svg_path
+        .and_then(Path::parent)
+        .unwrap_or_else(|| Path::new(""));
+    let mut warnings = Vec::new();
+    for node in doc.descendants().filter(|n| n.has_tag_name("image")) {
+        let href = match node
+            .attribute((XLINK_NS, "href"))
+            .or_else(|| node.attribute("href"))
+        {
+            Some(href) => href.trim(),
+            None => continue,
+        };
+
+        if let Err(e) = check_href(href, dir) {
+            let href: String = href.chars().take(40).collect();
+            warnings.push(format!("could not load image {}: {}", href, e));
+        }
+    }
+
+    warnings
+}
+
+fn check_href(href: &str, dir: &Path) -> Result<(), String> {
+    let data = if let Some(uri) = href.strip_prefix("data:") {
+        let comma = uri.find(',').ok_or("invalid data URI")?;
+        if !uri[..comma].ends_with(";base64") {
+            return Err(String::from("only base64 data URIs are supported"));
+        }
+
+        let data: String = uri[comma + 1..]
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        base64::decode(&data).map_err(|e| e.to_string())?
+    } else {
+        let path = href.strip_prefix("file://").unwrap_or(href);
+        if path.contains("://") {
+            return Err(String::from("remote images are not supported"));
+        }
+
+        fs::read(dir.join(path)).map_err(|e| e.to_string())?
+    };
+
+    if data.starts_with(b"\x89PNG")
+        || data.starts_with(b"\xff\xd8\xff")
+        || data.starts_with(b"\x1f\x8b")
+        || data.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'<')
+    {
+        Ok(())
+    } else {
+        Err(String::from("unsupported format, use PNG, JPEG or SVG"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink">
+<image href="data:image/png;base64,iVBORw0KGgo="/>
+<image xlink:href="data:image/gif;base64,R0lGODlh"/>
+<image href="http://example.com/a.png"/>
+<image href="missing.png"/>
+</svg>"#;
+
+        let warnings = check(svg, Some(Path::new("/nonexistent/a.svg")));
+        assert_eq!(3, warnings.len());
+        assert!(warnings[0].contains("unsupported format"));
+        assert!(warnings[1].contains("remote images"));
+        assert!(warnings[2].starts_with("could not load image missing.png"));
+    }
+}
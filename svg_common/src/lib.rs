@@ -0,0 +1,11 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// SVG handling shared by the svg and tsvg filters.
+
+pub mod anim;
+
+pub mod fonts;
+
+pub mod images;
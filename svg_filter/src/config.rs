@@ -0,0 +1,200 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::error::Error;
+use std::fmt;
+
+use svg_common::fonts::FontConfig;
+
+use crate::placement::{Anchor, Length, Placement};
+use crate::window::{parse_time, read_intervals, Interval, Window};
+
+pub(crate) struct LayerConfig {
+    pub(crate) placement: Option<Placement>,
+    pub(crate) opacity: f64,
+    pub(crate) window: Option<Window>,
+    pub(crate) fonts: FontConfig,
+    pub(crate) svg: SvgSource,
+}
+
+pub(crate) enum SvgSource {
+    Path(String),
+    Data(String),
+}
+
+impl fmt::Display for SvgSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SvgSource::Path(path) => write!(f, "{}", path),
+            SvgSource::Data(_) => write!(f, "<inline data>"),
+        }
+    }
+}
+
+const DATA_URI_PREFIX: &str = "data:image/svg+xml;base64,";
+
+// Parses a config with one or more layers separated by `;`, rendered in
+// the given order.
+pub(crate) fn parse(s: &str) -> Result<Vec<LayerConfig>, Box<dyn Error>> {
+    let mut layers = Vec::new();
+    let mut start = 0;
+    for (i, _) in s.match_indices(';') {
+        // Not a layer separator but part of a data URI.
+        if s[i + 1..].starts_with("base64,") {
+            continue;
+        }
+
+        layers.push(parse_layer(&s[start..i])?);
+        start = i + 1;
+    }
+
+    layers.push(parse_layer(&s[start..])?);
+    Ok(layers)
+}
+
+// Parses the options for one layer, `[key=value,...]svg=path` or
+// `[key=value,...]svg_data=base64`.
+fn parse_layer(s: &str) -> Result<LayerConfig, Box<dyn Error>> {
+    let regex = regex::Regex::new(r"^((?:[a-z_]+=[^,]*,)*)(svg|svg_data)=(.*)$")?;
+    let captures = regex
+        .captures(s)
+        .ok_or("invalid option, use: [key=value,...]svg=path|svg_data=base64")?;
+
+    let mut placement: Option<Placement> = None;
+    let mut opacity = 1.;
+    let (mut start, mut end, mut intervals) = (None, None, None);
+    let (mut fade_in, mut fade_out) = (0., 0.);
+    let mut fonts = FontConfig::default();
+    for kv in captures.get(1).unwrap().as_str().split_terminator(',') {
+        let mut kv = kv.splitn(2, '=');
+        let (key, value) = (kv.next().unwrap(), kv.next().unwrap());
+        match key {
+            "opacity" => {
+                opacity = value.parse()?;
+                if !(0. ..=1.).contains(&opacity) {
+                    return Err(format!("opacity out of range: {}", value).into());
+                }
+            }
+
+            "start" => start = Some(parse_time(value)?),
+            "end" => end = Some(parse_time(value)?),
+            "fade_in" => fade_in = parse_time(value)?,
+            "fade_out" => fade_out = parse_time(value)?,
+            "intervals" => intervals = Some(read_intervals(value)?),
+            _ if fonts.parse_option(key, value)? => {}
+            _ => {
+                let p = placement.get_or_insert_with(Placement::default);
+                match key {
+                    "anchor" => p.anchor = Anchor::parse(value)?,
+                    "margin_x" => p.margin_x = Length::parse(value)?,
+                    "margin_y" => p.margin_y = Length::parse(value)?,
+                    "scale" => p.scale = Some(value.parse()?),
+                    _ => return Err(format!("unknown option: {}", key).into()),
+                }
+            }
+        }
+    }
+
+    let intervals = match (intervals, start, end) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+            return Err("intervals can't be combined with start/end".into());
+        }
+
+        (Some(i), _, _) => Some(i),
+        (None, None, None) => None,
+        (None, start, end) => {
+            let start = start.unwrap_or(0.);
+            if end.map(|e| e <= start).unwrap_or(false) {
+                return Err("end must be after start".into());
+            }
+
+            Some(vec![Interval { start, end }])
+        }
+    };
+
+    let window = match intervals {
+        Some(intervals) => Some(Window {
+            intervals,
+            fade_in,
+            fade_out,
+        }),
+
+        None if fade_in > 0. || fade_out > 0. => {
+            return Err("fade_in/fade_out requires start, end or intervals".into());
+        }
+
+        None => None,
+    };
+
+    let value = captures.get(3).unwrap().as_str();
+    let svg = if captures.get(2).unwrap().as_str() == "svg_data" {
+        SvgSource::Data(decode_svg_data(value)?)
+    } else if let Some(data) = value.strip_prefix(DATA_URI_PREFIX) {
+        SvgSource::Data(decode_svg_data(data)?)
+    } else {
+        SvgSource::Path(String::from(value))
+    };
+
+    Ok(LayerConfig {
+        placement,
+        opacity,
+        window,
+        fonts,
+        svg,
+    })
+}
+
+fn decode_svg_data(data: &str) -> Result<String, Box<dyn Error>> {
+    let bytes = base64::decode(data)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers() {
+        let layers =
+            parse("svg=a.svg;anchor=top-right,scale=0.1,start=1s,fade_in=500,font_fallback=A|B C,svg=b,c.svg")
+                .unwrap();
+        assert_eq!(2, layers.len());
+
+        assert_eq!("a.svg", layers[0].svg.to_string());
+        assert!(layers[0].placement.is_none());
+        assert!(layers[0].window.is_none());
+        assert!(layers[0].fonts.family.is_none());
+
+        assert_eq!("b,c.svg", layers[1].svg.to_string());
+        let placement = layers[1].placement.unwrap();
+        assert_eq!(Anchor::TopRight, placement.anchor);
+        assert_eq!(Some(0.1), placement.scale);
+        let window = layers[1].window.as_ref().unwrap();
+        assert_eq!(
+            vec![Interval {
+                start: 1000.,
+                end: None
+            }],
+            window.intervals
+        );
+        assert_eq!(500., window.fade_in);
+        assert_eq!(vec!["A", "B C"], layers[1].fonts.fallback);
+
+        let layers =
+            parse("svg=data:image/svg+xml;base64,PHN2Zy8+;opacity=0.5,svg_data=PHN2Zy8+").unwrap();
+        assert_eq!(2, layers.len());
+        for layer in layers {
+            match layer.svg {
+                SvgSource::Data(data) => assert_eq!("<svg/>", data),
+                SvgSource::Path(_) => panic!("expected data"),
+            }
+        }
+
+        assert!(parse("svg=a.svg;").is_err());
+        assert!(parse("svg_data=!!!").is_err());
+        assert!(parse("foo=bar,svg=a.svg").is_err());
+        assert!(parse("start=10,end=5,svg=a.svg").is_err());
+        assert!(parse("font_strict=yes,svg=a.svg").is_err());
+    }
+}
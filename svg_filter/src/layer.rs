@@ -0,0 +1,289 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::error::Error;
+use std::fs;
+
+use resvg::{cairo, usvg};
+use svg_common::anim::Animations;
+use svg_common::images;
+
+use crate::config::{LayerConfig, SvgSource};
+use crate::placement::{Placement, Rect};
+use crate::template::{self, Template};
+use crate::window::Window;
+
+// An SVG tree rendered at its placed size for a specific frame size.
+struct Raster {
+    frame_size: (i32, i32),
+    rect: Rect,
+    surface: cairo::ImageSurface,
+}
+
+impl Raster {
+    fn render(
+        tree: &usvg::Tree,
+        placement: Option<Placement>,
+        width: i32,
+        height: i32,
+    ) -> Result<Raster, Box<dyn Error>> {
+        let rect = match placement {
+            Some(p) => {
+                let size = tree.svg_node().size;
+                p.place((size.width(), size.height()), (width, height))
+            }
+
+            None => Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+        };
+
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, rect.width, rect.height)
+            .map_err(|s| format!("could not create surface: {:?}", s))?;
+        {
+            let cr = cairo::Context::new(&surface);
+            cr.set_antialias(cairo::Antialias::Gray);
+            let size = resvg::ScreenSize::new(rect.width as u32, rect.height as u32).unwrap();
+            resvg::backend_cairo::render_to_canvas(tree, &crate::RESVG_OPTIONS, size, &cr);
+        }
+
+        Ok(Raster {
+            frame_size: (width, height),
+            rect,
+            surface,
+        })
+    }
+
+    fn paint(&self, cr: &cairo::Context, alpha: f64) {
+        cr.set_source_surface(&self.surface, self.rect.x as f64, self.rect.y as f64);
+        cr.paint_with_alpha(alpha);
+    }
+}
+
+// A tree that is rendered once for each frame size.
+struct StaticLayer {
+    tree: usvg::Tree,
+    raster: Option<Raster>,
+}
+
+impl StaticLayer {
+    fn new(tree: usvg::Tree) -> StaticLayer {
+        StaticLayer { tree, raster: None }
+    }
+
+    fn paint(
+        &mut self,
+        cr: &cairo::Context,
+        placement: Option<Placement>,
+        width: i32,
+        height: i32,
+        alpha: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        let stale = match &self.raster {
+            Some(r) => r.frame_size != (width, height),
+            None => true,
+        };
+
+        if stale {
+            self.raster = Some(Raster::render(&self.tree, placement, width, height)?);
+        }
+
+        self.raster.as_ref().unwrap().paint(cr, alpha);
+        Ok(())
+    }
+}
+
+// The part of a layer that changes over time, re-rendered when the
+// resulting SVG changes.
+enum Dynamic {
+    // The document with only the elements from the first to the last text
+    // element with placeholders shown.
+    Text(Template),
+    // The whole document with animations, and whether it has placeholders in
+    // text elements.
+    Animated(Animations, bool),
+}
+
+impl Dynamic {
+    fn render(&self, ts_millis: f64) -> Result<String, Box<dyn Error>> {
+        let now = chrono::Local::now();
+        match self {
+            Dynamic::Text(template) => Ok(template.render(ts_millis, now)),
+            Dynamic::Animated(animations, false) => Ok(animations.render(ts_millis / 1000.)),
+            Dynamic::Animated(animations, true) => {
+                // Only the text content is substituted, like a static document.
+                let svg = animations.render(ts_millis / 1000.);
+                match template::parse_document(&svg)? {
+                    Some(template) => Ok(template.render(ts_millis, now)),
+                    None => Ok(svg),
+                }
+            }
+        }
+    }
+}
+
+struct DynamicLayer {
+    source: Dynamic,
+    svg: String,
+    raster: Option<Raster>,
+}
+
+pub(crate) struct Layer {
+    // The static tree, or the elements below the text with placeholders. Not
+    // set if the document is animated.
+    below: Option<StaticLayer>,
+    // The elements above the text with placeholders.
+    above: Option<StaticLayer>,
+    // Where to put the tree in the frame, the tree is scaled to the whole
+    // frame if not set.
+    pub(crate) placement: Option<Placement>,
+    pub(crate) opacity: f64,
+    // When the tree is shown, always if not set.
+    pub(crate) window: Option<Window>,
+    // Resolves image paths relative to the SVG file.
+    options: usvg::Options,
+    dynamic: Option<DynamicLayer>,
+}
+
+impl Layer {
+    pub(crate) fn load(config: LayerConfig) -> Result<Layer, Box<dyn Error>> {
+        let data = match &config.svg {
+            SvgSource::Path(path) => fs::read(path)?,
+            SvgSource::Data(data) => data.clone().into_bytes(),
+        };
+
+        let mut options = crate::RESVG_OPTIONS.usvg.clone();
+        if let SvgSource::Path(path) = &config.svg {
+            options.path = Some(path.into());
+        }
+
+        let fonts = config.fonts.load()?;
+        if let Some(family) = fonts.default_family() {
+            options.font_family = family;
+        }
+
+        let svg = match std::str::from_utf8(&data) {
+            Ok(svg) => Some(fonts.apply(svg)?),
+            Err(_) => None,
+        };
+
+        if let Some(svg) = &svg {
+            for warning in images::check(svg, options.path.as_deref()) {
+                eprintln!("warning: {}: {}", config.svg, warning);
+            }
+        }
+
+        let svg = svg.as_deref();
+        let animations = svg.map(Animations::parse).transpose()?.flatten();
+        let (below, dynamic, above) = if let Some(animations) = animations {
+            let placeholders = template::parse_document(svg.unwrap())?.is_some();
+            let dynamic = Dynamic::Animated(animations, placeholders);
+            // Fail early on invalid placeholders or animated values.
+            usvg::Tree::from_str(&dynamic.render(0.)?, &options)?;
+            (None, Some(dynamic), None)
+        } else if let Some(split) = svg.map(template::split_document).transpose()?.flatten() {
+            let parse = |svg: Option<String>| -> Result<_, Box<dyn Error>> {
+                match svg {
+                    Some(svg) => Ok(Some(usvg::Tree::from_str(&svg, &options)?)),
+                    None => Ok(None),
+                }
+            };
+
+            let below = parse(split.below)?;
+            let above = parse(split.above)?;
+            let dynamic = Dynamic::Text(split.template);
+            usvg::Tree::from_str(&dynamic.render(0.)?, &options)?;
+            (below, Some(dynamic), above)
+        } else if let Some(svg) = svg {
+            let tree = usvg::Tree::from_str(svg, &options)?;
+            (Some(tree), None, None)
+        } else {
+            // Not UTF-8, e.g. compressed SVG.
+            let tree = usvg::Tree::from_data(&data, &options)?;
+            (Some(tree), None, None)
+        };
+
+        Ok(Layer {
+            below: below.map(StaticLayer::new),
+            above: above.map(StaticLayer::new),
+            placement: config.placement,
+            opacity: config.opacity,
+            window: config.window,
+            options,
+            dynamic: dynamic.map(|source| DynamicLayer {
+                source,
+                svg: String::new(),
+                raster: None,
+            }),
+        })
+    }
+
+    pub(crate) fn paint(
+        &mut self,
+        cr: &cairo::Context,
+        width: i32,
+        height: i32,
+        ts_millis: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        let alpha = match &self.window {
+            Some(w) => w.alpha(ts_millis) * self.opacity,
+            None => self.opacity,
+        };
+
+        if alpha <= 0. {
+            return Ok(());
+        }
+
+        // Overlapping parts of a split document would show through each
+        // other if faded separately.
+        let split = self.dynamic.is_some() && (self.below.is_some() || self.above.is_some());
+        if alpha < 1. && split {
+            cr.push_group();
+            let result = self.paint_parts(cr, width, height, ts_millis, 1.);
+            cr.pop_group_to_source();
+            cr.paint_with_alpha(alpha);
+            return result;
+        }
+
+        self.paint_parts(cr, width, height, ts_millis, alpha)
+    }
+
+    fn paint_parts(
+        &mut self,
+        cr: &cairo::Context,
+        width: i32,
+        height: i32,
+        ts_millis: f64,
+        alpha: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(below) = &mut self.below {
+            below.paint(cr, self.placement, width, height, alpha)?;
+        }
+
+        if let Some(dynamic) = &mut self.dynamic {
+            let svg = dynamic.source.render(ts_millis)?;
+            let stale = match &dynamic.raster {
+                Some(r) => r.frame_size != (width, height) || svg != dynamic.svg,
+                None => true,
+            };
+
+            if stale {
+                let tree = usvg::Tree::from_str(&svg, &self.options)?;
+                dynamic.raster = Some(Raster::render(&tree, self.placement, width, height)?);
+                dynamic.svg = svg;
+            }
+
+            dynamic.raster.as_ref().unwrap().paint(cr, alpha);
+        }
+
+        if let Some(above) = &mut self.above {
+            above.paint(cr, self.placement, width, height, alpha)?;
+        }
+
+        Ok(())
+    }
+}
@@ -5,6 +5,18 @@ use std::ptr;
 
 use resvg::{cairo, usvg};
 
+mod config;
+use config::LayerConfig;
+
+mod layer;
+use layer::Layer;
+
+mod placement;
+
+mod template;
+
+mod window;
+
 lazy_static::lazy_static! {
     pub(crate) static ref RESVG_OPTIONS: resvg::Options = resvg::Options {
         usvg: usvg::Options {
@@ -17,8 +29,14 @@ lazy_static::lazy_static! {
     };
 }
 
+struct Context(Vec<Layer>);
+
+/// # Safety
+///
+/// `config` must be null or a NUL-terminated string and `user_data` must be
+/// valid for writes.
 #[no_mangle]
-pub extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void) -> c_int {
+pub unsafe extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void) -> c_int {
     unsafe {
         *user_data = ptr::null_mut();
     }
@@ -28,24 +46,29 @@ pub extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void
         return 1;
     }
 
-    let svg_path = match parse_config(config) {
-        Ok(p) => p,
+    let configs = match parse_config(config) {
+        Ok(c) => c,
         Err(e) => {
             eprintln!("error parsing config: {}", e);
             return 1;
         }
     };
 
-    let tree = match usvg::Tree::from_file(svg_path, &RESVG_OPTIONS.usvg) {
-        Ok(t) => t,
-        Err(e) => {
-            eprintln!("error reading svg: {}", e);
-            return 1;
+    let mut layers = Vec::with_capacity(configs.len());
+    for config in configs {
+        let name = config.svg.to_string();
+        match Layer::load(config) {
+            Ok(layer) => layers.push(layer),
+            Err(e) => {
+                eprintln!("error reading svg {}: {}", name, e);
+                return 1;
+            }
         }
-    };
+    }
 
+    let ctx = Context(layers);
     unsafe {
-        *user_data = Box::into_raw(Box::new(tree)) as *mut c_void;
+        *user_data = Box::into_raw(Box::new(ctx)) as *mut c_void;
     }
     0
 }
@@ -57,7 +80,7 @@ pub extern "C" fn filter_frame(
     width: c_int,
     height: c_int,
     line_size: c_int,
-    _ts_millis: c_double,
+    ts_millis: c_double,
     user_data: *mut c_void,
 ) -> c_int {
     let cr = match new_cairo_context(data, data_size as usize, width, height, line_size) {
@@ -68,15 +91,19 @@ pub extern "C" fn filter_frame(
         }
     };
 
-    let tree = if user_data.is_null() {
+    let ctx = if user_data.is_null() {
         eprintln!("no user data");
         return 1;
     } else {
-        unsafe { &*(user_data as *const usvg::Tree) }
+        unsafe { &mut *(user_data as *mut Context) }
     };
 
-    let size = resvg::ScreenSize::new(width as u32, height as u32).unwrap();
-    resvg::backend_cairo::render_to_canvas(&tree, &RESVG_OPTIONS, size, &cr);
+    for layer in ctx.0.iter_mut() {
+        if let Err(e) = layer.paint(&cr, width, height, ts_millis) {
+            eprintln!("could not render svg: {}", e);
+            return 1;
+        }
+    }
 
     0
 }
@@ -85,17 +112,14 @@ pub extern "C" fn filter_frame(
 pub extern "C" fn filter_uninit(user_data: *mut c_void) {
     if !user_data.is_null() {
         unsafe {
-            drop(Box::from_raw(user_data as *mut usvg::Tree));
+            drop(Box::from_raw(user_data as *mut Context));
         }
     }
 }
 
-fn parse_config(config: *const c_char) -> Result<String, Box<dyn Error>> {
-    let regex = regex::Regex::new(r"^svg=(.*)$")?;
+fn parse_config(config: *const c_char) -> Result<Vec<LayerConfig>, Box<dyn Error>> {
     let opt = unsafe { CStr::from_ptr(config) }.to_str()?;
-    let captures = regex.captures(opt).ok_or("invalid option, use: svg=path")?;
-    let path = captures.get(1).unwrap().as_str();
-    Ok(String::from(path))
+    config::parse(opt)
 }
 
 fn new_cairo_context(
@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    pub(crate) fn parse(s: &str) -> Result<Anchor, Box<dyn Error>> {
+        let anchor = match s {
+            "top-left" => Anchor::TopLeft,
+            "top" => Anchor::Top,
+            "top-right" => Anchor::TopRight,
+            "left" => Anchor::Left,
+            "center" => Anchor::Center,
+            "right" => Anchor::Right,
+            "bottom-left" => Anchor::BottomLeft,
+            "bottom" => Anchor::Bottom,
+            "bottom-right" => Anchor::BottomRight,
+            _ => return Err(format!("invalid anchor: {}", s).into()),
+        };
+
+        Ok(anchor)
+    }
+
+    // Returns the horizontal and vertical alignment, 0 for start, 0.5 for
+    // center and 1 for end.
+    fn align(self) -> (f64, f64) {
+        match self {
+            Anchor::TopLeft => (0., 0.),
+            Anchor::Top => (0.5, 0.),
+            Anchor::TopRight => (1., 0.),
+            Anchor::Left => (0., 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1., 0.5),
+            Anchor::BottomLeft => (0., 1.),
+            Anchor::Bottom => (0.5, 1.),
+            Anchor::BottomRight => (1., 1.),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Length {
+    Pixels(f64),
+    Percent(f64),
+}
+
+impl Length {
+    pub(crate) fn parse(s: &str) -> Result<Length, Box<dyn Error>> {
+        let length = if let Some(p) = s.strip_suffix('%') {
+            Length::Percent(p.parse()?)
+        } else {
+            Length::Pixels(s.trim_end_matches("px").parse()?)
+        };
+
+        Ok(length)
+    }
+
+    fn resolve(self, frame_size: f64) -> f64 {
+        match self {
+            Length::Pixels(px) => px,
+            Length::Percent(p) => frame_size * p / 100.,
+        }
+    }
+}
+
+// Placement of an SVG tree in the frame, independent of the SVG's viewBox.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Placement {
+    pub(crate) anchor: Anchor,
+    pub(crate) margin_x: Length,
+    pub(crate) margin_y: Length,
+    // Height of the tree relative to the frame height, or the natural size
+    // of the tree if not set.
+    pub(crate) scale: Option<f64>,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement {
+            anchor: Anchor::TopLeft,
+            margin_x: Length::Pixels(0.),
+            margin_y: Length::Pixels(0.),
+            scale: None,
+        }
+    }
+}
+
+// A rectangle in frame pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Rect {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) width: i32,
+    pub(crate) height: i32,
+}
+
+impl Placement {
+    // Computes where a tree with the given natural size ends up in a frame.
+    pub(crate) fn place(&self, tree_size: (f64, f64), frame_size: (i32, i32)) -> Rect {
+        let (frame_w, frame_h) = (frame_size.0 as f64, frame_size.1 as f64);
+        let k = self.scale.map(|s| s * frame_h / tree_size.1).unwrap_or(1.);
+        let (w, h) = (
+            (tree_size.0 * k).round().max(1.),
+            (tree_size.1 * k).round().max(1.),
+        );
+
+        let mx = self.margin_x.resolve(frame_w);
+        let my = self.margin_y.resolve(frame_h);
+        let (ax, ay) = self.anchor.align();
+        // The margin pushes away from the anchored edge and is ignored
+        // when centered.
+        let x = ax * (frame_w - w) + (1. - 2. * ax) * mx;
+        let y = ay * (frame_h - h) + (1. - 2. * ay) * my;
+
+        Rect {
+            x: x.round() as i32,
+            y: y.round() as i32,
+            width: w as i32,
+            height: h as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn place() {
+        let frame = (1920, 1080);
+        let tree = (200., 100.);
+
+        let p = Placement::default();
+        assert_eq!(
+            Rect {
+                x: 0,
+                y: 0,
+                width: 200,
+                height: 100
+            },
+            p.place(tree, frame)
+        );
+
+        let p = Placement {
+            anchor: Anchor::BottomRight,
+            margin_x: Length::Pixels(20.),
+            margin_y: Length::Percent(10.),
+            scale: Some(0.1),
+        };
+        assert_eq!(
+            Rect {
+                x: 1684,
+                y: 864,
+                width: 216,
+                height: 108
+            },
+            p.place(tree, frame)
+        );
+
+        let p = Placement {
+            anchor: Anchor::Center,
+            margin_x: Length::Pixels(20.),
+            ..Placement::default()
+        };
+        assert_eq!(
+            Rect {
+                x: 860,
+                y: 490,
+                width: 200,
+                height: 100
+            },
+            p.place(tree, frame)
+        );
+    }
+}
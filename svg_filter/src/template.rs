@@ -0,0 +1,454 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::error::Error;
+use std::ops::Range;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, Utc};
+use roxmltree::{Document, Node};
+
+// A value substituted for `{{name[:arg]}}` in SVG text content.
+#[derive(Debug, PartialEq)]
+enum Placeholder {
+    // `{{ts_ms}}`, the frame timestamp in milliseconds.
+    TsMillis,
+    // `{{timecode:fps}}`, the frame timestamp as HH:MM:SS:FF.
+    Timecode(Fps),
+    // `{{frame:fps}}`, the frame number.
+    Frame(Fps),
+    // `{{clock:format}}`, the wall clock in strftime format.
+    Clock(String),
+    // `{{countdown:rfc3339}}`, time left until the given time as HH:MM:SS.
+    Countdown(DateTime<Utc>),
+}
+
+const DEFAULT_FPS: Fps = Fps {
+    nominal: 25,
+    ntsc: false,
+};
+const DEFAULT_CLOCK_FORMAT: &str = "%H:%M:%S";
+
+// A whole frame rate, or an NTSC rate of 1000/1001 of it, e.g. 29.97.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Fps {
+    nominal: u32,
+    ntsc: bool,
+}
+
+impl Fps {
+    fn parse(s: &str) -> Result<Fps, Box<dyn Error>> {
+        if let Ok(nominal) = s.parse::<u32>() {
+            if nominal == 0 {
+                return Err("invalid fps 0".into());
+            }
+
+            return Ok(Fps {
+                nominal,
+                ntsc: false,
+            });
+        }
+
+        let fps = s.parse::<f64>()?;
+        let nominal = fps.round();
+        if nominal < 1. || (nominal * 1000. / 1001. - fps).abs() > 0.005 {
+            return Err(format!("invalid fps {}", s).into());
+        }
+
+        Ok(Fps {
+            nominal: nominal as u32,
+            ntsc: true,
+        })
+    }
+
+    fn frame(&self, ts_millis: f64) -> u64 {
+        let duration = if self.ntsc { 1001. } else { 1000. };
+        // Timestamps may be rounded to milliseconds.
+        ((ts_millis.max(0.) + 0.5) * self.nominal as f64 / duration).floor() as u64
+    }
+
+    // Drop frame timecode HH:MM:SS;FF for 29.97 and 59.94, where frame
+    // numbers are skipped at the start of every minute except every tenth.
+    fn timecode(&self, ts_millis: f64) -> String {
+        let mut frames = self.frame(ts_millis);
+        let fps = self.nominal as u64;
+        let mut separator = ':';
+        if self.ntsc && (fps == 30 || fps == 60) {
+            let drop = fps / 15;
+            let per_ten_minutes = fps * 600 - drop * 9;
+            let per_minute = fps * 60 - drop;
+            let rest = frames % per_ten_minutes;
+            frames += drop * 9 * (frames / per_ten_minutes);
+            if rest > drop {
+                frames += drop * ((rest - drop) / per_minute);
+            }
+
+            separator = ';';
+        }
+
+        let secs = frames / fps;
+        format!(
+            "{:02}:{:02}:{:02}{}{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            separator,
+            frames % fps
+        )
+    }
+}
+
+impl Placeholder {
+    fn parse(s: &str) -> Result<Placeholder, Box<dyn Error>> {
+        let mut parts = s.trim().splitn(2, ':');
+        let name = parts.next().unwrap();
+        let arg = parts.next();
+        let fps = || arg.map(Fps::parse).unwrap_or(Ok(DEFAULT_FPS));
+
+        let placeholder = match name {
+            "ts_ms" => Placeholder::TsMillis,
+            "timecode" => Placeholder::Timecode(fps()?),
+            "frame" => Placeholder::Frame(fps()?),
+            "clock" => {
+                let format = arg.unwrap_or(DEFAULT_CLOCK_FORMAT);
+                if StrftimeItems::new(format).any(|i| i == Item::Error) {
+                    return Err(format!("invalid clock format: {}", format).into());
+                }
+
+                Placeholder::Clock(String::from(format))
+            }
+
+            "countdown" => {
+                let target = arg.ok_or("missing countdown time")?;
+                Placeholder::Countdown(DateTime::parse_from_rfc3339(target)?.with_timezone(&Utc))
+            }
+
+            _ => return Err(format!("unknown placeholder: {}", s).into()),
+        };
+
+        Ok(placeholder)
+    }
+
+    fn render(&self, ts_millis: f64, now: DateTime<Local>) -> String {
+        match self {
+            Placeholder::TsMillis => format!("{}", ts_millis.round() as i64),
+            Placeholder::Timecode(fps) => fps.timecode(ts_millis),
+            Placeholder::Frame(fps) => format!("{}", fps.frame(ts_millis)),
+            Placeholder::Clock(format) => now.format(format).to_string(),
+            Placeholder::Countdown(target) => {
+                let secs = (*target - now.with_timezone(&Utc)).num_seconds().max(0);
+                format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+// SVG markup with placeholders that are substituted on every frame.
+#[derive(Debug)]
+pub(crate) struct Template(Vec<Part>);
+
+impl Template {
+    pub(crate) fn parse(s: &str) -> Result<Template, Box<dyn Error>> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..].find("}}").ok_or("unterminated placeholder")? + start;
+            parts.push(Part::Literal(String::from(&rest[..start])));
+            parts.push(Part::Placeholder(Placeholder::parse(
+                &rest[start + 2..end],
+            )?));
+            rest = &rest[end + 2..];
+        }
+
+        parts.push(Part::Literal(String::from(rest)));
+        Ok(Template(parts))
+    }
+
+    pub(crate) fn render(&self, ts_millis: f64, now: DateTime<Local>) -> String {
+        let mut s = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Literal(l) => s.push_str(l),
+                Part::Placeholder(p) => escape(&p.render(ts_millis, now), &mut s),
+            }
+        }
+
+        s
+    }
+}
+
+fn escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+// Parses the placeholders in the text content of the `<text>` elements of
+// an SVG document, which is rendered as a whole to keep the order of the
+// elements and references to them. Returns None if there are no
+// placeholders.
+pub(crate) fn parse_document(svg: &str) -> Result<Option<Template>, Box<dyn Error>> {
+    if !svg.contains("{{") {
+        return Ok(None);
+    }
+
+    let doc = Document::parse(svg)?;
+    let mut parts = Vec::new();
+    let mut pos = 0;
+    for node in doc.root_element().descendants() {
+        if !is_placeholder_text(&node) {
+            continue;
+        }
+
+        let range = node.range();
+        parts.push(Part::Literal(String::from(&svg[pos..range.start])));
+        parts.extend(Template::parse(&svg[range.clone()])?.0);
+        pos = range.end;
+    }
+
+    if parts.is_empty() {
+        return Ok(None);
+    }
+
+    parts.push(Part::Literal(String::from(&svg[pos..])));
+    Ok(Some(Template(parts)))
+}
+
+fn is_placeholder_text(node: &Node) -> bool {
+    // Text may have nested tspans.
+    node.is_text()
+        && node.text().map(|t| t.contains("{{")).unwrap_or(false)
+        && node
+            .ancestors()
+            .any(|a| a.is_element() && a.tag_name().name() == "text")
+}
+
+// Elements that are only rendered through references.
+const NOT_RENDERED: &[&str] = &[
+    "clipPath",
+    "defs",
+    "filter",
+    "linearGradient",
+    "marker",
+    "mask",
+    "pattern",
+    "radialGradient",
+    "symbol",
+];
+
+// Elements that are rendered as a whole.
+const GRAPHICS: &[&str] = &[
+    "circle", "ellipse", "image", "line", "path", "polygon", "polyline", "rect", "text", "use",
+];
+
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+// An SVG document with placeholders, split in paint order into the static
+// elements below the first and above the last element with placeholders,
+// and a template of the elements in between. Each part is the whole
+// document with the elements of the other parts hidden, to keep the
+// references between them.
+pub(crate) struct Split {
+    pub(crate) below: Option<String>,
+    pub(crate) template: Template,
+    pub(crate) above: Option<String>,
+}
+
+// Returns None if no rendered element has placeholders.
+pub(crate) fn split_document(svg: &str) -> Result<Option<Split>, Box<dyn Error>> {
+    if !svg.contains("{{") {
+        return Ok(None);
+    }
+
+    let doc = Document::parse(svg)?;
+    let mut graphics = Vec::new();
+    collect_graphics(doc.root_element(), &mut graphics);
+    let dynamic: Vec<bool> = graphics
+        .iter()
+        .map(|node| has_placeholders(&doc, node))
+        .collect();
+    let (first, last) = match dynamic.iter().position(|d| *d) {
+        Some(first) => (first, dynamic.iter().rposition(|d| *d).unwrap()),
+        None => return Ok(None),
+    };
+
+    let part = |range: Range<usize>| {
+        let mut s = String::new();
+        let mut pos = 0;
+        let hidden = graphics
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !range.contains(i))
+            .flat_map(|(_, node)| node.descendants().filter(|n| n.is_element()));
+        for node in hidden {
+            let (edit, text) = hide(svg, &node);
+            s.push_str(&svg[pos..edit.start]);
+            s.push_str(text);
+            pos = edit.end;
+        }
+
+        s.push_str(&svg[pos..]);
+        s
+    };
+
+    let middle = part(first..last + 1);
+    Ok(Some(Split {
+        below: Some(first).filter(|f| *f > 0).map(|f| part(0..f)),
+        template: parse_document(&middle)?.ok_or("no placeholders in text")?,
+        above: Some(last + 1)
+            .filter(|l| *l < graphics.len())
+            .map(|l| part(l..graphics.len())),
+    }))
+}
+
+fn collect_graphics<'a, 'input>(node: Node<'a, 'input>, out: &mut Vec<Node<'a, 'input>>) {
+    for child in node.children().filter(|n| n.is_element()) {
+        let name = child.tag_name().name();
+        if GRAPHICS.contains(&name) {
+            out.push(child);
+        } else if !NOT_RENDERED.contains(&name) {
+            collect_graphics(child, out);
+        }
+    }
+}
+
+// Whether a text element, or the element referenced by a use element, has
+// placeholders.
+fn has_placeholders(doc: &Document, node: &Node) -> bool {
+    let target = if node.tag_name().name() == "use" {
+        node.attribute((XLINK_NS, "href"))
+            .or_else(|| node.attribute("href"))
+            .and_then(|href| href.strip_prefix('#'))
+            .and_then(|id| doc.descendants().find(|n| n.attribute("id") == Some(id)))
+    } else {
+        Some(*node)
+    };
+
+    match target {
+        Some(target) => target.descendants().any(|n| is_placeholder_text(&n)),
+        None => false,
+    }
+}
+
+// Returns the edit of the element's start tag that hides it.
+fn hide(svg: &str, node: &Node) -> (Range<usize>, &'static str) {
+    if let Some(attribute) = node.attribute_node("visibility") {
+        return (attribute.value_range(), "hidden");
+    }
+
+    let start = node.range().start + 1;
+    let end = start
+        + svg[start..]
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap();
+    (end..end, " visibility=\"hidden\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders() {
+        let template =
+            Template::parse("a{{ts_ms}}b{{timecode:25}}c{{frame}}d{{clock:%Y}}").unwrap();
+        let now = Local::now();
+        assert_eq!(
+            format!("a3723960b01:02:03:24c93099d{}", now.format("%Y")),
+            template.render(3_723_960., now)
+        );
+
+        let template = Template::parse("{{countdown:2020-01-01T01:00:00+00:00}}").unwrap();
+        let now = DateTime::parse_from_rfc3339("2020-01-01T00:00:30Z")
+            .unwrap()
+            .with_timezone(&Local);
+        assert_eq!("00:59:30", template.render(0., now));
+        let now = DateTime::parse_from_rfc3339("2020-01-01T02:00:00Z")
+            .unwrap()
+            .with_timezone(&Local);
+        assert_eq!("00:00:00", template.render(0., now));
+
+        assert!(Template::parse("{{ts_ms").is_err());
+        assert!(Template::parse("{{foo}}").is_err());
+        assert!(Template::parse("{{timecode:0}}").is_err());
+        assert!(Template::parse("{{timecode:29.5}}").is_err());
+        assert!(Template::parse("{{clock:%Q}}").is_err());
+    }
+
+    #[test]
+    fn document() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"><defs><text id="t">{{frame}}</text></defs><g transform="translate(5 5)"><text x="1">TC <tspan>{{timecode:25}}</tspan></text></g><rect width="10" height="10"/><use xlink:href="#t"/><desc>{{foo}}</desc></svg>"##;
+        let template = parse_document(svg).unwrap().unwrap();
+        assert_eq!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"><defs><text id="t">25</text></defs><g transform="translate(5 5)"><text x="1">TC <tspan>00:00:01:00</tspan></text></g><rect width="10" height="10"/><use xlink:href="#t"/><desc>{{foo}}</desc></svg>"##,
+            template.render(1000., Local::now())
+        );
+
+        assert!(parse_document("<svg/>").unwrap().is_none());
+        assert!(parse_document("<svg><desc>{{foo}}</desc></svg>")
+            .unwrap()
+            .is_none());
+        assert!(parse_document("<svg><text>{{foo}}</text></svg>").is_err());
+    }
+
+    #[test]
+    fn ntsc() {
+        let template = Template::parse("{{timecode:29.97}} {{frame:29.97}}").unwrap();
+        // Frame numbers 0 and 1 are skipped at the first minute.
+        assert_eq!("00:00:59;29 1799", template.render(60026.693, Local::now()));
+        assert_eq!("00:01:00;02 1800", template.render(60060., Local::now()));
+        assert_eq!(
+            "00:10:00;00 17982",
+            template.render(600_019.4, Local::now())
+        );
+
+        let template = Template::parse("{{timecode:59.94}}").unwrap();
+        assert_eq!("00:01:00;04", template.render(60060., Local::now()));
+
+        let template = Template::parse("{{timecode:23.976}}").unwrap();
+        assert_eq!("00:00:01:00", template.render(1001., Local::now()));
+    }
+
+    #[test]
+    fn split() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"><defs><text id="t">{{frame}}</text></defs><rect/><g><text visibility="visible">TC <tspan>{{timecode}}</tspan></text><circle/></g><use xlink:href="#t"/><path/></svg>"##;
+        let split = split_document(svg).unwrap().unwrap();
+        assert_eq!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"><defs><text id="t">{{frame}}</text></defs><rect/><g><text visibility="hidden">TC <tspan visibility="hidden">{{timecode}}</tspan></text><circle visibility="hidden"/></g><use visibility="hidden" xlink:href="#t"/><path visibility="hidden"/></svg>"##,
+            split.below.unwrap()
+        );
+        assert_eq!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"><defs><text id="t">25</text></defs><rect visibility="hidden"/><g><text visibility="visible">TC <tspan>00:00:01:00</tspan></text><circle/></g><use xlink:href="#t"/><path visibility="hidden"/></svg>"##,
+            split.template.render(1000., Local::now())
+        );
+        assert_eq!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"><defs><text id="t">{{frame}}</text></defs><rect visibility="hidden"/><g><text visibility="hidden">TC <tspan visibility="hidden">{{timecode}}</tspan></text><circle visibility="hidden"/></g><use visibility="hidden" xlink:href="#t"/><path/></svg>"##,
+            split.above.unwrap()
+        );
+
+        let split = split_document("<svg><text>{{frame}}</text></svg>")
+            .unwrap()
+            .unwrap();
+        assert!(split.below.is_none());
+        assert!(split.above.is_none());
+
+        // Not rendered.
+        assert!(
+            split_document("<svg><defs><text>{{frame}}</text></defs></svg>")
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,140 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::error::Error;
+use std::fs;
+
+// An interval in milliseconds where the SVG is shown, open ended if there
+// is no end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Interval {
+    pub(crate) start: f64,
+    pub(crate) end: Option<f64>,
+}
+
+// When the SVG is shown and how it fades in and out, in milliseconds.
+#[derive(Debug, Clone)]
+pub(crate) struct Window {
+    pub(crate) intervals: Vec<Interval>,
+    pub(crate) fade_in: f64,
+    pub(crate) fade_out: f64,
+}
+
+impl Window {
+    // Returns the opacity in [0, 1] at the given timestamp.
+    pub(crate) fn alpha(&self, ts_millis: f64) -> f64 {
+        self.intervals
+            .iter()
+            .map(|i| {
+                if ts_millis < i.start || i.end.map(|e| ts_millis >= e).unwrap_or(false) {
+                    return 0.;
+                }
+
+                let mut alpha: f64 = 1.;
+                if self.fade_in > 0. {
+                    alpha = alpha.min((ts_millis - i.start) / self.fade_in);
+                }
+
+                if let (Some(end), true) = (i.end, self.fade_out > 0.) {
+                    alpha = alpha.min((end - ts_millis) / self.fade_out);
+                }
+
+                alpha
+            })
+            .fold(0., f64::max)
+    }
+}
+
+// Parses a time given as milliseconds (`1500`), seconds (`1.5s`) or
+// clock time (`01:30`, `00:01:30.500`).
+pub(crate) fn parse_time(s: &str) -> Result<f64, Box<dyn Error>> {
+    let s = s.trim();
+    let millis = if s.contains(':') {
+        let mut secs = 0.;
+        for part in s.split(':') {
+            secs = secs * 60. + part.parse::<f64>()?;
+        }
+
+        secs * 1000.
+    } else if let Some(ms) = s.strip_suffix("ms") {
+        ms.parse()?
+    } else if let Some(secs) = s.strip_suffix('s') {
+        secs.parse::<f64>()? * 1000.
+    } else {
+        s.parse()?
+    };
+
+    if millis < 0. {
+        return Err(format!("negative time: {}", s).into());
+    }
+
+    Ok(millis)
+}
+
+// Reads a sidecar file with one `start end` interval per line. The end may
+// be left out for the last interval, blank lines and lines starting with
+// `#` are ignored.
+pub(crate) fn read_intervals(path: &str) -> Result<Vec<Interval>, Box<dyn Error>> {
+    let mut intervals = Vec::new();
+    for (n, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let start = parse_time(parts.next().unwrap())?;
+        let end = parts.next().map(parse_time).transpose()?;
+        if parts.next().is_some() || end.map(|e| e <= start).unwrap_or(false) {
+            return Err(format!("{}:{}: invalid interval", path, n + 1).into());
+        }
+
+        intervals.push(Interval { start, end });
+    }
+
+    Ok(intervals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times() {
+        assert_eq!(1500., parse_time("1500").unwrap());
+        assert_eq!(1500., parse_time("1500ms").unwrap());
+        assert_eq!(1500., parse_time("1.5s").unwrap());
+        assert_eq!(90_000., parse_time("01:30").unwrap());
+        assert_eq!(3_690_500., parse_time("01:01:30.5").unwrap());
+        assert!(parse_time("-1").is_err());
+        assert!(parse_time("abc").is_err());
+    }
+
+    #[test]
+    fn alpha() {
+        let window = Window {
+            intervals: vec![
+                Interval {
+                    start: 1000.,
+                    end: Some(2000.),
+                },
+                Interval {
+                    start: 5000.,
+                    end: None,
+                },
+            ],
+            fade_in: 100.,
+            fade_out: 200.,
+        };
+
+        assert_eq!(0., window.alpha(0.));
+        assert_eq!(0., window.alpha(1000.));
+        assert_eq!(0.5, window.alpha(1050.));
+        assert_eq!(1., window.alpha(1500.));
+        assert_eq!(0.5, window.alpha(1900.));
+        assert_eq!(0., window.alpha(2000.));
+        assert_eq!(0., window.alpha(4000.));
+        assert_eq!(1., window.alpha(100_000.));
+    }
+}
@@ -0,0 +1,316 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use resvg::{cairo, usvg};
+
+use crate::transition::{SvgKey, Transition};
+
+// A rasterized transition, cropped to the bounding box of its visible pixels.
+pub(crate) struct Raster {
+    x: i32,
+    y: i32,
+    surface: Option<cairo::ImageSurface>,
+}
+
+impl Raster {
+    pub(crate) fn render(tree: &usvg::Tree, width: i32, height: i32) -> anyhow::Result<Raster> {
+        let mut surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)
+            .map_err(|s| anyhow::anyhow!("could not create surface: {:?}", s))?;
+        {
+            let cr = cairo::Context::new(&surface);
+            cr.set_antialias(cairo::Antialias::Best);
+            let size = resvg::ScreenSize::new(width as u32, height as u32).unwrap();
+            resvg::backend_cairo::render_to_canvas(tree, &crate::RESVG_OPTIONS, size, &cr);
+        }
+
+        surface.flush();
+        let stride = surface.get_stride() as usize;
+        let bbox = match surface.get_data() {
+            Ok(data) => alpha_bbox(&data, stride, width, height),
+            Err(e) => return Err(anyhow::anyhow!("could not read surface: {:?}", e)),
+        };
+
+        let (x, y, w, h) = match bbox {
+            Some(bbox) => bbox,
+            None => {
+                return Ok(Raster {
+                    x: 0,
+                    y: 0,
+                    surface: None,
+                })
+            }
+        };
+
+        let cropped = cairo::ImageSurface::create(cairo::Format::ARgb32, w, h)
+            .map_err(|s| anyhow::anyhow!("could not create surface: {:?}", s))?;
+        {
+            let cr = cairo::Context::new(&cropped);
+            cr.set_operator(cairo::Operator::Source);
+            cr.set_source_surface(&surface, -x as f64, -y as f64);
+            cr.paint();
+        }
+
+        Ok(Raster {
+            x,
+            y,
+            surface: Some(cropped),
+        })
+    }
+
+    // Returns the (x, y, width, height) of the visible pixels.
+    pub(crate) fn bbox(&self) -> Option<(i32, i32, i32, i32)> {
+        self.surface
+            .as_ref()
+            .map(|s| (self.x, self.y, s.get_width(), s.get_height()))
+    }
+
+    pub(crate) fn paint(&self, cr: &cairo::Context) {
+        if let Some(surface) = &self.surface {
+            cr.set_source_surface(surface, self.x as f64, self.y as f64);
+            cr.paint();
+        }
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        self.surface
+            .as_ref()
+            .map(|s| s.get_stride() as usize * s.get_height() as usize)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    svg: SvgKey,
+    width: i32,
+    height: i32,
+}
+
+impl Key {
+    fn new(transition: &Transition, width: i32, height: i32) -> Key {
+        Key {
+            svg: transition.svg_key(),
+            width,
+            height,
+        }
+    }
+}
+
+struct Entry {
+    raster: Raster,
+    size: usize,
+    last_used: u64,
+}
+
+// Least recently used cache of rasterized transitions bounded by the
+// total size in bytes of the cached bitmaps.
+pub(crate) struct RasterCache {
+    limit: usize,
+    size: usize,
+    tick: u64,
+    entries: HashMap<Key, Entry>,
+}
+
+impl RasterCache {
+    pub(crate) fn new(limit: usize) -> RasterCache {
+        RasterCache {
+            limit,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    // Paints the transition if it's cached, returns false otherwise.
+    pub(crate) fn paint_cached(
+        &mut self,
+        transition: &Transition,
+        width: i32,
+        height: i32,
+        cr: &cairo::Context,
+    ) -> bool {
+        match self.get(&Key::new(transition, width, height)) {
+            Some(raster) => {
+                raster.paint(cr);
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    // Renders and paints the tree of a transition that isn't cached.
+    pub(crate) fn paint(
+        &mut self,
+        transition: &Transition,
+        tree: &usvg::Tree,
+        width: i32,
+        height: i32,
+        cr: &cairo::Context,
+    ) -> anyhow::Result<()> {
+        if self.limit == 0 {
+            let size = resvg::ScreenSize::new(width as u32, height as u32).unwrap();
+            resvg::backend_cairo::render_to_canvas(tree, &crate::RESVG_OPTIONS, size, cr);
+            return Ok(());
+        }
+
+        let raster = Raster::render(tree, width, height)?;
+        raster.paint(cr);
+        self.insert(Key::new(transition, width, height), raster);
+        Ok(())
+    }
+
+    fn get(&mut self, key: &Key) -> Option<&Raster> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.tick;
+        Some(&entry.raster)
+    }
+
+    // Rasters larger than the limit are not cached.
+    fn insert(&mut self, key: Key, raster: Raster) {
+        let size = raster.size_in_bytes();
+        if size > self.limit {
+            return;
+        }
+
+        if let Some(entry) = self.entries.remove(&key) {
+            self.size -= entry.size;
+        }
+
+        self.evict(self.limit - size);
+        self.size += size;
+        self.entries.insert(
+            key,
+            Entry {
+                raster,
+                size,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    fn evict(&mut self, max_size: usize) {
+        while self.size > max_size {
+            let key = match self.entries.iter().min_by_key(|(_, e)| e.last_used) {
+                Some((k, _)) => *k,
+                None => break,
+            };
+
+            if let Some(entry) = self.entries.remove(&key) {
+                self.size -= entry.size;
+            }
+        }
+    }
+}
+
+// Returns the (x, y, width, height) of the pixels with non-zero alpha in
+// an ARGB32 image, or None if the image is fully transparent.
+fn alpha_bbox(data: &[u8], stride: usize, width: i32, height: i32) -> Option<(i32, i32, i32, i32)> {
+    let (mut x0, mut y0, mut x1, mut y1) = (width, height, -1, -1);
+    for y in 0..height {
+        let row = &data[y as usize * stride..];
+        for x in 0..width {
+            let off = x as usize * 4;
+            let px = u32::from_ne_bytes([row[off], row[off + 1], row[off + 2], row[off + 3]]);
+            if px >> 24 != 0 {
+                x0 = x0.min(x);
+                x1 = x1.max(x);
+                y0 = y0.min(y);
+                y1 = y1.max(y);
+            }
+        }
+    }
+
+    if x1 < 0 {
+        None
+    } else {
+        Some((x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: usize) -> Key {
+        Key {
+            svg: SvgKey::Inline(id),
+            width: 1920,
+            height: 1080,
+        }
+    }
+
+    // An ARGB32 raster of width * height * 4 bytes.
+    fn raster(width: i32, height: i32) -> Raster {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).unwrap();
+        Raster {
+            x: 0,
+            y: 0,
+            surface: Some(surface),
+        }
+    }
+
+    #[test]
+    fn eviction() {
+        let mut cache = RasterCache::new(1000);
+        cache.insert(key(0), raster(10, 10));
+        cache.insert(key(1), raster(10, 10));
+        assert_eq!(800, cache.size);
+
+        // The least recently used raster is evicted to make room.
+        assert!(cache.get(&key(0)).is_some());
+        cache.insert(key(2), raster(10, 10));
+        assert_eq!(800, cache.size);
+        assert!(cache.get(&key(1)).is_none());
+        assert!(cache.get(&key(0)).is_some());
+        assert!(cache.get(&key(2)).is_some());
+
+        // Rasters larger than the limit are not cached.
+        cache.insert(key(3), raster(20, 20));
+        assert!(cache.get(&key(3)).is_none());
+        assert_eq!(800, cache.size);
+
+        // Empty rasters take no space.
+        cache.insert(
+            key(4),
+            Raster {
+                x: 0,
+                y: 0,
+                surface: None,
+            },
+        );
+        assert!(cache.get(&key(4)).is_some());
+        assert_eq!(800, cache.size);
+    }
+
+    fn set_alpha(data: &mut [u8], stride: usize, x: usize, y: usize) {
+        let px = 0xff00_0000u32.to_ne_bytes();
+        data[y * stride + x * 4..][..4].copy_from_slice(&px);
+    }
+
+    #[test]
+    fn bbox() {
+        let (width, height, stride) = (4, 3, 20);
+        let mut data = vec![0u8; stride * height as usize];
+        assert_eq!(None, alpha_bbox(&data, stride, width, height));
+
+        set_alpha(&mut data, stride, 1, 1);
+        set_alpha(&mut data, stride, 2, 2);
+        assert_eq!(Some((1, 1, 2, 2)), alpha_bbox(&data, stride, width, height));
+
+        set_alpha(&mut data, stride, 0, 0);
+        set_alpha(&mut data, stride, 3, 0);
+        assert_eq!(Some((0, 0, 4, 3)), alpha_bbox(&data, stride, width, height));
+
+        // Padding at the end of the rows is ignored.
+        let mut data = vec![0xff; stride * height as usize];
+        for row in data.chunks_mut(stride) {
+            row[..width as usize * 4].iter_mut().for_each(|b| *b = 0);
+        }
+        assert_eq!(None, alpha_bbox(&data, stride, width, height));
+    }
+}
@@ -0,0 +1,113 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{self, BufRead, BufReader, Read};
+
+use flate2::read::GzDecoder;
+use xz2::read::XzDecoder;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+impl Compression {
+    pub(crate) fn parse(s: &str) -> Option<Compression> {
+        match s {
+            "none" => Some(Compression::None),
+            "gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+    // Detects the compression from the first bytes of the data, anything
+    // else is assumed to be uncompressed.
+    pub(crate) fn detect(data: &[u8]) -> Compression {
+        if data.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if data.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else if data.starts_with(XZ_MAGIC) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+}
+
+// Returns a reader of the decompressed data, the compression is detected
+// if not given.
+pub(crate) fn decoder<'a, R: Read + 'a>(
+    source: R,
+    compression: Option<Compression>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let mut source = BufReader::new(source);
+    let compression = match compression {
+        Some(c) => c,
+        None => Compression::detect(source.fill_buf()?),
+    };
+
+    Ok(match compression {
+        Compression::None => Box::new(source),
+        Compression::Gzip => Box::new(GzDecoder::new(source)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(source)?),
+        Compression::Xz => Box::new(XzDecoder::new_multi_decoder(source)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use xz2::write::XzEncoder;
+
+    #[test]
+    fn detect() {
+        let gzip = [0x1f, 0x8b, 0x08, 0x00];
+        assert_eq!(Compression::Gzip, Compression::detect(&gzip));
+        let zstd = [0x28, 0xb5, 0x2f, 0xfd, 0x04];
+        assert_eq!(Compression::Zstd, Compression::detect(&zstd));
+        assert_eq!(Compression::Xz, Compression::detect(b"\xfd7zXZ\x00\x00"));
+        assert_eq!(Compression::None, Compression::detect(b"<transitions>"));
+        assert_eq!(Compression::None, Compression::detect(&[]));
+    }
+
+    fn decode(data: &[u8]) -> String {
+        let mut s = String::new();
+        let mut decoder = decoder(data, None).unwrap();
+        decoder.read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!("<transitions/>", decode(b"<transitions/>"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = b"<transitions/>";
+
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(data).unwrap();
+        assert_eq!("<transitions/>", decode(&gzip.finish().unwrap()));
+
+        let zstd = zstd::encode_all(&data[..], 0).unwrap();
+        assert_eq!("<transitions/>", decode(&zstd));
+
+        let mut xz = XzEncoder::new(Vec::new(), 6);
+        xz.write_all(data).unwrap();
+        assert_eq!("<transitions/>", decode(&xz.finish().unwrap()));
+    }
+}
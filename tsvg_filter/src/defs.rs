@@ -0,0 +1,188 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Document-level `<defs>` and `<style>` elements directly under
+// `<transitions>`, e.g. glyph outlines shared by many transitions. They are
+// kept as source text and merged into the SVG of a transition right before
+// it's parsed, to not keep a copy in every transition.
+
+use std::sync::Arc;
+
+use roxmltree::Node;
+use svg_common::fonts::Fonts;
+
+// The shared elements as source text. The generation changes when elements
+// are added in live mode, to tell the merged SVG of a file apart.
+#[derive(Clone)]
+pub(crate) struct Defs {
+    pub(crate) generation: usize,
+    pub(crate) svg: Arc<str>,
+    // The prefixes and URIs declared on the root, e.g. for `xlink:href` in
+    // the shared elements.
+    pub(crate) namespaces: Arc<[(String, String)]>,
+}
+
+pub(crate) fn is_defs(node: &Node) -> bool {
+    node.has_tag_name("defs") || node.has_tag_name("style")
+}
+
+// Returns the shared elements of the root in document order, if any.
+pub(crate) fn collect(source: &str, root: &Node, fonts: &Fonts) -> anyhow::Result<Option<Defs>> {
+    let defs = root
+        .children()
+        .filter(is_defs)
+        .map(|c| fonts.apply_fallbacks(source, c))
+        .collect::<Result<String, String>>()
+        .map_err(anyhow::Error::msg)?;
+
+    if defs.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Defs {
+        generation: 0,
+        svg: Arc::from(defs),
+        namespaces: namespaces(root),
+    }))
+}
+
+// Returns the prefixed namespace declarations of the root.
+pub(crate) fn namespaces(root: &Node) -> Arc<[(String, String)]> {
+    root.namespaces()
+        .iter()
+        .filter_map(|ns| Some((ns.name()?, ns.uri())))
+        .filter(|(prefix, _)| *prefix != "xml")
+        .map(|(prefix, uri)| (String::from(prefix), String::from(uri)))
+        .collect()
+}
+
+// Inserts the shared elements first in the root element of the SVG, and
+// declares the prefixes of the TSVG root that the SVG doesn't declare.
+pub(crate) fn merge(svg: &str, defs: &Defs) -> String {
+    let (name, end) = match root_start_tag(svg) {
+        Some(tag) => tag,
+        None => return String::from(svg),
+    };
+
+    let self_closing = svg[..end].ends_with("/>");
+    let tag = &svg[..end - if self_closing { 2 } else { 1 }];
+    let mut merged = String::from(tag);
+    for (prefix, uri) in defs.namespaces.iter() {
+        if !tag.contains(&format!("xmlns:{}=", prefix)) {
+            let uri = uri.replace('&', "&amp;").replace('"', "&quot;");
+            merged.push_str(&format!(" xmlns:{}=\"{}\"", prefix, uri));
+        }
+    }
+
+    merged.push('>');
+    merged.push_str(&defs.svg);
+    if self_closing {
+        merged.push_str(&format!("</{}>", name));
+    }
+
+    merged.push_str(&svg[end..]);
+    merged
+}
+
+// Returns the name of the root element and the end of its start tag,
+// skipping the XML declaration, comments and the doctype.
+fn root_start_tag(svg: &str) -> Option<(&str, usize)> {
+    let mut pos = 0;
+    loop {
+        pos += svg[pos..].find('<')?;
+        let rest = &svg[pos..];
+        let skip_to = if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<?") {
+            "?>"
+        } else if rest.starts_with("<!") {
+            ">"
+        } else {
+            break;
+        };
+
+        pos += rest.find(skip_to)? + skip_to.len();
+    }
+
+    let name_len = svg[pos + 1..]
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(svg.len() - pos - 1);
+
+    // Attribute values may contain `>`.
+    let mut quote = None;
+    for (i, c) in svg[pos..].char_indices() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('>', None) => return Some((&svg[pos + 1..pos + 1 + name_len], pos + i + 1)),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defs(svg: &str) -> Defs {
+        Defs {
+            generation: 0,
+            svg: Arc::from(svg),
+            namespaces: Arc::from(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn merge_defs() {
+        let d = defs("<defs><path id=\"p0\" d=\"M0 0\"/></defs>");
+        assert_eq!(
+            "<svg a=\"1>2\"><defs><path id=\"p0\" d=\"M0 0\"/></defs><use href=\"#p0\"/></svg>",
+            merge("<svg a=\"1>2\"><use href=\"#p0\"/></svg>", &d)
+        );
+
+        assert_eq!(
+            "<?xml version=\"1.0\"?><!-- <svg> --><svg><style/></svg>",
+            merge(
+                "<?xml version=\"1.0\"?><!-- <svg> --><svg/>",
+                &defs("<style/>")
+            )
+        );
+
+        assert_eq!("text", merge("text", &d));
+    }
+
+    #[test]
+    fn merge_namespaces() {
+        let source = r##"<transitions xmlns:xlink="http://www.w3.org/1999/xlink" xmlns:a="urn:a">
+<defs><path id="p0" d="M0 0h10"/><use id="u0" xlink:href="#p0"/></defs>
+</transitions>"##;
+        let doc = roxmltree::Document::parse(source).unwrap();
+        let fonts = svg_common::fonts::FontConfig::default().load().unwrap();
+        let d = collect(source, &doc.root_element(), &fonts)
+            .unwrap()
+            .unwrap();
+
+        // Only the prefixes the SVG doesn't declare itself.
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:a="urn:b"><use xlink:href="#u0"/></svg>"##;
+        let merged = merge(svg, &d);
+        assert_eq!(
+            concat!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:a="urn:b" "#,
+                r#"xmlns:xlink="http://www.w3.org/1999/xlink"><defs><path id="p0" d="M0 0h10"/>"#,
+                r##"<use id="u0" xlink:href="#p0"/></defs><use xlink:href="#u0"/></svg>"##
+            ),
+            merged
+        );
+
+        let doc = roxmltree::Document::parse(&merged).unwrap();
+        let xlink = "http://www.w3.org/1999/xlink";
+        let hrefs: Vec<&str> = doc
+            .descendants()
+            .filter_map(|n| n.attribute((xlink, "href")))
+            .collect();
+        assert_eq!(vec!["#p0", "#u0"], hrefs);
+    }
+}
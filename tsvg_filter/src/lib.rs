@@ -3,17 +3,40 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::ffi::CStr;
-use std::fs::File;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use std::ptr;
 
-use flate2::read::GzDecoder;
 use libc::{c_char, c_double, c_int, c_uchar, c_uint, c_void};
 use resvg::{cairo, usvg};
+use svg_common::fonts::{FontConfig, Fonts};
+
+mod cache;
+use cache::RasterCache;
+
+mod compression;
+use compression::Compression;
+
+mod defs;
+
+mod live;
+
+pub mod lint;
+
+mod motion;
 
 mod parse;
 
+mod subtitles;
+use subtitles::Template;
+
+mod timing;
+
 mod transition;
-use transition::Tree;
+use transition::{Transition, Tree};
+
+mod trees;
+use trees::{Parsed, TreeCache};
 
 lazy_static::lazy_static! {
     pub(crate) static ref RESVG_OPTIONS: resvg::Options = resvg::Options {
@@ -27,21 +50,66 @@ lazy_static::lazy_static! {
     };
 }
 
-#[derive(Debug)]
-enum Compression {
-    None,
-    Gzip,
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Tsvg,
+    Subtitles(subtitles::Format),
+}
+
+impl Format {
+    fn parse(s: &str) -> Option<Format> {
+        match s {
+            "tsvg" => Some(Format::Tsvg),
+            "srt" => Some(Format::Subtitles(subtitles::Format::Srt)),
+            "webvtt" => Some(Format::Subtitles(subtitles::Format::WebVtt)),
+            _ => None,
+        }
+    }
 }
 
+// Default memory limit for the raster cache, in MiB.
+const DEFAULT_CACHE_SIZE: usize = 256;
+// Default number of parsed transitions to keep.
+const DEFAULT_TREE_CACHE_SIZE: usize = 32;
+// Default number of upcoming transitions to parse ahead of time.
+const DEFAULT_PREFETCH: usize = 2;
+
 struct Config<'a> {
-    compression: Compression,
+    format: Format,
+    // Detected from the file if not given.
+    compression: Option<Compression>,
+    // SVG template for subtitles.
+    template: Option<&'a str>,
+    cache_size: usize,
+    tree_cache_size: usize,
+    prefetch: usize,
+    // Skip broken transitions with a warning instead of failing.
+    lenient: bool,
+    // Read transitions from a growing file or a named pipe.
+    live: bool,
+    fonts: FontConfig,
     tsvg: &'a str,
 }
 
-struct Context(Tree);
+struct Context {
+    // The TSVG or subtitle file, for errors.
+    path: PathBuf,
+    // Skip transitions that fail to render instead of failing.
+    lenient: bool,
+    tree: Tree,
+    // Receives the transitions in live mode.
+    live: Option<live::Reader>,
+    trees: TreeCache,
+    prefetch: usize,
+    cache: RasterCache,
+}
 
+/// # Safety
+///
+/// `config` must be null or a NUL-terminated string and `user_data` must be
+/// valid for writes.
 #[no_mangle]
-pub extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void) -> c_int {
+pub unsafe extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void) -> c_int {
     unsafe {
         *user_data = ptr::null_mut();
     }
@@ -51,15 +119,56 @@ pub extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void
         return 1;
     }
 
-    let tree = match parse_config(config).and_then(|c| parse_tsvg(&c)) {
-        Ok(t) => t,
+    let config = match parse_config(config) {
+        Ok(c) => c,
         Err(e) => {
             eprintln!("error parsing: {}", e);
             return 1;
         }
     };
 
-    let ctx = Context(tree);
+    let fonts = match config.fonts.load() {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("error loading fonts: {}", e);
+            return 1;
+        }
+    };
+
+    // Resolves image paths relative to the TSVG file.
+    let mut options = RESVG_OPTIONS.usvg.clone();
+    options.path = Some(config.tsvg.into());
+    if let Some(family) = fonts.default_family() {
+        options.font_family = family;
+    }
+
+    let (tree, live) = if config.live {
+        match start_live(&config, &fonts, &options) {
+            Ok(reader) => (Tree::new(Vec::new()), Some(reader)),
+            Err(e) => {
+                eprintln!("error starting live mode: {}", e);
+                return 1;
+            }
+        }
+    } else {
+        match parse_tsvg(&config, &fonts) {
+            Ok(t) => (t, None),
+            Err(e) => {
+                eprintln!("error parsing: {}", e);
+                return 1;
+            }
+        }
+    };
+
+    let ctx = Context {
+        path: PathBuf::from(config.tsvg),
+        lenient: config.lenient,
+        tree,
+        live,
+        trees: TreeCache::new(options, fonts, config.tree_cache_size),
+        prefetch: config.prefetch,
+        cache: RasterCache::new(config.cache_size * 1024 * 1024),
+    };
     unsafe {
         *user_data = Box::into_raw(Box::new(ctx)) as *mut c_void;
     }
@@ -85,25 +194,51 @@ pub extern "C" fn filter_frame(
         eprintln!("no user data");
         return 1;
     } else {
-        unsafe { &*(user_data as *const Context) }
+        unsafe { &mut *(user_data as *mut Context) }
     };
 
-    let transitions = ctx.0.search(ts_millis);
-    if transitions.is_empty() {
-        return 0;
+    if let Some(live) = &ctx.live {
+        ctx.tree.extend(live.received());
     }
 
-    let cr = match new_cairo_context(data, data_size as usize, width, height, line_size) {
-        Ok(cr) => cr,
-        Err(status) => {
-            eprintln!("could not create cairo context: {:?}", status);
-            return 1;
+    let transitions = ctx.tree.search(ts_millis);
+    if !transitions.is_empty() {
+        let cr = match new_cairo_context(data, data_size as usize, width, height, line_size) {
+            Ok(cr) => cr,
+            Err(status) => {
+                eprintln!("could not create cairo context: {:?}", status);
+                return 1;
+            }
+        };
+
+        for transition in transitions {
+            // The error was reported when it failed.
+            if ctx.lenient && ctx.trees.has_failed(transition) {
+                continue;
+            }
+
+            let (trees, cache) = (&mut ctx.trees, &mut ctx.cache);
+            let result = paint_transition(trees, cache, transition, ts_millis, width, height, &cr);
+            if let Err(e) = result {
+                let e = transition.located(&ctx.path, format!("could not render: {}", e));
+                if !ctx.lenient {
+                    eprintln!("{}", e);
+                    return 1;
+                }
+
+                eprintln!("warning: {} (skipped)", e);
+                ctx.trees.set_failed(transition);
+            }
         }
-    };
+    }
 
-    let size = resvg::ScreenSize::new(width as u32, height as u32).unwrap();
-    for transition in transitions {
-        resvg::backend_cairo::render_to_canvas(&transition.tree, &RESVG_OPTIONS, size, &cr);
+    let upcoming = ctx.tree.upcoming(ts_millis).take(ctx.prefetch);
+    // The frame is fine, the transition fails or is skipped when it becomes
+    // active.
+    if let Err((transition, e)) = ctx.trees.prefetch(upcoming) {
+        let e = transition.located(&ctx.path, format!("could not prefetch: {}", e));
+        let skipped = if ctx.lenient { " (skipped)" } else { "" };
+        eprintln!("warning: {}{}", e, skipped);
     }
 
     0
@@ -121,34 +256,225 @@ pub extern "C" fn filter_uninit(user_data: *mut c_void) {
 fn parse_config<'a>(config: *const c_char) -> anyhow::Result<Config<'a>> {
     let cstr = unsafe { CStr::from_ptr(config) };
     let s = cstr.to_str()?;
-    let re = regex::Regex::new(r"^(?:compression=(none|gzip),)?tsvg=(.+)$").unwrap();
+    let re = regex::Regex::new(r"^((?:[a-z_]+=[^,]*,)*)(?:tsvg|file)=(.+)$").unwrap();
     if let Some(cap) = re.captures(s) {
-        let compression = if let Some(c) = cap.get(1) {
-            if c.as_str() == "none" {
-                Compression::None
-            } else {
-                Compression::Gzip
-            }
-        } else {
-            Compression::Gzip
+        let mut config = Config {
+            format: Format::Tsvg,
+            compression: None,
+            template: None,
+            cache_size: DEFAULT_CACHE_SIZE,
+            tree_cache_size: DEFAULT_TREE_CACHE_SIZE,
+            prefetch: DEFAULT_PREFETCH,
+            lenient: false,
+            live: false,
+            fonts: FontConfig::default(),
+            tsvg: cap.get(2).unwrap().as_str(),
         };
 
-        Ok(Config {
-            compression,
-            tsvg: cap.get(2).unwrap().as_str(),
-        })
+        let opts = cap.get(1).unwrap().as_str();
+        for opt in opts.split(',').filter(|o| !o.is_empty()) {
+            let mut kv = opt.splitn(2, '=');
+            let (k, v) = (kv.next().unwrap(), kv.next().unwrap());
+            match k {
+                "format" => {
+                    config.format = match Format::parse(v) {
+                        Some(f) => f,
+                        None => return Err(anyhow::anyhow!("invalid format {}", v)),
+                    }
+                }
+
+                "compression" => {
+                    config.compression = match Compression::parse(v) {
+                        Some(c) => Some(c),
+                        None => return Err(anyhow::anyhow!("invalid compression {}", v)),
+                    }
+                }
+
+                "template" => config.template = Some(v),
+
+                "cache_size" => config.cache_size = v.parse()?,
+                "tree_cache_size" => config.tree_cache_size = v.parse()?,
+                "prefetch" => config.prefetch = v.parse()?,
+                "lenient" => {
+                    config.lenient = match v {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(anyhow::anyhow!("invalid lenient {}", v)),
+                    }
+                }
+
+                "live" => {
+                    config.live = match v {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(anyhow::anyhow!("invalid live {}", v)),
+                    }
+                }
+
+                _ => {
+                    let font_option = config.fonts.parse_option(k, v);
+                    if !font_option.map_err(anyhow::Error::msg)? {
+                        return Err(anyhow::anyhow!("unknown option {}", k));
+                    }
+                }
+            }
+        }
+
+        Ok(config)
     } else {
         Err(anyhow::anyhow!(s))
     }
 }
 
-fn parse_tsvg(config: &Config) -> anyhow::Result<Tree> {
-    let f = File::open(config.tsvg)?;
-    if let Compression::Gzip = config.compression {
-        parse::parse_tsvg(GzDecoder::new(f))
+fn parse_tsvg(config: &Config, fonts: &Fonts) -> anyhow::Result<Tree> {
+    let path = Path::new(config.tsvg);
+    let source = compression::decoder(File::open(path)?, config.compression)?;
+
+    let transitions = match config.format {
+        Format::Tsvg => parse::parse_tsvg(source, fonts, path, config.lenient)?,
+        Format::Subtitles(format) => {
+            let template = config.template.map(fs::read_to_string).transpose()?;
+            let template = Template::parse(template.as_deref())?;
+            subtitles::transitions(source, format, &template)?
+        }
+    };
+
+    Ok(Tree::new(transitions))
+}
+
+// The stream is read as it's written, so it can't be compressed.
+fn start_live(
+    config: &Config,
+    fonts: &Fonts,
+    options: &usvg::Options,
+) -> anyhow::Result<live::Reader> {
+    if let Format::Subtitles(_) = config.format {
+        return Err(anyhow::anyhow!("live mode requires format tsvg"));
+    }
+
+    if config.compression.unwrap_or(Compression::None) != Compression::None {
+        return Err(anyhow::anyhow!("live mode requires compression none"));
+    }
+
+    let path = Path::new(config.tsvg);
+    Ok(live::Reader::start(path, fonts.clone(), options.clone()))
+}
+
+fn paint_transition(
+    trees: &mut TreeCache,
+    cache: &mut RasterCache,
+    transition: &Transition,
+    ts_millis: f64,
+    width: i32,
+    height: i32,
+    cr: &cairo::Context,
+) -> anyhow::Result<()> {
+    let mut alpha = transition.alpha(ts_millis);
+    let state = transition
+        .motion
+        .as_ref()
+        .map(|m| m.at(ts_millis - transition.time_in as f64));
+
+    if let Some(state) = &state {
+        alpha *= state.opacity;
+        if state.scale.0 == 0. || state.scale.1 == 0. {
+            return Ok(());
+        }
+    }
+
+    if alpha <= 0. {
+        return Ok(());
+    }
+
+    // A cached raster would be blurred by the scale, scaled transitions are
+    // rendered from the tree instead.
+    let cache = match &state {
+        Some(state) if state.scale != (1., 1.) => None,
+        _ => Some(cache),
+    };
+
+    cr.save();
+    if let Some(state) = &state {
+        let (cx, cy) = (width as f64 / 2., height as f64 / 2.);
+        cr.translate(state.translate.0 + cx, state.translate.1 + cy);
+        cr.scale(state.scale.0, state.scale.1);
+        cr.translate(-cx, -cy);
+    }
+
+    let result = if alpha >= 1. {
+        paint_tree(trees, cache, transition, ts_millis, width, height, cr)
     } else {
-        parse::parse_tsvg(f)
+        cr.push_group();
+        let result = paint_tree(trees, cache, transition, ts_millis, width, height, cr);
+        cr.pop_group_to_source();
+        cr.paint_with_alpha(alpha);
+        result
+    };
+
+    cr.restore();
+    result
+}
+
+fn paint_tree(
+    trees: &mut TreeCache,
+    mut cache: Option<&mut RasterCache>,
+    transition: &Transition,
+    ts_millis: f64,
+    width: i32,
+    height: i32,
+    cr: &cairo::Context,
+) -> anyhow::Result<()> {
+    if !trees.is_animated(transition)? {
+        if let Some(cache) = &mut cache {
+            if cache.paint_cached(transition, width, height, cr) {
+                return Ok(());
+            }
+        }
+
+        let tree = match trees.get(transition)? {
+            Parsed::Static(tree) => tree,
+            Parsed::Animated(_) => return Err(anyhow::anyhow!("unexpected animated transition")),
+        };
+
+        return match cache {
+            Some(cache) => cache.paint(transition, tree, width, height, cr),
+            None => {
+                render_tree(tree, width, height, cr);
+                Ok(())
+            }
+        };
+    }
+
+    // Animated transitions are rendered on every frame until their
+    // animations have ended, the final state is then cached like a static
+    // transition.
+    let t = (ts_millis - transition.time_in as f64) / 1000.;
+    let animations = match trees.get(transition)? {
+        Parsed::Animated(animations) => animations,
+        Parsed::Static(_) => return Err(anyhow::anyhow!("unexpected static transition")),
+    };
+
+    let ended = t >= animations.end();
+    let mut cache = cache.filter(|_| ended);
+    if let Some(cache) = &mut cache {
+        if cache.paint_cached(transition, width, height, cr) {
+            return Ok(());
+        }
     }
+
+    let svg = animations.render(t);
+    let tree = usvg::Tree::from_str(&svg, &trees.options(transition))?;
+    if let Some(cache) = cache {
+        return cache.paint(transition, &tree, width, height, cr);
+    }
+
+    render_tree(&tree, width, height, cr);
+    Ok(())
+}
+
+fn render_tree(tree: &usvg::Tree, width: i32, height: i32, cr: &cairo::Context) {
+    let size = resvg::ScreenSize::new(width as u32, height as u32).unwrap();
+    resvg::backend_cairo::render_to_canvas(tree, &RESVG_OPTIONS, size, cr);
 }
 
 fn new_cairo_context(
@@ -0,0 +1,549 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Checks TSVG files for errors that would make `filter_init` fail and for
+// likely mistakes, e.g. overlapping transitions. Used by `tsvg-lint`.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use roxmltree::{Document, TextPos};
+use svg_common::fonts::{self, FontConfig, Fonts};
+
+use crate::cache::Raster;
+use crate::compression::{self, Compression};
+use crate::parse;
+use crate::subtitles::{self, Template};
+use crate::transition::Transition;
+use crate::trees::{self, Parsed};
+use crate::Format;
+
+const USAGE: &str =
+    "usage: tsvg-lint [--dump] [--size WIDTHxHEIGHT] [--compression none|gzip|zstd|xz] \
+[--format tsvg|srt|webvtt] [--template FILE] [--lenient] FILE...";
+
+struct Options {
+    dump: bool,
+    // The frame size used for the bounding boxes.
+    size: (i32, i32),
+    // Detected from the file if not given.
+    compression: Option<Compression>,
+    format: Format,
+    // SVG template for subtitles.
+    template: Option<String>,
+    // Broken transitions are skipped by the filter.
+    lenient: bool,
+}
+
+struct Entry {
+    // 1-based position among the transitions of the file.
+    ordinal: usize,
+    // Subtitle cues don't have a position.
+    pos: Option<TextPos>,
+    transition: Transition,
+    animated: bool,
+    bbox: Option<(i32, i32, i32, i32)>,
+}
+
+struct Report<'a> {
+    path: &'a str,
+    errors: usize,
+    warnings: usize,
+}
+
+impl<'a> Report<'a> {
+    fn error(&mut self, pos: Option<TextPos>, message: impl Display) {
+        self.errors += 1;
+        println!("{}: error: {}", self.location(pos), message);
+    }
+
+    fn warning(&mut self, pos: Option<TextPos>, message: impl Display) {
+        self.warnings += 1;
+        println!("{}: warning: {}", self.location(pos), message);
+    }
+
+    fn location(&self, pos: Option<TextPos>) -> String {
+        match pos {
+            Some(pos) => format!("{}:{}:{}", self.path, pos.row, pos.col),
+            None => String::from(self.path),
+        }
+    }
+}
+
+// Runs the linter with the command line arguments, without the program
+// name, and returns the exit code.
+pub fn main(args: &[String]) -> i32 {
+    let mut options = Options {
+        dump: false,
+        size: (1920, 1080),
+        compression: None,
+        format: Format::Tsvg,
+        template: None,
+        lenient: false,
+    };
+
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let valid = match arg.as_str() {
+            "--dump" => {
+                options.dump = true;
+                true
+            }
+
+            "--size" => match parse_size(args.next()) {
+                Some(size) => {
+                    options.size = size;
+                    true
+                }
+                None => false,
+            },
+
+            "--compression" => {
+                options.compression = args.next().and_then(|c| Compression::parse(c));
+                options.compression.is_some()
+            }
+
+            "--format" => match args.next().and_then(|f| Format::parse(f)) {
+                Some(format) => {
+                    options.format = format;
+                    true
+                }
+                None => false,
+            },
+
+            "--template" => {
+                options.template = args.next().cloned();
+                options.template.is_some()
+            }
+
+            "--lenient" => {
+                options.lenient = true;
+                true
+            }
+
+            a if a.starts_with("--") => false,
+            a => {
+                paths.push(a);
+                true
+            }
+        };
+
+        if !valid {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    }
+
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        return 2;
+    }
+
+    let mut errors = 0;
+    for path in paths {
+        errors += lint_file(path, &options);
+    }
+
+    if errors > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+fn parse_size(s: Option<&String>) -> Option<(i32, i32)> {
+    let mut parts = s?.splitn(2, 'x').map(|p| p.parse::<i32>().ok());
+    match (parts.next()?, parts.next()?) {
+        (Some(w), Some(h)) if w > 0 && h > 0 => Some((w, h)),
+        _ => None,
+    }
+}
+
+fn read(path: &str, compression: Option<Compression>) -> io::Result<String> {
+    let mut s = String::new();
+    compression::decoder(File::open(path)?, compression)?.read_to_string(&mut s)?;
+    Ok(s)
+}
+
+// Returns the number of errors.
+fn lint_file(path: &str, options: &Options) -> usize {
+    let mut report = Report {
+        path,
+        errors: 0,
+        warnings: 0,
+    };
+
+    let source = match read(path, options.compression) {
+        Ok(s) => s,
+        Err(e) => {
+            report.error(None, e);
+            return report.errors;
+        }
+    };
+
+    // Fonts are checked against the installed ones instead of failing.
+    let fonts = match FontConfig::default().load() {
+        Ok(fonts) => fonts,
+        Err(e) => {
+            report.error(None, e);
+            return report.errors;
+        }
+    };
+
+    let installed = fonts::installed_families();
+
+    let transitions = match options.format {
+        Format::Tsvg => parse_tsvg(&source, &fonts, path, options.lenient, &mut report),
+        Format::Subtitles(format) => {
+            let template = options.template.as_deref();
+            match parse_subtitles(&source, format, template) {
+                Ok(transitions) => transitions.into_iter().map(|t| (None, t)).collect(),
+                Err(e) => {
+                    report.error(None, e);
+                    Vec::new()
+                }
+            }
+        }
+    };
+
+    let mut usvg_options = crate::RESVG_OPTIONS.usvg.clone();
+    usvg_options.path = Some(path.into());
+
+    let mut entries = Vec::new();
+    for (pos, transition) in transitions {
+        let ordinal = transition.id;
+        let error = |e| format!("{}: {}", transition, e);
+        let parsed = trees::parse(&transition, &fonts, &usvg_options);
+        let animated = matches!(parsed, Ok((Parsed::Animated(_), _)));
+        let parsed = parsed
+            .and_then(|(p, warnings)| Ok((p.into_tree(&transition, &usvg_options)?, warnings)));
+
+        let tree = match parsed {
+            Ok((tree, warnings)) => {
+                for warning in warnings {
+                    report.warning(pos, error(warning));
+                }
+
+                tree
+            }
+
+            Err(e) if options.lenient => {
+                report.warning(pos, format!("{} (skipped)", error(e.to_string())));
+                continue;
+            }
+
+            Err(e) => {
+                report.error(pos, error(e.to_string()));
+                continue;
+            }
+        };
+
+        for family in fonts::families(&transition.merged_svg()) {
+            if !installed.contains(&family.to_lowercase()) {
+                report.warning(pos, error(format!("font family not found: {}", family)));
+            }
+        }
+
+        let bbox = if options.dump {
+            match Raster::render(&tree, options.size.0, options.size.1) {
+                Ok(raster) => raster.bbox(),
+                Err(e) => {
+                    report.warning(pos, error(e.to_string()));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        entries.push(Entry {
+            ordinal,
+            pos,
+            transition,
+            animated,
+            bbox,
+        });
+    }
+
+    check(&entries, &mut report);
+
+    if options.dump {
+        for e in &entries {
+            dump(e, &report);
+        }
+    }
+
+    let (on_screen, open_from) = on_screen(&entries);
+    println!(
+        "{}: {} transitions, {} on screen{}, {} errors, {} warnings",
+        path,
+        entries.len(),
+        format_time(on_screen),
+        open_from
+            .map(|t| format!(" and from {} on", format_time(t)))
+            .unwrap_or_default(),
+        report.errors,
+        report.warnings
+    );
+
+    report.errors
+}
+
+// Returns the transitions that can be parsed with their positions, the
+// errors are reported as warnings if `lenient` since the filter skips them.
+fn parse_tsvg(
+    source: &str,
+    fonts: &Fonts,
+    path: &str,
+    lenient: bool,
+    report: &mut Report,
+) -> Vec<(Option<TextPos>, Transition)> {
+    let doc = match Document::parse(source) {
+        Ok(doc) => doc,
+        Err(e) => {
+            report.error(Some(e.pos()), parse::xml_message(&e));
+            return Vec::new();
+        }
+    };
+
+    let transitions = match parse::Transitions::new(source, &doc, fonts, Path::new(path)) {
+        Ok(t) => t,
+        Err((pos, e)) => {
+            report.error(Some(pos), e);
+            return Vec::new();
+        }
+    };
+
+    let mut result = Vec::new();
+    for transition in transitions {
+        match transition {
+            Ok((pos, t)) => result.push((Some(pos), t)),
+            Err((pos, e)) if lenient => report.warning(Some(pos), format!("{} (skipped)", e)),
+            Err((pos, e)) => report.error(Some(pos), e),
+        }
+    }
+
+    result
+}
+
+fn parse_subtitles(
+    source: &str,
+    format: subtitles::Format,
+    template: Option<&str>,
+) -> anyhow::Result<Vec<Transition>> {
+    let template = template.map(fs::read_to_string).transpose()?;
+    let template = Template::parse(template.as_deref())?;
+    subtitles::transitions(source.as_bytes(), format, &template)
+}
+
+fn check(entries: &[Entry], report: &mut Report) {
+    let mut indexes: HashMap<usize, usize> = HashMap::new();
+    for e in entries {
+        let t = &e.transition;
+        if let Some(time_out) = t.time_out.filter(|&o| o <= t.time_in) {
+            report.error(
+                e.pos,
+                format!(
+                    "transition {}: time-out {} is not after time-in {}",
+                    e.ordinal,
+                    format_time(time_out),
+                    format_time(t.time_in)
+                ),
+            );
+        }
+
+        if let Some(other) = indexes.insert(t.index, e.ordinal) {
+            report.warning(
+                e.pos,
+                format!(
+                    "transition {}: duplicate index {}, also used by transition {}",
+                    e.ordinal, t.index, other
+                ),
+            );
+        }
+    }
+
+    for (a, b, from, to) in overlaps(entries) {
+        report.warning(
+            b.pos,
+            format!(
+                "transition {}: overlaps transition {} from {} to {}",
+                b.ordinal,
+                a.ordinal,
+                format_time(from),
+                to.map(format_time)
+                    .unwrap_or_else(|| String::from("the end")),
+            ),
+        );
+    }
+}
+
+// Returns the overlapping pairs, ordered by time-in, and the overlap.
+fn overlaps(entries: &[Entry]) -> Vec<(&Entry, &Entry, u64, Option<u64>)> {
+    let mut sorted: Vec<&Entry> = entries
+        .iter()
+        .filter(|e| e.transition.time_out.map(|o| o > e.transition.time_in) != Some(false))
+        .collect();
+    sorted.sort_by_key(|e| e.transition.time_in);
+
+    let mut result = Vec::new();
+    let mut active: Vec<&Entry> = Vec::new();
+    for e in sorted {
+        let time_in = e.transition.time_in;
+        active.retain(|a| a.transition.time_out.map(|o| o > time_in) != Some(false));
+        for a in &active {
+            let to = match (a.transition.time_out, e.transition.time_out) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+
+            result.push((*a, e, time_in, to));
+        }
+
+        active.push(e);
+    }
+
+    result
+}
+
+// Returns the total time with any transition on screen, and the start of
+// the transitions without time-out if any.
+fn on_screen(entries: &[Entry]) -> (u64, Option<u64>) {
+    let mut intervals: Vec<(u64, u64)> = entries
+        .iter()
+        .filter_map(|e| e.transition.time_out.map(|o| (e.transition.time_in, o)))
+        .filter(|(i, o)| o > i)
+        .collect();
+    intervals.sort_unstable();
+
+    let open_from = entries
+        .iter()
+        .filter(|e| e.transition.time_out.is_none())
+        .map(|e| e.transition.time_in)
+        .min();
+
+    let mut total = 0;
+    let mut end = 0;
+    for (time_in, time_out) in intervals {
+        let time_out = open_from.map(|o| time_out.min(o)).unwrap_or(time_out);
+        let start = time_in.max(end);
+        if time_out > start {
+            total += time_out - start;
+            end = time_out;
+        }
+    }
+
+    (total, open_from)
+}
+
+fn dump(e: &Entry, report: &Report) {
+    let t = &e.transition;
+    let mut flags: Vec<&str> = Vec::new();
+    if e.animated {
+        flags.push("animated");
+    }
+
+    if t.fade_in > 0 || t.fade_out > 0 {
+        flags.push("fade");
+    }
+
+    if t.motion.is_some() {
+        flags.push("motion");
+    }
+
+    println!(
+        "{}: transition {} index {} {} -> {} bbox {}{}",
+        report.location(e.pos),
+        e.ordinal,
+        t.index,
+        format_time(t.time_in),
+        t.time_out
+            .map(format_time)
+            .unwrap_or_else(|| String::from("end")),
+        e.bbox
+            .map(|(x, y, w, h)| format!("{},{} {}x{}", x, y, w, h))
+            .unwrap_or_else(|| String::from("empty")),
+        if flags.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", flags.join(", "))
+        }
+    );
+}
+
+// Formats milliseconds as `HH:MM:SS.mmm`.
+fn format_time(millis: u64) -> String {
+    let secs = millis / 1000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transition::test_transition;
+
+    fn entry(ordinal: usize, index: usize, time_in: u64, time_out: Option<u64>) -> Entry {
+        Entry {
+            ordinal,
+            pos: Some(TextPos::new(ordinal as u32, 1)),
+            transition: Transition {
+                index,
+                id: ordinal,
+                ..test_transition(time_in, time_out)
+            },
+            animated: false,
+            bbox: None,
+        }
+    }
+
+    #[test]
+    fn checks() {
+        let entries = vec![
+            entry(1, 0, 0, Some(2000)),
+            entry(2, 1, 1000, Some(3000)),
+            entry(3, 1, 5000, Some(5000)),
+            entry(4, 3, 6000, None),
+            entry(5, 4, 7000, Some(8000)),
+        ];
+
+        let mut report = Report {
+            path: "a.tsvg",
+            errors: 0,
+            warnings: 0,
+        };
+
+        check(&entries, &mut report);
+        assert_eq!(1, report.errors);
+        // A duplicate index and two overlaps.
+        assert_eq!(3, report.warnings);
+
+        let pairs: Vec<(usize, usize, u64, Option<u64>)> = overlaps(&entries)
+            .iter()
+            .map(|(a, b, from, to)| (a.ordinal, b.ordinal, *from, *to))
+            .collect();
+        assert_eq!(
+            vec![(1, 2, 1000, Some(2000)), (4, 5, 7000, Some(8000))],
+            pairs
+        );
+
+        assert_eq!((3000, Some(6000)), on_screen(&entries));
+        assert_eq!((0, None), on_screen(&[]));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!("00:00:00.000", format_time(0));
+        assert_eq!("01:02:03.045", format_time(3_723_045));
+    }
+}
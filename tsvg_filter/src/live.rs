@@ -0,0 +1,551 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Live mode, where transitions are read from an append-only TSVG stream,
+// e.g. a growing file or a named pipe, on a background thread. Every
+// complete transition is parsed and validated with usvg as it arrives and
+// sent to the filter, which inserts it into its tree without blocking.
+
+use std::fs::OpenOptions;
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use resvg::usvg;
+use roxmltree::{Document, TextPos};
+use svg_common::fonts::Fonts;
+
+use crate::defs::{self, Defs};
+use crate::parse::{self, SvgFiles};
+use crate::timing::Clock;
+use crate::transition::{self, Transition};
+use crate::trees;
+
+// How often to check for more data at the end of the stream.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub(crate) struct Reader {
+    receiver: Receiver<Transition>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reader {
+    pub(crate) fn start(path: &Path, fonts: Fonts, options: usvg::Options) -> Reader {
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let path = PathBuf::from(path);
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                if let Err(e) = read(&path, fonts, &options, &sender, &stop) {
+                    eprintln!("{}: {}", path.display(), e);
+                }
+            })
+        };
+
+        Reader {
+            receiver,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    // Returns the transitions parsed since the last call.
+    pub(crate) fn received(&self) -> Vec<Transition> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+// The thread never blocks for longer than the poll interval, so it stops
+// soon after the filter.
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn read(
+    path: &Path,
+    fonts: Fonts,
+    options: &usvg::Options,
+    sender: &Sender<Transition>,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    // Opening a named pipe without a writer would block otherwise, and so
+    // would reading from it while the writer is idle.
+    let mut file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+    let mut scanner = Scanner::default();
+    let mut stream = Stream::new(path, fonts.clone());
+    let mut buf = vec![0; 64 * 1024];
+    while !stop.load(Ordering::Relaxed) {
+        match file.read(&mut buf) {
+            Ok(0) => thread::sleep(POLL_INTERVAL),
+            Ok(n) => scanner.push(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+
+        while let Some(item) = scanner.next() {
+            let transition = match item.and_then(|item| stream.handle(item)) {
+                Ok(Handled::Transition(t)) => t,
+                Ok(Handled::None) => continue,
+                Ok(Handled::End) => return Ok(()),
+                Err(e) => {
+                    eprintln!("warning: {}:{} (skipped)", path.display(), e);
+                    continue;
+                }
+            };
+
+            // Checked here to not fail the filter when it becomes active.
+            match trees::parse_tree(&transition, &fonts, options) {
+                Ok((_, warnings)) => {
+                    for warning in warnings {
+                        eprintln!("warning: {}", transition.located(path, warning));
+                    }
+                }
+
+                Err(e) => {
+                    eprintln!("warning: {} (skipped)", transition.located(path, e));
+                    continue;
+                }
+            }
+
+            // The filter is gone.
+            if sender.send(transition).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+enum Handled {
+    Transition(Transition),
+    None,
+    End,
+}
+
+// The state of the stream after the root start tag.
+struct Stream<'a> {
+    path: &'a Path,
+    fonts: Fonts,
+    root: Option<(String, Clock)>,
+    defs: String,
+    shared_defs: Option<Defs>,
+    files: SvgFiles,
+    ordinal: usize,
+}
+
+impl<'a> Stream<'a> {
+    fn new(path: &'a Path, fonts: Fonts) -> Stream<'a> {
+        Stream {
+            path,
+            fonts,
+            root: None,
+            defs: String::new(),
+            shared_defs: None,
+            files: SvgFiles::default(),
+            ordinal: 0,
+        }
+    }
+
+    // Errors are prefixed with the position.
+    fn handle(&mut self, item: Item) -> anyhow::Result<Handled> {
+        let (text, pos) = match item {
+            Item::Root(tag, pos) => {
+                // Parsed as an empty document to get the attributes.
+                let source = root_document(&tag, "");
+                let doc = Document::parse(&source)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", pos, parse::xml_message(&e)))?;
+                let clock = Clock::from_root(&doc.root_element())
+                    .map_err(|e| anyhow::anyhow!("{}: {}", pos, e))?;
+                if tag.ends_with("/>") {
+                    return Ok(Handled::End);
+                }
+
+                self.root = Some((tag, clock));
+                return Ok(Handled::None);
+            }
+
+            Item::Element(text, pos) => (text, pos),
+            Item::End => return Ok(Handled::End),
+        };
+
+        let (tag, clock) = match &self.root {
+            Some(root) => root,
+            None => return Err(anyhow::anyhow!("{}: no valid transitions element", pos)),
+        };
+
+        let source = root_document(tag, &text);
+        let doc = Document::parse(&source)
+            .map_err(|e| anyhow::anyhow!("{}: {}", pos, parse::xml_message(&e)))?;
+        let node = doc.root_element().first_element_child().unwrap();
+        if defs::is_defs(&node) {
+            let defs = self
+                .fonts
+                .apply_fallbacks(&source, node)
+                .map_err(anyhow::Error::msg)?;
+            self.defs.push_str(&defs);
+            let generation = self.shared_defs.as_ref().map_or(0, |d| d.generation + 1);
+            self.shared_defs = Some(Defs {
+                generation,
+                svg: Arc::from(self.defs.as_str()),
+                namespaces: defs::namespaces(&doc.root_element()),
+            });
+            return Ok(Handled::None);
+        }
+
+        self.ordinal += 1;
+        let ordinal = self.ordinal;
+        let time_in = node.attribute("time-in").and_then(|v| clock.parse(v).ok());
+        let located =
+            |e| anyhow::anyhow!("{}: {}: {}", pos, transition::describe(ordinal, time_in), e);
+        if !node.has_tag_name("transition") {
+            let e = format!("unexpected element {}", node.tag_name().name());
+            return Err(located(e));
+        }
+
+        let transition =
+            parse::parse_transition(ordinal, ordinal, &node, clock, &mut self.files, self.path)
+                .map_err(|e| located(e.to_string()))?;
+
+        Ok(Handled::Transition(Transition {
+            pos: Some(pos),
+            defs: self.shared_defs.clone(),
+            ..transition
+        }))
+    }
+}
+
+// The root start tag with the content, to resolve namespace prefixes.
+fn root_document(tag: &str, content: &str) -> String {
+    match tag.strip_suffix("/>") {
+        Some(tag) => format!("{}>{}</transitions>", tag, content),
+        None => format!("{}{}</transitions>", tag, content),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Item {
+    // The root start tag and its position.
+    Root(String, TextPos),
+    // A complete element under the root and its position.
+    Element(String, TextPos),
+    // The root end tag.
+    End,
+}
+
+// Splits the data of the stream into the root start tag and the complete
+// elements under it, as the data arrives.
+#[derive(Default)]
+struct Scanner {
+    buf: Vec<u8>,
+    // Lines and characters before the start of buf on its line.
+    line: u32,
+    col: u32,
+    root: bool,
+    done: bool,
+}
+
+impl Scanner {
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    // Returns None until the next item is complete.
+    fn next(&mut self) -> Option<anyhow::Result<Item>> {
+        if self.done {
+            return None;
+        }
+
+        loop {
+            let start = self
+                .buf
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .unwrap_or(self.buf.len());
+            self.consume(start);
+
+            let buf = &self.buf[..];
+            if buf.is_empty() {
+                return None;
+            }
+
+            let skip_to: &[u8] = if buf.starts_with(b"<?") {
+                b"?>"
+            } else if buf.starts_with(b"<!--") {
+                b"-->"
+            } else if buf.len() < 4 && b"<!--".starts_with(buf) {
+                // Could still be a comment.
+                return None;
+            } else if !self.root && buf.starts_with(b"<!") {
+                b">"
+            } else {
+                break;
+            };
+
+            let end = find(buf, skip_to)? + skip_to.len();
+            self.consume(end);
+        }
+
+        let pos = TextPos::new(self.line + 1, self.col + 1);
+        let buf = &self.buf[..];
+        // A read can end in the middle of a tag name.
+        if partial_tag(buf, self.root) {
+            return None;
+        }
+
+        let item = if !self.root {
+            if !starts_with_tag(buf, b"transitions") {
+                Err(anyhow::anyhow!("{}: expected transitions element", pos))
+            } else {
+                let end = tag_end(buf)?;
+                self.root = true;
+                Ok(Item::Root(self.text(end), pos))
+            }
+        } else if buf.starts_with(b"</transitions") {
+            // Anything after the root is ignored.
+            self.buf = Vec::new();
+            self.done = true;
+            Ok(Item::End)
+        } else if let Some(name) = [&b"transition"[..], b"defs", b"style"]
+            .iter()
+            .find(|n| starts_with_tag(buf, n))
+        {
+            let end = tag_end(buf)?;
+            let end = if buf[..end].ends_with(b"/>") {
+                end
+            } else {
+                element_end(buf, end, name)?
+            };
+
+            Ok(Item::Element(self.text(end), pos))
+        } else {
+            Err(anyhow::anyhow!("{}: unexpected content", pos))
+        };
+
+        // Skips to the next tag after unexpected content.
+        if item.is_err() {
+            let end = find(&self.buf[1..], b"<").map(|e| e + 1);
+            self.consume(end.unwrap_or(self.buf.len()));
+        }
+
+        Some(item)
+    }
+
+    // Removes and returns the text up to the given end.
+    fn text(&mut self, end: usize) -> String {
+        let text = String::from_utf8_lossy(&self.buf[..end]).into_owned();
+        self.consume(end);
+        text
+    }
+
+    fn consume(&mut self, end: usize) {
+        for &b in &self.buf[..end] {
+            if b == b'\n' {
+                self.line += 1;
+                self.col = 0;
+            } else if b & 0xc0 != 0x80 {
+                // Not a continuation byte of a UTF-8 character.
+                self.col += 1;
+            }
+        }
+
+        self.buf.drain(..end);
+    }
+}
+
+fn find(buf: &[u8], s: &[u8]) -> Option<usize> {
+    buf.windows(s.len()).position(|w| w == s)
+}
+
+// Whether buf is the start of a tag that more data could complete, up to
+// the character after the name.
+fn partial_tag(buf: &[u8], root: bool) -> bool {
+    let tags: &[&[u8]] = if root {
+        &[b"<transition", b"<defs", b"<style", b"</transitions"]
+    } else {
+        &[b"<transitions"]
+    };
+
+    tags.iter()
+        .any(|tag| buf.len() <= tag.len() && tag.starts_with(buf))
+}
+
+fn starts_with_tag(buf: &[u8], name: &[u8]) -> bool {
+    buf.len() > name.len() + 1
+        && buf[0] == b'<'
+        && &buf[1..=name.len()] == name
+        && (buf[name.len() + 1].is_ascii_whitespace() || b"/>".contains(&buf[name.len() + 1]))
+}
+
+// Returns the end of the start tag, attribute values may contain `>`.
+fn tag_end(buf: &[u8]) -> Option<usize> {
+    let mut quote = None;
+    for (i, &b) in buf.iter().enumerate() {
+        match (b, quote) {
+            (b'"', None) | (b'\'', None) => quote = Some(b),
+            (b, Some(q)) if b == q => quote = None,
+            (b'>', None) => return Some(i + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// Returns the end of the end tag, skipping CDATA sections.
+fn element_end(buf: &[u8], start: usize, name: &[u8]) -> Option<usize> {
+    let end_tag = [&b"</"[..], name, b">"].concat();
+    let mut pos = start;
+    loop {
+        let end = find(&buf[pos..], &end_tag)?;
+        match find(&buf[pos..], b"<![CDATA[") {
+            Some(cdata) if cdata < end => pos += cdata + find(&buf[pos + cdata..], b"]]>")? + 3,
+            _ => return Some(pos + end + end_tag.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(scanner: &mut Scanner) -> Vec<Item> {
+        let mut items = Vec::new();
+        while let Some(item) = scanner.next() {
+            items.push(item.unwrap());
+        }
+
+        items
+    }
+
+    #[test]
+    fn scanner() {
+        let mut scanner = Scanner::default();
+        scanner.push(b"<?xml version=\"1.0\"?>\n<transitions fps=\"25\"");
+        assert!(items(&mut scanner).is_empty());
+
+        scanner.push(b">\n<!-- a -->\n<transition time-in=\"0\"><![CDATA[<svg>");
+        assert_eq!(
+            vec![Item::Root(
+                String::from("<transitions fps=\"25\">"),
+                TextPos::new(2, 1)
+            )],
+            items(&mut scanner)
+        );
+
+        scanner.push(b"<text>a</transition></text></svg>]]></transition>\n<defs/>");
+        scanner.push(b"\n<transition time-in=\"10\" src=\"a>b.svg\"/></transitions>");
+        assert_eq!(
+            vec![
+                Item::Element(
+                    String::from(concat!(
+                        "<transition time-in=\"0\"><![CDATA[<svg>",
+                        "<text>a</transition></text></svg>]]></transition>"
+                    )),
+                    TextPos::new(4, 1)
+                ),
+                Item::Element(String::from("<defs/>"), TextPos::new(5, 1)),
+                Item::Element(
+                    String::from("<transition time-in=\"10\" src=\"a>b.svg\"/>"),
+                    TextPos::new(6, 1)
+                ),
+                Item::End,
+            ],
+            items(&mut scanner)
+        );
+
+        let mut scanner = Scanner::default();
+        scanner.push("<transitions>\n<foo/>å<transition time-in=\"0\"/>".as_bytes());
+        assert!(scanner.next().unwrap().is_ok());
+        assert!(scanner.next().unwrap().is_err());
+        assert_eq!(
+            Item::Element(
+                String::from("<transition time-in=\"0\"/>"),
+                TextPos::new(2, 8)
+            ),
+            scanner.next().unwrap().unwrap()
+        );
+    }
+
+    #[test]
+    fn scanner_split() {
+        let data = concat!(
+            "<?xml version=\"1.0\"?><!-- a --><transitions fps=\"25\"><!-- b -->",
+            "<defs/><style>text {}</style><transition time-in=\"0\"/>",
+            "<transition time-in=\"1\"><![CDATA[<svg/>]]></transition></transitions>"
+        );
+
+        let mut scanner = Scanner::default();
+        scanner.push(data.as_bytes());
+        let expected = items(&mut scanner);
+        assert_eq!(6, expected.len());
+
+        // Every read ends at the next byte, also inside tag names.
+        let mut scanner = Scanner::default();
+        let mut split = Vec::new();
+        for b in data.bytes() {
+            scanner.push(&[b]);
+            split.extend(items(&mut scanner));
+        }
+
+        assert_eq!(expected, split);
+
+        for data in &["<transiti", "</transi", "<!-", "<", "<defs"] {
+            let mut scanner = Scanner::default();
+            scanner.push(b"<transitions>");
+            scanner.next().unwrap().unwrap();
+            scanner.push(data.as_bytes());
+            assert!(scanner.next().is_none());
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let fonts = svg_common::fonts::FontConfig::default().load().unwrap();
+        let mut stream = Stream::new(Path::new("live.tsvg"), fonts);
+        let root = String::from("<transitions timebase=\"frames\" fps=\"25\">");
+        let root = Item::Root(root, TextPos::new(1, 1));
+        assert!(matches!(stream.handle(root), Ok(Handled::None)));
+
+        let defs = Item::Element(String::from("<style>text {}</style>"), TextPos::new(2, 1));
+        assert!(matches!(stream.handle(defs), Ok(Handled::None)));
+
+        let s = "<transition time-in=\"25\" duration=\"50\"><![CDATA[<svg/>]]></transition>";
+        let transition = match stream.handle(Item::Element(String::from(s), TextPos::new(3, 1))) {
+            Ok(Handled::Transition(t)) => t,
+            _ => panic!(),
+        };
+
+        assert_eq!(1000, transition.time_in);
+        assert_eq!(Some(3000), transition.time_out);
+        assert_eq!(Some(TextPos::new(3, 1)), transition.pos);
+        assert_eq!("<svg><style>text {}</style></svg>", transition.merged_svg());
+
+        // The SVG is checked by the reader before the transition is sent.
+        let s = "<transition time-in=\"50\"><![CDATA[<svg><g></svg>]]></transition>";
+        let transition = match stream.handle(Item::Element(String::from(s), TextPos::new(4, 3))) {
+            Ok(Handled::Transition(t)) => t,
+            _ => panic!(),
+        };
+
+        let e = trees::parse_tree(&transition, &stream.fonts, &usvg::Options::default()).err();
+        assert!(e.unwrap().to_string().starts_with("invalid SVG: "));
+    }
+}
@@ -0,0 +1,149 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use svg_common::anim::Easing;
+
+// A keyframe as given in the TSVG, properties not given keep the value of
+// the previous keyframe.
+pub(crate) struct Keyframe {
+    // Milliseconds relative to the time-in of the transition.
+    pub(crate) time: u64,
+    pub(crate) translate: Option<(f64, f64)>,
+    pub(crate) scale: Option<(f64, f64)>,
+    pub(crate) opacity: Option<f64>,
+    // Used from this keyframe to the next.
+    pub(crate) easing: Easing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct State {
+    // Pixels in the output frame.
+    pub(crate) translate: (f64, f64),
+    // Around the center of the output frame.
+    pub(crate) scale: (f64, f64),
+    pub(crate) opacity: f64,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            translate: (0., 0.),
+            scale: (1., 1.),
+            opacity: 1.,
+        }
+    }
+}
+
+impl State {
+    fn interpolate(&self, to: &State, t: f64) -> State {
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        State {
+            translate: (
+                lerp(self.translate.0, to.translate.0),
+                lerp(self.translate.1, to.translate.1),
+            ),
+            scale: (
+                lerp(self.scale.0, to.scale.0),
+                lerp(self.scale.1, to.scale.1),
+            ),
+            opacity: lerp(self.opacity, to.opacity),
+        }
+    }
+}
+
+// Keyframed translation, scale and opacity of a transition.
+pub(crate) struct Motion {
+    keyframes: Vec<(u64, State, Easing)>,
+}
+
+impl Motion {
+    pub(crate) fn new(mut keyframes: Vec<Keyframe>) -> Option<Motion> {
+        if keyframes.is_empty() {
+            return None;
+        }
+
+        keyframes.sort_by_key(|k| k.time);
+        let mut state = State::default();
+        let keyframes = keyframes
+            .into_iter()
+            .map(|k| {
+                state.translate = k.translate.unwrap_or(state.translate);
+                state.scale = k.scale.unwrap_or(state.scale);
+                state.opacity = k.opacity.unwrap_or(state.opacity);
+                (k.time, state, k.easing)
+            })
+            .collect();
+
+        Some(Motion { keyframes })
+    }
+
+    // Returns the state at the given milliseconds relative to time-in.
+    pub(crate) fn at(&self, t_millis: f64) -> State {
+        let next = self
+            .keyframes
+            .iter()
+            .position(|(time, _, _)| t_millis < *time as f64);
+
+        match next {
+            Some(0) => self.keyframes[0].1,
+            None => self.keyframes[self.keyframes.len() - 1].1,
+            Some(i) => {
+                let (t0, from, easing) = self.keyframes[i - 1];
+                let (t1, to, _) = self.keyframes[i];
+                let t = (t_millis - t0 as f64) / (t1 - t0) as f64;
+                from.interpolate(&to, easing.apply(t))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn motion() {
+        assert!(Motion::new(vec![]).is_none());
+
+        let motion = Motion::new(vec![
+            Keyframe {
+                time: 1000,
+                translate: Some((0., 0.)),
+                scale: None,
+                opacity: None,
+                easing: Easing::Linear,
+            },
+            Keyframe {
+                time: 0,
+                translate: Some((-400., 0.)),
+                scale: Some((2., 2.)),
+                opacity: Some(0.),
+                easing: Easing::Linear,
+            },
+        ])
+        .unwrap();
+
+        let start = State {
+            translate: (-400., 0.),
+            scale: (2., 2.),
+            opacity: 0.,
+        };
+
+        let end = State {
+            translate: (0., 0.),
+            ..start
+        };
+
+        assert_eq!(start, motion.at(-100.));
+        assert_eq!(
+            State {
+                translate: (-100., 0.),
+                ..start
+            },
+            motion.at(750.)
+        );
+        assert_eq!(end, motion.at(1000.));
+        assert_eq!(end, motion.at(5000.));
+    }
+}
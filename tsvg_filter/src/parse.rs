@@ -2,66 +2,254 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{io, string::String};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{io, iter, string::String};
 
-use resvg::usvg;
-use roxmltree::{Document, Node, NodeType};
+use roxmltree::{Children, Document, Node, NodeType, TextPos};
+use svg_common::anim::Easing;
+use svg_common::fonts::Fonts;
 
-use crate::transition::{Transition, Tree};
+use crate::defs::{self, Defs};
+use crate::motion::{Keyframe, Motion};
+use crate::timing::Clock;
+use crate::transition::{self, Transition};
 
-pub(crate) fn parse_tsvg<R: io::Read>(mut source: R) -> anyhow::Result<Tree> {
+// Only the timing of the transitions is parsed, their SVG is checked when
+// it's parsed for rendering. Errors are prefixed with the path and position
+// in the TSVG file, and broken transitions are skipped with a warning if
+// `lenient`.
+pub(crate) fn parse_tsvg<R: io::Read>(
+    mut source: R,
+    fonts: &Fonts,
+    tsvg_path: &Path,
+    lenient: bool,
+) -> anyhow::Result<Vec<Transition>> {
     let mut s = String::new();
     source.read_to_string(&mut s)?;
 
-    let doc = Document::parse(&s)?;
-    let root = doc.root_element();
-    let root_name = root.tag_name().name();
-    if root_name != "transitions" {
-        return Err(anyhow::anyhow!("unexpected root element {}", root_name));
+    let doc = Document::parse(&s).map_err(|e| located(tsvg_path, e.pos(), xml_message(&e)))?;
+    let transitions = Transitions::new(&s, &doc, fonts, tsvg_path)
+        .map_err(|(pos, e)| located(tsvg_path, pos, e))?;
+
+    let mut result = Vec::new();
+    for transition in transitions {
+        match transition {
+            Ok((_, t)) => result.push(t),
+            Err((pos, e)) if lenient => {
+                eprintln!("warning: {} (skipped)", located(tsvg_path, pos, e))
+            }
+            Err((pos, e)) => return Err(located(tsvg_path, pos, e)),
+        }
     }
 
-    let mut transitions = Vec::new();
-    for (i, c) in root.children().enumerate() {
-        let node_type = c.node_type();
-        match node_type {
-            NodeType::Text => {
-                let text = c.text().unwrap().trim();
-                if text.is_empty() {
-                    continue;
-                }
+    Ok(result)
+}
 
-                return Err(anyhow::anyhow!("unexpected text node"));
-            }
+// The transitions of a TSVG document in document order with their
+// positions, or the errors of the other children of the root and of the
+// transitions that can't be parsed with their positions.
+pub(crate) struct Transitions<'a, 'input> {
+    children: iter::Enumerate<Children<'a, 'input>>,
+    positions: Positions<'input>,
+    clock: Clock,
+    defs: Option<Defs>,
+    files: SvgFiles,
+    tsvg_path: &'a Path,
+    ordinal: usize,
+}
 
-            NodeType::Element => {
-                let name = c.tag_name().name();
-                if name != "transition" {
-                    return Err(anyhow::anyhow!("unexpected element {}", name));
-                }
+impl<'a, 'input> Transitions<'a, 'input> {
+    // Fails with the position of the root element if it isn't valid.
+    pub(crate) fn new(
+        source: &'input str,
+        doc: &'a Document<'input>,
+        fonts: &'a Fonts,
+        tsvg_path: &'a Path,
+    ) -> Result<Transitions<'a, 'input>, (TextPos, String)> {
+        let root = doc.root_element();
+        let mut positions = Positions::new(source);
+        let root_pos = positions.at(root.range().start);
+        let root_name = root.tag_name().name();
+        if root_name != "transitions" {
+            return Err((root_pos, format!("unexpected root element {}", root_name)));
+        }
+
+        let clock = Clock::from_root(&root).map_err(|e| (root_pos, e.to_string()))?;
+        let defs = defs::collect(source, &root, fonts).map_err(|e| (root_pos, e.to_string()))?;
+        Ok(Transitions {
+            children: root.children().enumerate(),
+            positions,
+            clock,
+            defs,
+            files: SvgFiles::default(),
+            tsvg_path,
+            ordinal: 0,
+        })
+    }
 
-                transitions.push(parse_transition(i, &c)?);
+    fn transition(
+        &mut self,
+        i: usize,
+        node: &Node,
+        pos: TextPos,
+    ) -> Result<(TextPos, Transition), (TextPos, String)> {
+        self.ordinal += 1;
+        let ordinal = self.ordinal;
+        match parse_transition(
+            i,
+            ordinal,
+            node,
+            &self.clock,
+            &mut self.files,
+            self.tsvg_path,
+        ) {
+            Ok(t) => {
+                let t = Transition {
+                    pos: Some(pos),
+                    defs: self.defs.clone(),
+                    ..t
+                };
+                Ok((pos, t))
             }
 
-            _ => {
-                return Err(anyhow::anyhow!("unexpected node type {:?}", node_type));
+            Err(e) => {
+                let time_in = node
+                    .attribute("time-in")
+                    .and_then(|v| self.clock.parse(v).ok());
+                Err((
+                    pos,
+                    format!("{}: {}", transition::describe(ordinal, time_in), e),
+                ))
             }
         }
     }
+}
+
+impl<'a, 'input> Iterator for Transitions<'a, 'input> {
+    type Item = Result<(TextPos, Transition), (TextPos, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((i, c)) = self.children.next() {
+            let pos = self.positions.at(c.range().start);
+            let e = match c.node_type() {
+                NodeType::Text if c.text().unwrap().trim().is_empty() => continue,
+                NodeType::Text => String::from("unexpected text node"),
+                NodeType::Element if defs::is_defs(&c) => continue,
+                NodeType::Element if c.has_tag_name("transition") => {
+                    return Some(self.transition(i, &c, pos));
+                }
+
+                NodeType::Element => format!("unexpected element {}", c.tag_name().name()),
+                node_type => format!("unexpected node type {:?}", node_type),
+            };
+
+            return Some(Err((pos, e)));
+        }
+
+        None
+    }
+}
+
+fn located(tsvg_path: &Path, pos: TextPos, e: impl Display) -> anyhow::Error {
+    anyhow::anyhow!("{}:{}:{}: {}", tsvg_path.display(), pos.row, pos.col, e)
+}
+
+// Returns the message of an XML error without its position.
+pub(crate) fn xml_message(e: &roxmltree::Error) -> String {
+    e.to_string().replace(&format!(" at {}", e.pos()), "")
+}
+
+// Converts byte offsets in increasing order to positions, without
+// scanning from the start of the text like `Document::text_pos_at`.
+pub(crate) struct Positions<'a> {
+    text: &'a str,
+    offset: usize,
+    pos: TextPos,
+}
+
+impl<'a> Positions<'a> {
+    pub(crate) fn new(text: &'a str) -> Positions<'a> {
+        Positions {
+            text,
+            offset: 0,
+            pos: TextPos::new(1, 1),
+        }
+    }
+
+    pub(crate) fn at(&mut self, offset: usize) -> TextPos {
+        if offset < self.offset {
+            *self = Positions::new(self.text);
+        }
+
+        for c in self.text[self.offset..offset].chars() {
+            if c == '\n' {
+                self.pos = TextPos::new(self.pos.row + 1, 1);
+            } else {
+                self.pos.col += 1;
+            }
+        }
 
-    Ok(Tree::new(transitions))
+        self.offset = offset;
+        self.pos
+    }
 }
 
-fn parse_transition(idx: usize, node: &Node) -> anyhow::Result<Transition> {
+// The index defaults to `idx`, the position among the children of the root.
+pub(crate) fn parse_transition(
+    idx: usize,
+    id: usize,
+    node: &Node,
+    clock: &Clock,
+    files: &mut SvgFiles,
+    tsvg_path: &Path,
+) -> anyhow::Result<Transition> {
     let time_in = node
         .attribute("time-in")
-        .map(|v| v.parse::<u64>())
+        .map(|v| clock.parse(v))
         .transpose()?
         .ok_or(anyhow::anyhow!("no time-in attribute in transition"))?;
 
-    let time_out = node
-        .attribute("time-out")
-        .map(|v| v.parse::<u64>())
-        .transpose()?;
+    let time_out = match (node.attribute("time-out"), node.attribute("duration")) {
+        (Some(_), Some(_)) => {
+            return Err(anyhow::anyhow!(
+                "both time-out and duration attributes in transition"
+            ))
+        }
+
+        (Some(v), None) => Some(clock.parse(v)?),
+        (None, Some(v)) => Some(time_in + clock.parse(v)?),
+        (None, None) => None,
+    };
+
+    let fade_in = node
+        .attribute("fade-in")
+        .map(|v| clock.parse(v))
+        .transpose()?
+        .unwrap_or(0);
+
+    let fade_out = node
+        .attribute("fade-out")
+        .map(|v| clock.parse(v))
+        .transpose()?
+        .unwrap_or(0);
+
+    if fade_out > 0 && time_out.is_none() {
+        return Err(anyhow::anyhow!("fade-out without time-out in transition"));
+    }
+
+    let opacity = node
+        .attribute("opacity")
+        .map(|v| v.parse::<f64>())
+        .transpose()?
+        .unwrap_or(1.);
+
+    if !(0. ..=1.).contains(&opacity) {
+        return Err(anyhow::anyhow!("invalid opacity {} in transition", opacity));
+    }
 
     let index = node
         .attribute("index")
@@ -69,39 +257,266 @@ fn parse_transition(idx: usize, node: &Node) -> anyhow::Result<Transition> {
         .transpose()?
         .unwrap_or(idx);
 
-    let tree = parse_svg(node)?;
+    let keyframes = node
+        .children()
+        .filter(|c| c.is_element())
+        .map(|c| parse_keyframe(&c, clock))
+        .collect::<anyhow::Result<Vec<Keyframe>>>()?;
+
+    // The SVG itself is checked and parsed when it's first rendered.
+    let (svg, src) = match node.attribute("src") {
+        Some(src) => {
+            if svg_node(node).is_some() {
+                return Err(anyhow::anyhow!("both src and SVG data in transition"));
+            }
+
+            let file = files.load(src, tsvg_path)?;
+            let src = (file.id, file.path.clone());
+            (file.svg.clone(), Some(src))
+        }
+
+        None => (Arc::from(parse_svg(node)?), None),
+    };
+
     Ok(Transition {
         time_in,
         time_out,
         index,
-        tree,
+        id,
+        svg,
+        src,
+        fade_in,
+        fade_out,
+        opacity,
+        motion: Motion::new(keyframes),
+        pos: None,
+        defs: None,
     })
 }
 
-fn parse_svg(transition_node: &Node) -> anyhow::Result<usvg::Tree> {
-    match transition_node.first_child() {
-        None => return Err(anyhow::anyhow!("missing SVG data in transition")),
+struct SvgFile {
+    id: usize,
+    path: Arc<Path>,
+    svg: Arc<str>,
+}
 
-        Some(c) => {
-            let node_type = c.node_type();
-            if node_type != NodeType::Text {
-                return Err(anyhow::anyhow!(
-                    "unexpected {:?} child node in transition",
-                    node_type
-                ));
-            }
+// The SVG files referenced by transitions with `src`, loaded once.
+#[derive(Default)]
+pub(crate) struct SvgFiles {
+    files: HashMap<PathBuf, SvgFile>,
+}
 
-            if c.has_siblings() {
-                return Err(anyhow::anyhow!("unexpeted multiple children in transition"));
-            }
+impl SvgFiles {
+    // Paths are relative to the TSVG file.
+    fn load(&mut self, src: &str, tsvg_path: &Path) -> anyhow::Result<&SvgFile> {
+        let path = tsvg_path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(src);
 
-            let text = c.text().unwrap().trim();
-            if text.is_empty() {
-                Err(anyhow::anyhow!("empty SVG data in transition"))
-            } else {
-                let tree = usvg::Tree::from_str(text, &super::RESVG_OPTIONS.usvg)?;
-                Ok(tree)
-            }
+        if !self.files.contains_key(&path) {
+            let file = SvgFile::load(self.files.len(), &path)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+            self.files.insert(path.clone(), file);
         }
+
+        Ok(&self.files[&path])
+    }
+}
+
+impl SvgFile {
+    fn load(id: usize, path: &Path) -> anyhow::Result<SvgFile> {
+        let svg = fs::read_to_string(path)?;
+        Ok(SvgFile {
+            id,
+            path: Arc::from(path),
+            svg: Arc::from(svg.trim()),
+        })
+    }
+}
+
+fn parse_keyframe(node: &Node, clock: &Clock) -> anyhow::Result<Keyframe> {
+    let name = node.tag_name().name();
+    if name != "keyframe" {
+        return Err(anyhow::anyhow!("unexpected element {} in transition", name));
+    }
+
+    let time = node
+        .attribute("time")
+        .map(|v| clock.parse(v))
+        .transpose()?
+        .ok_or(anyhow::anyhow!("no time attribute in keyframe"))?;
+
+    let translate = node
+        .attribute("translate")
+        .map(|v| parse_pair(v, false))
+        .transpose()?;
+
+    let scale = node
+        .attribute("scale")
+        .map(|v| parse_pair(v, true))
+        .transpose()?;
+
+    let opacity = node
+        .attribute("opacity")
+        .map(|v| v.parse::<f64>())
+        .transpose()?;
+
+    if let Some(o) = opacity.filter(|o| !(0. ..=1.).contains(o)) {
+        return Err(anyhow::anyhow!("invalid opacity {} in keyframe", o));
+    }
+
+    let easing = match node.attribute("easing") {
+        Some(v) => Easing::parse(v).ok_or(anyhow::anyhow!("invalid easing {}", v))?,
+        None => Easing::Linear,
+    };
+
+    Ok(Keyframe {
+        time,
+        translate,
+        scale,
+        opacity,
+        easing,
+    })
+}
+
+// Parses "x y" or "x,y", or a single value used for both if `uniform`.
+fn parse_pair(s: &str, uniform: bool) -> anyhow::Result<(f64, f64)> {
+    let values = s
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()?;
+
+    match values[..] {
+        [x, y] if x.is_finite() && y.is_finite() => Ok((x, y)),
+        [v] if uniform && v.is_finite() => Ok((v, v)),
+        _ => Err(anyhow::anyhow!("invalid value {}", s)),
+    }
+}
+
+fn parse_svg<'a>(transition_node: &Node<'a, '_>) -> anyhow::Result<&'a str> {
+    if !transition_node.has_children() {
+        return Err(anyhow::anyhow!("missing SVG data in transition"));
+    }
+
+    // Keyframe elements may surround the SVG data.
+    let mut svg = None;
+    for c in transition_node.children().filter(|c| !c.is_element()) {
+        let node_type = c.node_type();
+        if node_type != NodeType::Text {
+            return Err(anyhow::anyhow!(
+                "unexpected {:?} child node in transition",
+                node_type
+            ));
+        }
+
+        let text = c.text().unwrap().trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        if svg.is_some() {
+            return Err(anyhow::anyhow!("unexpeted multiple children in transition"));
+        }
+
+        svg = Some(text);
+    }
+
+    svg.ok_or(anyhow::anyhow!("empty SVG data in transition"))
+}
+
+// Returns the text node with the SVG data of a transition.
+fn svg_node<'a, 'input>(transition_node: &Node<'a, 'input>) -> Option<Node<'a, 'input>> {
+    transition_node
+        .children()
+        .find(|c| c.is_text() && !c.text().unwrap().trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transition::Tree;
+    use svg_common::fonts::FontConfig;
+
+    fn parse(s: &str, lenient: bool) -> anyhow::Result<Tree> {
+        let fonts = FontConfig::default().load().unwrap();
+        parse_tsvg(s.as_bytes(), &fonts, Path::new("a.tsvg"), lenient).map(Tree::new)
+    }
+
+    #[test]
+    fn errors() {
+        let e = parse("<transitions>\n  <foo/>\n</transitions>", false).err();
+        assert_eq!("a.tsvg:2:3: unexpected element foo", e.unwrap().to_string());
+
+        let s = r#"<transitions>
+<transition time-in="0" time-out="500"><![CDATA[<svg/>]]></transition>
+<transition time-in="1000" opacity="2"><![CDATA[<svg/>]]></transition>
+</transitions>"#;
+        assert_eq!(
+            "a.tsvg:3:1: transition 2 (time-in 1000ms): invalid opacity 2 in transition",
+            parse(s, false).err().unwrap().to_string()
+        );
+
+        let tree = parse(s, true).unwrap();
+        assert_eq!(1, tree.search(0.).len());
+        assert!(tree.search(1000.).is_empty());
+
+        // The SVG is only checked when it's parsed for rendering.
+        let s = r#"<transitions>
+<transition time-in="0"><![CDATA[<svg><g></svg>]]></transition>
+</transitions>"#;
+        assert!(parse(s, false).is_ok());
+    }
+
+    #[test]
+    fn src() {
+        let dir = std::env::temp_dir().join("tsvg_filter_parse_src");
+        fs::create_dir_all(dir.join("graphics")).unwrap();
+        fs::write(dir.join("graphics/strap.svg"), "<svg/>").unwrap();
+
+        let s = r#"<transitions>
+<transition time-in="0" time-out="1000" src="graphics/strap.svg"/>
+<transition time-in="2000" time-out="3000" src="graphics/strap.svg">
+<keyframe time="0" opacity="0"/>
+</transition>
+</transitions>"#;
+        let fonts = FontConfig::default().load().unwrap();
+        let transitions = parse_tsvg(s.as_bytes(), &fonts, &dir.join("a.tsvg"), false);
+        let tree = Tree::new(transitions.unwrap());
+        let (a, b) = (tree.search(0.)[0], tree.search(2000.)[0]);
+        assert!(Arc::ptr_eq(&a.svg, &b.svg));
+        assert!(a.svg_key() == b.svg_key());
+        assert_eq!("<svg/>", &*a.svg);
+
+        let s = r#"<transitions>
+<transition time-in="0" src="graphics/missing.svg"/>
+</transitions>"#;
+        let e = parse_tsvg(s.as_bytes(), &fonts, &dir.join("a.tsvg"), false).err();
+        assert!(e.unwrap().to_string().contains("graphics/missing.svg: "));
+    }
+
+    #[test]
+    fn defs() {
+        let s = r##"<transitions>
+<defs><path id="p0" d="M0 0h10"/></defs>
+<transition time-in="0"><![CDATA[<svg><use href="#p0"/></svg>]]></transition>
+<style>path { fill: red }</style>
+</transitions>"##;
+        let tree = parse(s, false).unwrap();
+        assert_eq!(
+            r##"<svg><defs><path id="p0" d="M0 0h10"/></defs><style>path { fill: red }</style><use href="#p0"/></svg>"##,
+            tree.search(0.)[0].merged_svg()
+        );
+    }
+
+    #[test]
+    fn positions() {
+        let s = "ab\ncd\n\nåä";
+        let mut positions = Positions::new(s);
+        assert_eq!(TextPos::new(1, 2), positions.at(1));
+        assert_eq!(TextPos::new(2, 2), positions.at(4));
+        assert_eq!(TextPos::new(4, 2), positions.at(9));
+        assert_eq!(TextPos::new(1, 1), positions.at(0));
     }
 }
@@ -0,0 +1,502 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// SRT and WebVTT subtitles, laid out as SVG text in a template.
+
+use std::io;
+
+use roxmltree::Document;
+
+use crate::timing::Clock;
+use crate::transition::Transition;
+
+// Replaced with the text elements of a cue.
+const PLACEHOLDER: &str = "{cues}";
+
+const DEFAULT_TEMPLATE: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1920 1080" data-line-height="64" data-margin="54">
+<defs>
+<filter id="box" x="-0.03" y="-0.08" width="1.06" height="1.16">
+<feFlood flood-color="black" flood-opacity="0.6" result="box"/>
+<feComposite in="SourceGraphic" in2="box" operator="over"/>
+</filter>
+</defs>
+<style>
+.cue { font-family: sans-serif; font-size: 52px; fill: white; stroke: black; stroke-width: 2px; filter: url(#box) }
+</style>
+{cues}
+</svg>"##;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+    Srt,
+    WebVtt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Start,
+    Center,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Line {
+    Auto,
+    // Line number, negative numbers count from the bottom.
+    Number(i32),
+    // Percentage of the height and the alignment of the cue box to it.
+    Percent(f64, Align),
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Span {
+    text: String,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct Cue {
+    start: u64,
+    end: u64,
+    lines: Vec<Vec<Span>>,
+    line: Line,
+    // Percentage of the width.
+    position: Option<f64>,
+    align: Align,
+}
+
+// An SVG document with a `{cues}` placeholder, the root element may set
+// the line height and the margin with `data-line-height` and `data-margin`.
+pub(crate) struct Template {
+    svg: String,
+    width: f64,
+    height: f64,
+    line_height: f64,
+    margin: f64,
+}
+
+impl Template {
+    pub(crate) fn parse(svg: Option<&str>) -> anyhow::Result<Template> {
+        let svg = svg.unwrap_or(DEFAULT_TEMPLATE);
+        if !svg.contains(PLACEHOLDER) {
+            return Err(anyhow::anyhow!(
+                "no {} placeholder in template",
+                PLACEHOLDER
+            ));
+        }
+
+        let doc = Document::parse(svg)?;
+        let root = doc.root_element();
+        let number = |name| -> anyhow::Result<Option<f64>> {
+            Ok(root
+                .attribute(name)
+                .map(|v: &str| v.trim_end_matches("px").parse::<f64>())
+                .transpose()?)
+        };
+
+        let (width, height) = match root.attribute("viewBox") {
+            Some(v) => {
+                let v = v
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.parse::<f64>())
+                    .collect::<Result<Vec<f64>, _>>()?;
+                if v.len() != 4 {
+                    return Err(anyhow::anyhow!("invalid viewBox in template"));
+                }
+
+                (v[2], v[3])
+            }
+
+            None => match (number("width")?, number("height")?) {
+                (Some(w), Some(h)) => (w, h),
+                _ => return Err(anyhow::anyhow!("no viewBox or size in template")),
+            },
+        };
+
+        let line_height = number("data-line-height")?.unwrap_or(height / 16.);
+        let margin = number("data-margin")?.unwrap_or(height / 20.);
+        Ok(Template {
+            svg: String::from(svg),
+            width,
+            height,
+            line_height,
+            margin,
+        })
+    }
+
+    fn render(&self, cue: &Cue) -> String {
+        let (x, anchor) = match (cue.position, cue.align) {
+            (Some(p), Align::Start) => (p * self.width / 100., "start"),
+            (Some(p), Align::Center) => (p * self.width / 100., "middle"),
+            (Some(p), Align::End) => (p * self.width / 100., "end"),
+            (None, Align::Start) => (self.margin, "start"),
+            (None, Align::Center) => (self.width / 2., "middle"),
+            (None, Align::End) => (self.width - self.margin, "end"),
+        };
+
+        // The baseline is placed at 80% of the line height.
+        let box_height = cue.lines.len() as f64 * self.line_height;
+        let top = match cue.line {
+            Line::Auto => self.height - self.margin - box_height,
+            Line::Number(n) if n >= 0 => self.margin + n as f64 * self.line_height,
+            Line::Number(n) => {
+                self.height - self.margin + (n + 1) as f64 * self.line_height - box_height
+            }
+            Line::Percent(p, align) => {
+                let y = p * self.height / 100.;
+                match align {
+                    Align::Start => y,
+                    Align::Center => y - box_height / 2.,
+                    Align::End => y - box_height,
+                }
+            }
+        };
+
+        let mut text = format!(r#"<text class="cue" text-anchor="{}">"#, anchor);
+        for (i, line) in cue.lines.iter().enumerate() {
+            let y = top + (i as f64 + 0.8) * self.line_height;
+            text.push_str(&format!(r#"<tspan x="{}" y="{}">"#, x, y));
+            for span in line {
+                let mut attrs = String::new();
+                if span.bold {
+                    attrs.push_str(r#" font-weight="bold""#);
+                }
+                if span.italic {
+                    attrs.push_str(r#" font-style="italic""#);
+                }
+                if span.underline {
+                    attrs.push_str(r#" text-decoration="underline""#);
+                }
+
+                text.push_str(&format!("<tspan{}>{}</tspan>", attrs, escape(&span.text)));
+            }
+            text.push_str("</tspan>");
+        }
+        text.push_str("</text>");
+
+        self.svg.replacen(PLACEHOLDER, &text, 1)
+    }
+}
+
+// Returns a transition for every cue, in order.
+pub(crate) fn transitions<R: io::Read>(
+    mut source: R,
+    format: Format,
+    template: &Template,
+) -> anyhow::Result<Vec<Transition>> {
+    let mut s = String::new();
+    source.read_to_string(&mut s)?;
+
+    let cues = match format {
+        Format::Srt => parse_srt(&s)?,
+        Format::WebVtt => parse_webvtt(&s)?,
+    };
+
+    let mut transitions = Vec::with_capacity(cues.len());
+    for (index, cue) in cues.iter().enumerate() {
+        transitions.push(Transition {
+            time_in: cue.start,
+            time_out: Some(cue.end),
+            index,
+            id: index + 1,
+            svg: template.render(cue).into(),
+            src: None,
+            defs: None,
+            fade_in: 0,
+            fade_out: 0,
+            opacity: 1.,
+            motion: None,
+            pos: None,
+        });
+    }
+
+    Ok(transitions)
+}
+
+fn parse_srt(s: &str) -> anyhow::Result<Vec<Cue>> {
+    blocks(s)
+        .iter()
+        .map(|block| parse_cue(block).map(|(cue, _)| cue))
+        .collect()
+}
+
+fn parse_webvtt(s: &str) -> anyhow::Result<Vec<Cue>> {
+    let mut blocks = blocks(s).into_iter();
+    match blocks.next() {
+        Some(header) if header[0].starts_with("WEBVTT") => {}
+        _ => return Err(anyhow::anyhow!("missing WEBVTT header")),
+    }
+
+    let mut cues = Vec::new();
+    for block in blocks {
+        let first = block[0];
+        if first.starts_with("NOTE") || first == "STYLE" || first == "REGION" {
+            continue;
+        }
+
+        let (mut cue, settings) = parse_cue(&block)?;
+        for setting in settings.split_whitespace() {
+            parse_setting(&mut cue, setting)?;
+        }
+
+        cues.push(cue);
+    }
+
+    Ok(cues)
+}
+
+// Parses a cue with an optional identifier, returns it with its settings.
+fn parse_cue<'a>(block: &[&'a str]) -> anyhow::Result<(Cue, &'a str)> {
+    let (timing, text) = match block {
+        [timing, text @ ..] if timing.contains("-->") => (timing, text),
+        [_, timing, text @ ..] => (timing, text),
+        _ => return Err(anyhow::anyhow!("missing cue timing after {}", block[0])),
+    };
+
+    let (start, end, settings) = parse_timing(timing)?;
+    let cue = Cue {
+        start,
+        end,
+        lines: parse_text(text),
+        line: Line::Auto,
+        position: None,
+        align: Align::Center,
+    };
+
+    Ok((cue, settings))
+}
+
+// Splits the input in blocks of non-empty lines.
+fn blocks(s: &str) -> Vec<Vec<&str>> {
+    let mut blocks = vec![];
+    let mut block = vec![];
+    for line in s.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            if !block.is_empty() {
+                blocks.push(block);
+                block = vec![];
+            }
+        } else {
+            block.push(line);
+        }
+    }
+
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+
+    blocks
+}
+
+// Parses `start --> end settings`, the SRT decimal comma is accepted.
+fn parse_timing(s: &str) -> anyhow::Result<(u64, u64, &str)> {
+    let mut parts = s.splitn(2, "-->");
+    let start = parts.next().unwrap().trim();
+    let rest = parts.next().unwrap_or("").trim_start();
+    let (end, settings) = match rest.find(char::is_whitespace) {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+
+    let clock = Clock::default();
+    let time = |t: &str| {
+        clock
+            .parse(&t.replace(',', "."))
+            .map_err(|e| anyhow::anyhow!("invalid cue timing {}: {}", s, e))
+    };
+
+    Ok((time(start)?, time(end)?, settings))
+}
+
+fn parse_setting(cue: &mut Cue, setting: &str) -> anyhow::Result<()> {
+    let invalid = || anyhow::anyhow!("invalid cue setting {}", setting);
+    let mut kv = setting.splitn(2, ':');
+    let (key, value) = (kv.next().unwrap(), kv.next().ok_or_else(invalid)?);
+    let mut args = value.splitn(2, ',');
+    let value = args.next().unwrap();
+    let percent = || -> anyhow::Result<f64> {
+        match value.strip_suffix('%').map(str::parse::<f64>) {
+            Some(Ok(p)) if (0. ..=100.).contains(&p) => Ok(p),
+            _ => Err(invalid()),
+        }
+    };
+
+    match key {
+        "line" => {
+            cue.line = if value.ends_with('%') {
+                let align = match args.next() {
+                    None | Some("start") => Align::Start,
+                    Some("center") => Align::Center,
+                    Some("end") => Align::End,
+                    _ => return Err(invalid()),
+                };
+
+                Line::Percent(percent()?, align)
+            } else {
+                Line::Number(value.parse().map_err(|_| invalid())?)
+            }
+        }
+
+        "position" => cue.position = Some(percent()?),
+        "align" => {
+            cue.align = match value {
+                "start" | "left" => Align::Start,
+                "center" | "middle" => Align::Center,
+                "end" | "right" => Align::End,
+                _ => return Err(invalid()),
+            }
+        }
+
+        // Other settings like size and region are ignored.
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Parses the lines of cue text, `<b>`, `<i>` and `<u>` are kept and other
+// tags are removed.
+fn parse_text(lines: &[&str]) -> Vec<Vec<Span>> {
+    let (mut bold, mut italic, mut underline) = (0, 0, 0);
+    let mut result = vec![];
+    for line in lines {
+        let mut spans = vec![];
+        let mut span = Span {
+            bold: bold > 0,
+            italic: italic > 0,
+            underline: underline > 0,
+            ..Span::default()
+        };
+
+        let mut rest = *line;
+        while !rest.is_empty() {
+            let (text, tag) = match rest.find('<') {
+                Some(i) => match rest[i..].find('>') {
+                    Some(j) => (&rest[..i], Some(&rest[i + 1..i + j])),
+                    None => (rest, None),
+                },
+                None => (rest, None),
+            };
+
+            span.text.push_str(&unescape(text));
+            let tag = match tag {
+                Some(tag) => tag,
+                None => break,
+            };
+
+            rest = &rest[text.len() + tag.len() + 2..];
+            let (close, name) = match tag.strip_prefix('/') {
+                Some(name) => (true, name),
+                None => (false, tag),
+            };
+
+            let counter = match name.split(|c: char| c == '.' || c.is_whitespace()).next() {
+                Some("b") => &mut bold,
+                Some("i") => &mut italic,
+                Some("u") => &mut underline,
+                _ => continue,
+            };
+
+            if !span.text.is_empty() {
+                spans.push(span.clone());
+                span.text.clear();
+            }
+
+            if close {
+                *counter = std::cmp::max(*counter - 1, 0);
+            } else {
+                *counter += 1;
+            }
+
+            span.bold = bold > 0;
+            span.italic = italic > 0;
+            span.underline = underline > 0;
+        }
+
+        if !span.text.is_empty() {
+            spans.push(span);
+        }
+
+        result.push(spans);
+    }
+
+    result
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&lrm;", "\u{200e}")
+        .replace("&rlm;", "\u{200f}")
+        .replace("&amp;", "&")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srt() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\nHello <b>bold</b> &amp; <i>italic\r\nworld</i>\r\n\r\n2\r\n00:01:00,000 --> 00:01:01,000\r\nA < B\r\n";
+        let cues = parse_srt(srt).unwrap();
+        assert_eq!(2, cues.len());
+        assert_eq!((1000, 2500), (cues[0].start, cues[0].end));
+        assert_eq!(60_000, cues[1].start);
+
+        let bold = Span {
+            text: String::from("bold"),
+            bold: true,
+            ..Span::default()
+        };
+        assert_eq!(4, cues[0].lines[0].len());
+        assert_eq!("Hello ", cues[0].lines[0][0].text);
+        assert_eq!(bold, cues[0].lines[0][1]);
+        assert_eq!(" & ", cues[0].lines[0][2].text);
+        assert!(cues[0].lines[0][3].italic);
+        assert!(cues[0].lines[1][0].italic);
+        assert_eq!("A < B", cues[1].lines[0][0].text);
+
+        let template = Template::parse(None).unwrap();
+        let svg = template.render(&cues[1]);
+        assert!(svg.contains(r#"<text class="cue" text-anchor="middle"><tspan x="960" y="1013.2"><tspan>A &lt; B</tspan></tspan></text>"#));
+        assert!(Document::parse(&template.render(&cues[0])).is_ok());
+    }
+
+    #[test]
+    fn webvtt() {
+        let vtt = "WEBVTT - title\n\nNOTE a comment\n\nintro\n00:01.000 --> 00:02.000 line:10% align:start position:5%\n<v Speaker>Hi <c.yellow>there</c>\n\n00:00:03.000 --> 00:00:04.000 line:-2 align:right\nBye\n";
+        let cues = parse_webvtt(vtt).unwrap();
+        assert_eq!(2, cues.len());
+        assert_eq!(
+            Cue {
+                start: 1000,
+                end: 2000,
+                lines: vec![vec![Span {
+                    text: String::from("Hi there"),
+                    ..Span::default()
+                }]],
+                line: Line::Percent(10., Align::Start),
+                position: Some(5.),
+                align: Align::Start,
+            },
+            cues[0]
+        );
+        assert_eq!(Line::Number(-2), cues[1].line);
+        assert_eq!(Align::End, cues[1].align);
+
+        assert!(parse_webvtt("00:01.000 --> 00:02.000\nHi\n").is_err());
+        assert!(parse_webvtt("WEBVTT\n\n00:01.000 --> 00:02.000 line:x\nHi\n").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use roxmltree::Node;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Timebase {
+    Millis,
+    Frames,
+    // MPEG-TS 90 kHz clock ticks.
+    Hz90k,
+}
+
+// Converts the time values of a TSVG document to milliseconds, as given by
+// the `timebase` and `fps` attributes of the root element.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Clock {
+    timebase: Timebase,
+    fps: Option<f64>,
+}
+
+// Milliseconds without a frame rate.
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock {
+            timebase: Timebase::Millis,
+            fps: None,
+        }
+    }
+}
+
+impl Clock {
+    pub(crate) fn from_root(root: &Node) -> anyhow::Result<Clock> {
+        let timebase = match root.attribute("timebase") {
+            None | Some("ms") => Timebase::Millis,
+            Some("frames") => Timebase::Frames,
+            Some("90khz") => Timebase::Hz90k,
+            Some(tb) => return Err(anyhow::anyhow!("invalid timebase {}", tb)),
+        };
+
+        let fps = root.attribute("fps").map(parse_fps).transpose()?;
+        if timebase == Timebase::Frames && fps.is_none() {
+            return Err(anyhow::anyhow!("timebase frames requires fps"));
+        }
+
+        Ok(Clock { timebase, fps })
+    }
+
+    // Parses a number in the timebase, a `HH:MM:SS:FF` timecode (`;` before
+    // the frames for drop-frame) or a `HH:MM:SS.mmm` clock time.
+    pub(crate) fn parse(&self, s: &str) -> anyhow::Result<u64> {
+        let s = s.trim();
+        let millis = if s.contains(&[':', ';'][..]) {
+            let parts: Vec<&str> = s.split(&[':', ';'][..]).collect();
+            if parts.len() == 4 {
+                self.parse_timecode(&parts, s.contains(';'))?
+            } else {
+                parse_clock_time(&parts)?
+            }
+        } else {
+            let value: f64 = s.parse()?;
+            match self.timebase {
+                Timebase::Millis => value,
+                Timebase::Frames => value * 1000. / self.fps.unwrap(),
+                Timebase::Hz90k => value / 90.,
+            }
+        };
+
+        if !millis.is_finite() || millis < 0. {
+            return Err(anyhow::anyhow!("invalid time {}", s));
+        }
+
+        Ok(millis.round() as u64)
+    }
+
+    fn parse_timecode(&self, parts: &[&str], drop_frame: bool) -> anyhow::Result<f64> {
+        let fps = self
+            .fps
+            .ok_or_else(|| anyhow::anyhow!("timecode requires fps"))?;
+
+        let mut n = [0u64; 4];
+        for (i, p) in parts.iter().enumerate() {
+            n[i] = p.parse()?;
+        }
+
+        let [h, m, s, f] = n;
+        let nominal = fps.round() as u64;
+        if m >= 60 || s >= 60 || f >= nominal {
+            return Err(anyhow::anyhow!("invalid timecode {}", parts.join(":")));
+        }
+
+        if !drop_frame {
+            let frames = (h * 3600 + m * 60 + s) * nominal + f;
+            return Ok(frames as f64 * 1000. / fps);
+        }
+
+        // Drop-frame timecode skips the first frame numbers of every minute
+        // except every tenth, 2 frames at 29.97 and 4 at 59.94 fps.
+        if nominal != 30 && nominal != 60 {
+            return Err(anyhow::anyhow!(
+                "drop-frame timecode requires 29.97 or 59.94 fps"
+            ));
+        }
+
+        let drop = nominal / 15;
+        if s == 0 && m % 10 != 0 && f < drop {
+            return Err(anyhow::anyhow!(
+                "invalid drop-frame timecode {}",
+                parts.join(":")
+            ));
+        }
+
+        let minutes = h * 60 + m;
+        let frames = (h * 3600 + m * 60 + s) * nominal + f - drop * (minutes - minutes / 10);
+        Ok(frames as f64 * 1001. / nominal as f64)
+    }
+}
+
+// Parses a frame rate given as a number or a ratio, e.g. `25` or `30000/1001`.
+fn parse_fps(s: &str) -> anyhow::Result<f64> {
+    let mut parts = s.splitn(2, '/');
+    let mut fps: f64 = parts.next().unwrap().trim().parse()?;
+    if let Some(den) = parts.next() {
+        fps /= den.trim().parse::<f64>()?;
+    }
+
+    if !fps.is_finite() || fps <= 0. {
+        return Err(anyhow::anyhow!("invalid fps {}", s));
+    }
+
+    Ok(fps)
+}
+
+// Parses `HH:MM:SS.mmm` or `MM:SS.mmm`.
+fn parse_clock_time(parts: &[&str]) -> anyhow::Result<f64> {
+    if parts.len() > 3 {
+        return Err(anyhow::anyhow!("invalid time {}", parts.join(":")));
+    }
+
+    let mut secs = 0.;
+    for p in parts {
+        secs = secs * 60. + p.parse::<f64>()?;
+    }
+
+    Ok(secs * 1000.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(root: &str) -> Clock {
+        let doc = roxmltree::Document::parse(root).unwrap();
+        Clock::from_root(&doc.root_element()).unwrap()
+    }
+
+    #[test]
+    fn times() {
+        let ms = clock("<transitions/>");
+        assert_eq!(1500, ms.parse("1500").unwrap());
+        assert_eq!(3_723_500, ms.parse("01:02:03.500").unwrap());
+        assert!(ms.parse("00:00:01:00").is_err());
+        assert!(ms.parse("-1").is_err());
+
+        let frames = clock(r#"<transitions timebase="frames" fps="25"/>"#);
+        assert_eq!(2000, frames.parse("50").unwrap());
+        assert_eq!(3_723_480, frames.parse("01:02:03:12").unwrap());
+        assert!(frames.parse("00:00:00:25").is_err());
+
+        let hz = clock(r#"<transitions timebase="90khz"/>"#);
+        assert_eq!(1000, hz.parse("90000").unwrap());
+
+        let ntsc = clock(r#"<transitions fps="30000/1001"/>"#);
+        // 00:01:00;02 is frame 1800 and 00:10:00;00 is frame 17982.
+        assert_eq!(60_060, ntsc.parse("00:01:00;02").unwrap());
+        assert_eq!(599_999, ntsc.parse("00:10:00;00").unwrap());
+        assert!(ntsc.parse("00:01:00;00").is_err());
+
+        let doc = roxmltree::Document::parse(r#"<transitions timebase="frames"/>"#).unwrap();
+        assert!(Clock::from_root(&doc.root_element()).is_err());
+    }
+}
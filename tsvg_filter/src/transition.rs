@@ -2,124 +2,139 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::cmp::Ordering;
-use std::collections::VecDeque;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
 
-use resvg::usvg;
+use interval_tree::{Interval, IntervalTree};
+use roxmltree::TextPos;
+
+use crate::defs::{self, Defs};
+use crate::motion::Motion;
 
 pub(crate) struct Transition {
     pub(crate) time_in: u64,
     pub(crate) time_out: Option<u64>,
     pub(crate) index: usize,
-    pub(crate) tree: usvg::Tree,
-}
-
-// Tree implementation inspired by https://github.com/main--/rust-intervaltree
-
-pub(crate) struct Tree {
-    nodes: Vec<TreeNode>,
+    // Unique among the transitions of a filter instance, the ordinal
+    // assigned when parsed. Keys the SVG of the transition in the caches.
+    pub(crate) id: usize,
+    // The SVG source, parsed when the transition becomes active. Shared by
+    // the transitions referencing the same SVG file.
+    pub(crate) svg: Arc<str>,
+    // The id and path of the referenced SVG file, if any.
+    pub(crate) src: Option<(usize, Arc<Path>)>,
+    // Document-level defs and styles, merged into the SVG before parsing.
+    pub(crate) defs: Option<Defs>,
+    // Fade durations in milliseconds.
+    pub(crate) fade_in: u64,
+    pub(crate) fade_out: u64,
+    pub(crate) opacity: f64,
+    // Keyframes relative to time_in.
+    pub(crate) motion: Option<Motion>,
+    // Position of the transition element in the TSVG file, for errors.
+    pub(crate) pos: Option<TextPos>,
 }
 
-struct TreeNode {
-    transition: Transition,
-    max: Option<u64>,
+// Identifies the SVG of a transition in the caches, which is shared by the
+// transitions referencing the same SVG file with the same defs.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum SvgKey {
+    Inline(usize),
+    // The id of the file and the generation of the defs, if any.
+    File(usize, Option<usize>),
 }
 
-impl Tree {
-    pub(crate) fn new(transitions: Vec<Transition>) -> Tree {
-        let mut nodes: Vec<TreeNode> = transitions
-            .into_iter()
-            .map(|transition| {
-                let max = transition.time_out;
-                TreeNode { transition, max }
-            })
-            .collect();
-
-        nodes.sort_by(|a, b| Self::cmp_transitions(&a.transition, &b.transition));
-        if !nodes.is_empty() {
-            Self::update_max(&mut nodes);
+impl Transition {
+    // Returns the SVG with the document-level defs and styles, if any.
+    pub(crate) fn merged_svg(&self) -> Cow<'_, str> {
+        match &self.defs {
+            Some(defs) => Cow::Owned(defs::merge(&self.svg, defs)),
+            None => Cow::Borrowed(&self.svg),
         }
+    }
 
-        Tree { nodes }
+    pub(crate) fn svg_key(&self) -> SvgKey {
+        match &self.src {
+            Some((id, _)) => SvgKey::File(*id, self.defs.as_ref().map(|d| d.generation)),
+            None => SvgKey::Inline(self.id),
+        }
     }
 
-    pub(crate) fn search(&self, ts_millis: f64) -> Vec<&Transition> {
-        let mut result = vec![];
-        let mut stack = VecDeque::new();
-        if !self.nodes.is_empty() {
-            stack.push_back((0, self.nodes.len()));
+    // Returns the opacity of the transition at the given timestamp.
+    pub(crate) fn alpha(&self, ts_millis: f64) -> f64 {
+        let mut alpha = self.opacity;
+        if self.fade_in > 0 {
+            let t = (ts_millis - self.time_in as f64) / self.fade_in as f64;
+            alpha *= t.clamp(0., 1.);
         }
 
-        while let Some((s, l)) = stack.pop_back() {
-            let idx = s + l / 2;
-            let node = &self.nodes[idx];
-            if node.max.map(|m| ts_millis < m as f64).unwrap_or(true) {
-                let ls = idx - s;
-                if ls > 0 {
-                    stack.push_back((s, ls));
-                }
-
-                if ts_millis >= node.transition.time_in as f64 {
-                    let rs = l + s - idx - 1;
-                    if rs > 0 {
-                        stack.push_back((idx + 1, rs));
-                    }
-
-                    if node
-                        .transition
-                        .time_out
-                        .map(|o| ts_millis < o as f64)
-                        .unwrap_or(true)
-                    {
-                        result.push(&node.transition);
-                    }
-                }
-            }
+        if let (Some(time_out), true) = (self.time_out, self.fade_out > 0) {
+            let t = (time_out as f64 - ts_millis) / self.fade_out as f64;
+            alpha *= t.clamp(0., 1.);
         }
 
-        result.sort_by(|a, b| a.index.cmp(&b.index));
-        result
+        alpha
     }
 
-    fn update_max(nodes: &mut [TreeNode]) -> Option<u64> {
-        let mid = nodes.len() / 2;
-        if nodes.len() > 1 {
-            let (left, rest) = nodes.split_at_mut(mid);
-            if !left.is_empty() {
-                let left_max = Self::update_max(left);
-                rest[0].max = Self::max(rest[0].max, left_max);
-            }
-
-            let (rest, right) = nodes.split_at_mut(mid + 1);
-            if !right.is_empty() {
-                let right_max = Self::update_max(right);
-                rest[mid].max = Self::max(rest[mid].max, right_max);
-            }
+    // Prefixes a message with the file and the position of the transition,
+    // e.g. `a.tsvg:3:1: transition 2 (time-in 1000ms): message`.
+    pub(crate) fn located(&self, path: &Path, message: impl fmt::Display) -> String {
+        match self.pos {
+            Some(pos) => format!("{}:{}: {}: {}", path.display(), pos, self, message),
+            None => format!("{}: {}: {}", path.display(), self, message),
         }
+    }
+}
 
-        nodes[mid].max
+// A transition with an empty SVG that tests override fields of.
+#[cfg(test)]
+pub(crate) fn test_transition(time_in: u64, time_out: Option<u64>) -> Transition {
+    Transition {
+        time_in,
+        time_out,
+        index: 0,
+        id: 0,
+        svg: Arc::from(""),
+        src: None,
+        defs: None,
+        fade_in: 0,
+        fade_out: 0,
+        opacity: 1.,
+        motion: None,
+        pos: None,
     }
+}
 
-    fn cmp_transitions(a: &Transition, b: &Transition) -> Ordering {
-        let ordering = a.time_in.cmp(&b.time_in);
-        if ordering == Ordering::Equal {
-            match (a.time_out, b.time_out) {
-                (None, None) => Ordering::Equal,
-                (None, _) => Ordering::Greater,
-                (_, None) => Ordering::Less,
-                (Some(a), Some(b)) => a.cmp(&b),
-            }
-        } else {
-            ordering
-        }
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", describe(self.id, Some(self.time_in)))
     }
+}
 
-    fn max(a: Option<u64>, b: Option<u64>) -> Option<u64> {
-        match (a, b) {
-            (None, _) => None,
-            (_, None) => None,
-            (Some(a), Some(b)) => Some(a.max(b)),
-        }
+// Names a transition in errors, also before it's parsed, e.g.
+// `transition 2 (time-in 1000ms)`. The time-in is left out if it's invalid.
+pub(crate) fn describe(id: usize, time_in: Option<u64>) -> String {
+    match time_in {
+        Some(time_in) => format!("transition {} (time-in {}ms)", id, time_in),
+        None => format!("transition {}", id),
+    }
+}
+
+pub(crate) type Tree = IntervalTree<Transition>;
+
+impl Interval for Transition {
+    fn time_in(&self) -> u64 {
+        self.time_in
+    }
+
+    fn time_out(&self) -> Option<u64> {
+        self.time_out
+    }
+
+    fn index(&self) -> usize {
+        self.index
     }
 }
 
@@ -135,39 +150,17 @@ mod tests {
 
     #[test]
     fn it_works() {
-        let svg_tree = usvg::Tree::create(usvg::Svg {
-            size: usvg::Size::new(640.0, 360.0).unwrap(),
-            view_box: usvg::ViewBox {
-                rect: usvg::Rect::new(0.0, 0.0, 640.0, 360.0).unwrap(),
-                aspect: usvg::AspectRatio::default(),
-            },
-        });
+        let transition = |time_in, time_out, index| Transition {
+            index,
+            id: index,
+            ..test_transition(time_in, time_out)
+        };
 
         let transitions = vec![
-            Transition {
-                time_in: 4,
-                time_out: Some(8),
-                index: 0,
-                tree: svg_tree.clone(),
-            },
-            Transition {
-                time_in: 2,
-                time_out: Some(10),
-                index: 1,
-                tree: svg_tree.clone(),
-            },
-            Transition {
-                time_in: 10,
-                time_out: None,
-                index: 2,
-                tree: svg_tree.clone(),
-            },
-            Transition {
-                time_in: 10,
-                time_out: Some(12),
-                index: 3,
-                tree: svg_tree,
-            },
+            transition(4, Some(8), 0),
+            transition(2, Some(10), 1),
+            transition(10, None, 2),
+            transition(10, Some(12), 3),
         ];
 
         let tree = Tree::new(transitions);
@@ -206,5 +199,104 @@ mod tests {
         assert_eq!(1, result.len());
         assert_eq!(10, result[0].time_in);
         assert_eq!(None, result[0].time_out);
+
+        let upcoming: Vec<u64> = tree.upcoming(4.0).map(|t| t.time_in).collect();
+        assert_eq!(vec![10, 10], upcoming);
+        assert_eq!(4, tree.upcoming(0.0).count());
+        assert_eq!(0, tree.upcoming(10.0).count());
+    }
+
+    #[test]
+    fn extend() {
+        let transition = |time_in, time_out, index| Transition {
+            index,
+            id: index,
+            ..test_transition(time_in, time_out)
+        };
+
+        let mut tree = Tree::new(Vec::new());
+        assert!(tree.search(0.0).is_empty());
+
+        tree.extend(vec![
+            transition(0, Some(100), 0),
+            transition(50, Some(60), 1),
+        ]);
+        tree.extend(vec![transition(20, None, 2)]);
+        tree.extend(vec![transition(90, Some(95), 3)]);
+
+        let indexes = |ts| -> Vec<usize> { tree.search(ts).iter().map(|t| t.index).collect() };
+        assert_eq!(vec![0], indexes(10.0));
+        assert_eq!(vec![0, 1, 2], indexes(55.0));
+        assert_eq!(vec![0, 2, 3], indexes(90.0));
+        assert_eq!(vec![2], indexes(1000.0));
+
+        let upcoming: Vec<u64> = tree.upcoming(30.0).map(|t| t.time_in).collect();
+        assert_eq!(vec![50, 90], upcoming);
+    }
+
+    #[test]
+    fn alpha() {
+        let mut transition = Transition {
+            fade_in: 500,
+            fade_out: 1000,
+            opacity: 0.8,
+            ..test_transition(1000, Some(3000))
+        };
+
+        assert_eq!(0., transition.alpha(1000.));
+        assert_eq!(0.4, transition.alpha(1250.));
+        assert_eq!(0.8, transition.alpha(2000.));
+        assert_eq!(0.4, transition.alpha(2500.));
+
+        transition.time_out = None;
+        assert_eq!(0.8, transition.alpha(10000.));
+    }
+
+    #[test]
+    fn svg_key() {
+        let transition = |id| Transition {
+            id,
+            src: Some((0, Arc::from(Path::new("a.svg")))),
+            ..test_transition(0, None)
+        };
+
+        let (mut a, mut b) = (transition(1), transition(2));
+        assert!(a.svg_key() == b.svg_key());
+
+        // Defs added in live mode change the merged SVG of the file.
+        let defs = |generation| Defs {
+            generation,
+            svg: Arc::from("<style/>"),
+            namespaces: Arc::from(Vec::new()),
+        };
+        a.defs = Some(defs(0));
+        b.defs = Some(defs(1));
+        assert!(a.svg_key() != b.svg_key());
+
+        a.src = None;
+        b.src = None;
+        assert!(a.svg_key() != b.svg_key());
+    }
+
+    #[test]
+    fn located() {
+        let mut transition = Transition {
+            id: 2,
+            ..test_transition(1000, None)
+        };
+
+        let path = Path::new("a.srt");
+        assert_eq!(
+            "a.srt: transition 2 (time-in 1000ms): error",
+            transition.located(path, "error")
+        );
+
+        transition.pos = Some(TextPos::new(3, 5));
+        assert_eq!(
+            "a.srt:3:5: transition 2 (time-in 1000ms): error",
+            transition.located(path, "error")
+        );
+
+        assert_eq!("transition 2", describe(2, None));
     }
 }
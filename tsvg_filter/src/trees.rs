@@ -0,0 +1,285 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use resvg::usvg;
+use svg_common::anim::Animations;
+use svg_common::fonts::Fonts;
+use svg_common::images;
+
+use crate::defs;
+use crate::transition::{SvgKey, Transition};
+
+pub(crate) enum Parsed {
+    Static(usvg::Tree),
+    Animated(Animations),
+}
+
+// Least recently used cache of parsed transitions bounded by the number
+// of transitions. The SVG of a transition is only checked and prepared
+// when it's parsed, to keep `filter_init` fast.
+pub(crate) struct TreeCache {
+    pub(crate) options: usvg::Options,
+    fonts: Fonts,
+    limit: usize,
+    tick: u64,
+    entries: HashMap<SvgKey, (Parsed, u64)>,
+    // Whether the SVG is animated, kept when it's evicted to find the raster
+    // of a static one without parsing it again.
+    animated: HashMap<SvgKey, bool>,
+    // Transitions that failed to parse or render, not prefetched again. The
+    // filter skips them if lenient, otherwise they fail when active.
+    failed: HashSet<SvgKey>,
+    // Image warnings are reported once, e.g. for a subtitle template.
+    warned: HashSet<String>,
+}
+
+impl TreeCache {
+    pub(crate) fn new(options: usvg::Options, fonts: Fonts, limit: usize) -> TreeCache {
+        TreeCache {
+            options,
+            fonts,
+            limit: limit.max(1),
+            tick: 0,
+            entries: HashMap::new(),
+            animated: HashMap::new(),
+            failed: HashSet::new(),
+            warned: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn get(&mut self, transition: &Transition) -> anyhow::Result<&Parsed> {
+        let key = transition.svg_key();
+
+        self.tick += 1;
+        if !self.entries.contains_key(&key) {
+            let parsed = self.parse(transition)?;
+            self.insert(key, parsed);
+        }
+
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.1 = self.tick;
+        Ok(&entry.0)
+    }
+
+    // Whether the SVG of the transition is animated, parses it if unknown.
+    pub(crate) fn is_animated(&mut self, transition: &Transition) -> anyhow::Result<bool> {
+        match self.animated.get(&transition.svg_key()) {
+            Some(&animated) => Ok(animated),
+            None => Ok(matches!(self.get(transition)?, Parsed::Animated(_))),
+        }
+    }
+
+    pub(crate) fn has_failed(&self, transition: &Transition) -> bool {
+        self.failed.contains(&transition.svg_key())
+    }
+
+    pub(crate) fn set_failed(&mut self, transition: &Transition) {
+        self.failed.insert(transition.svg_key());
+    }
+
+    // Parses the first of the given transitions that isn't cached, to have
+    // it ready before it becomes active. A transition that fails is skipped
+    // by later calls, its error is reported again by `get`.
+    pub(crate) fn prefetch<'a, I>(
+        &mut self,
+        transitions: I,
+    ) -> Result<(), (&'a Transition, anyhow::Error)>
+    where
+        I: Iterator<Item = &'a Transition>,
+    {
+        for transition in transitions {
+            let key = transition.svg_key();
+
+            if !self.entries.contains_key(&key) && !self.failed.contains(&key) {
+                match self.parse(transition) {
+                    Ok(parsed) => self.insert(key, parsed),
+                    Err(e) => {
+                        self.failed.insert(key);
+                        return Err((transition, e));
+                    }
+                }
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse(&mut self, transition: &Transition) -> anyhow::Result<Parsed> {
+        let (parsed, warnings) = parse(transition, &self.fonts, &self.options)?;
+        let path = self
+            .options
+            .path
+            .as_deref()
+            .unwrap_or_else(|| Path::new(""));
+        for warning in warnings {
+            if !self.warned.contains(&warning) {
+                eprintln!("warning: {}", transition.located(path, &warning));
+                self.warned.insert(warning);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn insert(&mut self, key: SvgKey, parsed: Parsed) {
+        let animated = matches!(parsed, Parsed::Animated(_));
+        self.animated.insert(key, animated);
+        while self.entries.len() >= self.limit {
+            let oldest = match self.entries.iter().min_by_key(|(_, e)| e.1) {
+                Some((k, _)) => *k,
+                None => break,
+            };
+
+            self.entries.remove(&oldest);
+        }
+
+        self.entries.insert(key, (parsed, self.tick));
+    }
+
+    pub(crate) fn options(&self, transition: &Transition) -> Cow<'_, usvg::Options> {
+        with_path(transition, &self.options)
+    }
+}
+
+impl Parsed {
+    // Returns the tree, of an animated SVG as it is at time-in.
+    pub(crate) fn into_tree(
+        self,
+        transition: &Transition,
+        options: &usvg::Options,
+    ) -> anyhow::Result<usvg::Tree> {
+        match self {
+            Parsed::Static(tree) => Ok(tree),
+            Parsed::Animated(animations) => {
+                let svg = animations.render(0.);
+                Ok(usvg::Tree::from_str(&svg, &with_path(transition, options))?)
+            }
+        }
+    }
+}
+
+// Parses the SVG of a transition as it's rendered, with the fonts applied
+// and the shared defs and styles merged, which may animate it too. Also
+// returns warnings about images that can't be loaded. Errors in SVG files
+// are prefixed with the path of the file.
+pub(crate) fn parse(
+    transition: &Transition,
+    fonts: &Fonts,
+    options: &usvg::Options,
+) -> anyhow::Result<(Parsed, Vec<String>)> {
+    let options = with_path(transition, options);
+    let result = fonts
+        .apply(&transition.svg)
+        .map_err(anyhow::Error::msg)
+        .and_then(|svg| {
+            let svg = match &transition.defs {
+                Some(d) => defs::merge(&svg, d),
+                None => svg,
+            };
+
+            let warnings = images::check(&svg, options.path.as_deref());
+            let parsed = match Animations::parse(&svg) {
+                Ok(Some(animations)) => Parsed::Animated(animations),
+                Ok(None) => Parsed::Static(
+                    usvg::Tree::from_str(&svg, &options)
+                        .map_err(|e| anyhow::anyhow!("invalid SVG: {}", e))?,
+                ),
+                Err(e) => return Err(anyhow::anyhow!("invalid SVG: {}", e)),
+            };
+
+            Ok((parsed, warnings))
+        });
+
+    match &transition.src {
+        Some((_, path)) => result.map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e)),
+        None => result,
+    }
+}
+
+// Parses the SVG of a transition, animated ones as they are at time-in.
+pub(crate) fn parse_tree(
+    transition: &Transition,
+    fonts: &Fonts,
+    options: &usvg::Options,
+) -> anyhow::Result<(usvg::Tree, Vec<String>)> {
+    let (parsed, warnings) = parse(transition, fonts, options)?;
+    Ok((parsed.into_tree(transition, options)?, warnings))
+}
+
+// Images in SVG files are resolved relative to the file instead of the
+// TSVG file.
+fn with_path<'a>(transition: &Transition, options: &'a usvg::Options) -> Cow<'a, usvg::Options> {
+    match &transition.src {
+        Some((_, path)) => {
+            let mut options = options.clone();
+            options.path = Some(path.to_path_buf());
+            Cow::Owned(options)
+        }
+
+        None => Cow::Borrowed(options),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use svg_common::fonts::FontConfig;
+
+    use crate::defs::Defs;
+    use crate::transition::test_transition;
+
+    #[test]
+    fn shared_animations() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect class="a" width="10" height="10"/></svg>"#;
+        let style = "<style>@keyframes a { from { opacity: 0 } to { opacity: 1 } }
+.a { animation: a 1s }</style>";
+        let transition = |id, defs| Transition {
+            id,
+            svg: Arc::from(svg),
+            defs,
+            ..test_transition(0, None)
+        };
+
+        let fonts = FontConfig::default().load().unwrap();
+        let mut trees = TreeCache::new(usvg::Options::default(), fonts, 1);
+        let defs = Defs {
+            generation: 0,
+            svg: Arc::from(style),
+            namespaces: Arc::from(Vec::new()),
+        };
+        assert!(trees.is_animated(&transition(1, Some(defs))).unwrap());
+        assert!(!trees.is_animated(&transition(2, None)).unwrap());
+
+        // Known after the parsed SVG is evicted.
+        assert!(trees.animated[&SvgKey::Inline(1)]);
+        assert!(!trees.entries.contains_key(&SvgKey::Inline(1)));
+    }
+
+    #[test]
+    fn errors() {
+        let fonts = FontConfig::default().load().unwrap();
+        let options = usvg::Options::default();
+        let transition = Transition {
+            svg: Arc::from("<svg><g></svg>"),
+            ..test_transition(0, None)
+        };
+
+        let e = parse(&transition, &fonts, &options).err().unwrap();
+        assert!(e.to_string().starts_with("invalid SVG: "));
+
+        let transition = Transition {
+            src: Some((0, Arc::from(Path::new("a.svg")))),
+            ..transition
+        };
+        let e = parse(&transition, &fonts, &options).err().unwrap();
+        assert!(e.to_string().starts_with("a.svg: invalid SVG: "));
+    }
+}
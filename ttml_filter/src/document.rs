@@ -0,0 +1,468 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use roxmltree::{Document as XmlDocument, Node, NodeType};
+
+use crate::style::{Length, Style};
+use crate::timing::TimeParams;
+
+pub(crate) const TTP_NS: &str = "http://www.w3.org/ns/ttml#parameter";
+pub(crate) const TTS_NS: &str = "http://www.w3.org/ns/ttml#styling";
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+
+pub(crate) struct Region {
+    pub(crate) origin: (Length, Length),
+    pub(crate) extent: (Length, Length),
+    // Computed from the initial style, content inherits from it.
+    pub(crate) style: Style,
+}
+
+// A run of text with the same style.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Run {
+    pub(crate) text: String,
+    pub(crate) style: Style,
+    pub(crate) time_in: u64,
+    pub(crate) time_out: Option<u64>,
+}
+
+impl Run {
+    pub(crate) fn is_active(&self, ts_millis: f64) -> bool {
+        ts_millis >= self.time_in as f64
+            && self.time_out.map(|t| ts_millis < t as f64).unwrap_or(true)
+    }
+}
+
+pub(crate) struct Paragraph {
+    pub(crate) time_in: u64,
+    pub(crate) time_out: Option<u64>,
+    // Document order.
+    pub(crate) index: usize,
+    pub(crate) region: usize,
+    pub(crate) style: Style,
+    pub(crate) lines: Vec<Vec<Run>>,
+}
+
+impl interval_tree::Interval for Paragraph {
+    fn time_in(&self) -> u64 {
+        self.time_in
+    }
+
+    fn time_out(&self) -> Option<u64> {
+        self.time_out
+    }
+
+    fn index(&self) -> usize {
+        self.index
+    }
+}
+
+pub(crate) struct Document {
+    // The root container size in pixels given by `tts:extent`.
+    pub(crate) extent: Option<(f64, f64)>,
+    // Columns and rows given by `ttp:cellResolution`.
+    pub(crate) cell_resolution: (f64, f64),
+    pub(crate) regions: Vec<Region>,
+    pub(crate) paragraphs: Vec<Paragraph>,
+}
+
+// Begin and end in milliseconds, None is an indefinite end.
+type Interval = (f64, Option<f64>);
+
+struct Parser<'a> {
+    params: TimeParams,
+    styles: HashMap<&'a str, Node<'a, 'a>>,
+    regions: HashMap<&'a str, usize>,
+    region_styles: Vec<Style>,
+    paragraphs: Vec<Paragraph>,
+}
+
+pub(crate) fn parse_ttml(s: &str) -> anyhow::Result<Document> {
+    let doc = XmlDocument::parse(s)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "tt" {
+        return Err(anyhow::anyhow!(
+            "unexpected root element {}",
+            root.tag_name().name()
+        ));
+    }
+
+    let extent = match root.attribute((TTS_NS, "extent")) {
+        None | Some("auto") => None,
+        Some(v) => {
+            let px = v
+                .split_whitespace()
+                .map(|p| p.strip_suffix("px").and_then(|p| p.parse::<f64>().ok()))
+                .collect::<Option<Vec<f64>>>();
+            match px.as_deref() {
+                Some(&[w, h]) if w > 0. && h > 0. => Some((w, h)),
+                _ => return Err(anyhow::anyhow!("invalid tts:extent {}", v)),
+            }
+        }
+    };
+
+    let cell_resolution = match root.attribute((TTP_NS, "cellResolution")) {
+        None => (32., 15.),
+        Some(v) => {
+            let n = v
+                .split_whitespace()
+                .map(|n| n.parse::<f64>().ok())
+                .collect::<Option<Vec<f64>>>();
+            match n.as_deref() {
+                Some(&[c, r]) if c > 0. && r > 0. => (c, r),
+                _ => return Err(anyhow::anyhow!("invalid ttp:cellResolution {}", v)),
+            }
+        }
+    };
+
+    let mut parser = Parser {
+        params: TimeParams::from_root(&root)?,
+        styles: HashMap::new(),
+        regions: HashMap::new(),
+        region_styles: Vec::new(),
+        paragraphs: Vec::new(),
+    };
+
+    let head = root.children().find(|c| c.has_tag_name("head"));
+    for node in head.iter().flat_map(|h| h.descendants()) {
+        if node.has_tag_name("style") {
+            if let Some(id) = node.attribute((XML_NS, "id")) {
+                parser.styles.insert(id, node);
+            }
+        }
+    }
+
+    let mut regions = Vec::new();
+    for node in head.iter().flat_map(|h| h.descendants()) {
+        if !node.has_tag_name("region") {
+            continue;
+        }
+
+        let style = parser.specified_style(&node)?.inherit(&Style::initial());
+        if let Some(id) = node.attribute((XML_NS, "id")) {
+            parser.regions.insert(id, regions.len());
+        }
+
+        regions.push(Region {
+            origin: style
+                .origin
+                .unwrap_or((Length::Percent(0.), Length::Percent(0.))),
+            extent: style
+                .extent
+                .unwrap_or((Length::Percent(100.), Length::Percent(100.))),
+            style,
+        });
+    }
+
+    // Without regions everything is shown in a default region that covers
+    // the root container.
+    let default_region = if regions.is_empty() {
+        regions.push(Region {
+            origin: (Length::Percent(0.), Length::Percent(0.)),
+            extent: (Length::Percent(100.), Length::Percent(100.)),
+            style: Style::initial(),
+        });
+        Some(0)
+    } else {
+        None
+    };
+
+    parser.region_styles = regions.iter().map(|r| r.style.clone()).collect();
+    if let Some(body) = root.children().find(|c| c.has_tag_name("body")) {
+        parser.parse_block(&body, &Style::default(), default_region, (0., None))?;
+    }
+
+    Ok(Document {
+        extent,
+        cell_resolution,
+        regions,
+        paragraphs: parser.paragraphs,
+    })
+}
+
+impl<'a> Parser<'a> {
+    // Returns the referential and inline styles of an element.
+    fn specified_style(&self, node: &Node) -> anyhow::Result<Style> {
+        let mut style = Style::default();
+        self.referential_style(node, &mut style, &mut Vec::new())?;
+        style.merge(&Style::parse(node)?);
+        Ok(style)
+    }
+
+    fn referential_style(
+        &self,
+        node: &Node,
+        style: &mut Style,
+        seen: &mut Vec<&'a str>,
+    ) -> anyhow::Result<()> {
+        for id in node.attribute("style").unwrap_or("").split_whitespace() {
+            let referenced = match self.styles.get(id) {
+                Some(n) => n,
+                None => return Err(anyhow::anyhow!("unknown style {}", id)),
+            };
+
+            let id = referenced.attribute((XML_NS, "id")).unwrap();
+            if seen.contains(&id) {
+                return Err(anyhow::anyhow!("circular style reference {}", id));
+            }
+
+            seen.push(id);
+            self.referential_style(referenced, style, seen)?;
+            style.merge(&Style::parse(referenced)?);
+            seen.pop();
+        }
+
+        Ok(())
+    }
+
+    fn region(&self, node: &Node, parent: Option<usize>) -> anyhow::Result<Option<usize>> {
+        match node.attribute("region") {
+            Some(id) => match self.regions.get(id) {
+                Some(&r) => Ok(Some(r)),
+                None => Err(anyhow::anyhow!("unknown region {}", id)),
+            },
+            None => Ok(parent),
+        }
+    }
+
+    fn interval(&self, node: &Node, parent: Interval) -> anyhow::Result<Interval> {
+        let attr = |name| {
+            node.attribute(name)
+                .map(|v| self.params.parse(v))
+                .transpose()
+        };
+
+        let begin = parent.0 + attr("begin")?.unwrap_or(0.);
+        let end = [
+            attr("end")?.map(|e| parent.0 + e),
+            attr("dur")?.map(|d| begin + d),
+            parent.1,
+        ]
+        .iter()
+        .flatten()
+        .fold(None, |min: Option<f64>, &e| {
+            Some(min.map_or(e, |m| m.min(e)))
+        });
+
+        Ok((begin, end))
+    }
+
+    // Parses body and div elements.
+    fn parse_block(
+        &mut self,
+        node: &Node,
+        parent_style: &Style,
+        parent_region: Option<usize>,
+        parent_interval: Interval,
+    ) -> anyhow::Result<()> {
+        let style = self.specified_style(node)?.inherit(parent_style);
+        let region = self.region(node, parent_region)?;
+        let interval = self.interval(node, parent_interval)?;
+        for child in node.children().filter(|c| c.is_element()) {
+            match child.tag_name().name() {
+                "div" => self.parse_block(&child, &style, region, interval)?,
+                "p" => self.parse_paragraph(&child, &style, region, interval)?,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_paragraph(
+        &mut self,
+        node: &Node,
+        parent_style: &Style,
+        parent_region: Option<usize>,
+        parent_interval: Interval,
+    ) -> anyhow::Result<()> {
+        let index = self.paragraphs.len();
+        let (begin, end) = self.interval(node, parent_interval)?;
+        // Content without a region isn't shown if the document has regions.
+        let region = match self.region(node, parent_region)? {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+
+        if end.map(|e| e <= begin).unwrap_or(false) {
+            return Ok(());
+        }
+
+        // The inherited properties are resolved against the region.
+        let region_style = &self.region_styles[region];
+        let style = self
+            .specified_style(node)?
+            .inherit(parent_style)
+            .inherit(region_style);
+
+        let mut lines = vec![vec![]];
+        self.parse_inline(node, &style, (begin, end), &mut lines)?;
+        let lines = normalize(lines);
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        self.paragraphs.push(Paragraph {
+            time_in: begin.round() as u64,
+            time_out: end.map(|e| e.round() as u64),
+            index,
+            region,
+            style,
+            lines,
+        });
+
+        Ok(())
+    }
+
+    fn parse_inline(
+        &self,
+        node: &Node,
+        style: &Style,
+        interval: Interval,
+        lines: &mut Vec<Vec<Run>>,
+    ) -> anyhow::Result<()> {
+        for child in node.children() {
+            match child.node_type() {
+                NodeType::Text => lines.last_mut().unwrap().push(Run {
+                    text: collapse(child.text().unwrap()),
+                    style: style.clone(),
+                    time_in: interval.0.round() as u64,
+                    time_out: interval.1.map(|e| e.round() as u64),
+                }),
+
+                NodeType::Element => match child.tag_name().name() {
+                    "br" => lines.push(vec![]),
+                    "span" => {
+                        let style = self.specified_style(&child)?.inherit(style);
+                        let interval = self.interval(&child, interval)?;
+                        self.parse_inline(&child, &style, interval, lines)?;
+                    }
+
+                    _ => {}
+                },
+
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Replaces every sequence of white space with a single space.
+fn collapse(s: &str) -> String {
+    let mut collapsed = String::with_capacity(s.len());
+    for (i, word) in s.split(|c: char| c.is_ascii_whitespace()).enumerate() {
+        if i > 0 && !collapsed.ends_with(' ') {
+            collapsed.push(' ');
+        }
+
+        collapsed.push_str(word);
+    }
+
+    collapsed
+}
+
+// Removes the white space at the start and end of the lines, and between
+// runs, and drops the empty runs and lines.
+fn normalize(lines: Vec<Vec<Run>>) -> Vec<Vec<Run>> {
+    let mut result = Vec::new();
+    for line in lines {
+        let mut runs: Vec<Run> = Vec::new();
+        for mut run in line {
+            let space_before = runs.last().map(|r| r.text.ends_with(' ')).unwrap_or(true);
+            if space_before {
+                run.text = String::from(run.text.trim_start_matches(' '));
+            }
+
+            if !run.text.is_empty() {
+                runs.push(run);
+            }
+        }
+
+        if let Some(last) = runs.last_mut() {
+            last.text.truncate(last.text.trim_end_matches(' ').len());
+        }
+
+        runs.retain(|r| !r.text.is_empty());
+        if !runs.is_empty() {
+            result.push(runs);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tt xmlns="http://www.w3.org/ns/ttml" xmlns:tts="http://www.w3.org/ns/ttml#styling"
+    xmlns:ttp="http://www.w3.org/ns/ttml#parameter" ttp:cellResolution="50 30"
+    xml:lang="sv">
+  <head>
+    <styling>
+      <style xml:id="base" tts:fontSize="80%" tts:color="white"/>
+      <style xml:id="yellow" style="base" tts:color="yellow"/>
+    </styling>
+    <layout>
+      <region xml:id="bottom" tts:origin="10% 10%" tts:extent="80% 80%" tts:displayAlign="after"/>
+    </layout>
+  </head>
+  <body region="bottom" style="base">
+    <div begin="10s">
+      <p begin="00:00:01.000" end="00:00:03.000">
+        Hello <span style="yellow">yellow</span>
+        <br/>
+        <span tts:fontWeight="bold" begin="1s">world</span>
+      </p>
+      <p begin="5s" dur="1s">Second</p>
+      <p>No time</p>
+    </div>
+    <p region="nowhere" begin="0s" end="1s">Unknown</p>
+  </body>
+</tt>"#;
+
+    #[test]
+    fn parse() {
+        assert!(parse_ttml(TTML).is_err());
+
+        let ttml = TTML.replace(r#" region="nowhere""#, "");
+        let doc = parse_ttml(&ttml).unwrap();
+        assert_eq!(None, doc.extent);
+        assert_eq!((50., 30.), doc.cell_resolution);
+        assert_eq!(1, doc.regions.len());
+        assert_eq!(4, doc.paragraphs.len());
+
+        let p = &doc.paragraphs[0];
+        assert_eq!((11000, Some(13000)), (p.time_in, p.time_out));
+        assert_eq!(2, p.lines.len());
+        assert_eq!(
+            vec!["Hello ", "yellow"],
+            p.lines[0]
+                .iter()
+                .map(|r| r.text.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(Length::Cells(0.8)), p.lines[0][0].style.font_size);
+        assert_eq!(
+            Some(Length::Cells(0.8 * 0.8)),
+            p.lines[0][1].style.font_size
+        );
+        assert_ne!(p.lines[0][0].style.color, p.lines[0][1].style.color);
+        assert_eq!("world", p.lines[1][0].text);
+        assert_eq!(Some(true), p.lines[1][0].style.bold);
+        assert_eq!(12000, p.lines[1][0].time_in);
+        assert!(!p.lines[1][0].is_active(11500.));
+
+        let p = &doc.paragraphs[1];
+        assert_eq!((15000, Some(16000)), (p.time_in, p.time_out));
+        let p = &doc.paragraphs[2];
+        assert_eq!((10000, None), (p.time_in, p.time_out));
+        assert_eq!(0, doc.paragraphs[3].time_in);
+    }
+}
@@ -0,0 +1,166 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ffi::CStr;
+use std::fs;
+use std::ptr;
+
+use interval_tree::IntervalTree;
+use libc::{c_char, c_double, c_int, c_uchar, c_uint, c_void};
+
+mod document;
+use document::{Document, Paragraph};
+
+mod render;
+
+mod style;
+
+mod timing;
+
+struct Config<'a> {
+    ttml: &'a str,
+}
+
+struct Context {
+    // The paragraphs are moved to the tree.
+    document: Document,
+    tree: IntervalTree<Paragraph>,
+}
+
+/// # Safety
+///
+/// `config` must be null or a NUL-terminated string and `user_data` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn filter_init(config: *const c_char, user_data: *mut *mut c_void) -> c_int {
+    unsafe {
+        *user_data = ptr::null_mut();
+    }
+
+    if config.is_null() {
+        eprintln!("got null config");
+        return 1;
+    }
+
+    let config = match parse_config(config) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("invalid config: {}", e);
+            return 1;
+        }
+    };
+
+    let mut document = match read_ttml(config.ttml) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("{}: {}", config.ttml, e);
+            return 1;
+        }
+    };
+
+    let tree = IntervalTree::new(std::mem::take(&mut document.paragraphs));
+    let ctx = Context { document, tree };
+    unsafe {
+        *user_data = Box::into_raw(Box::new(ctx)) as *mut c_void;
+    }
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn filter_frame(
+    data: *mut c_uchar,
+    data_size: c_uint,
+    width: c_int,
+    height: c_int,
+    line_size: c_int,
+    ts_millis: c_double,
+    user_data: *mut c_void,
+) -> c_int {
+    if data.is_null() || width <= 0 || height <= 0 {
+        return 0;
+    }
+
+    let ctx = if user_data.is_null() {
+        eprintln!("no user data");
+        return 1;
+    } else {
+        unsafe { &*(user_data as *const Context) }
+    };
+
+    let paragraphs = ctx.tree.search(ts_millis);
+    if paragraphs.is_empty() {
+        return 0;
+    }
+
+    let cr = match new_cairo_context(data, data_size as usize, width, height, line_size) {
+        Ok(cr) => cr,
+        Err(status) => {
+            eprintln!("could not create cairo context: {:?}", status);
+            return 1;
+        }
+    };
+
+    render::render(
+        &ctx.document,
+        &paragraphs,
+        ts_millis,
+        width as f64,
+        height as f64,
+        &cr,
+    );
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn filter_uninit(user_data: *mut c_void) {
+    if !user_data.is_null() {
+        unsafe {
+            drop(Box::from_raw(user_data as *mut Context));
+        }
+    }
+}
+
+fn parse_config<'a>(config: *const c_char) -> anyhow::Result<Config<'a>> {
+    let cstr = unsafe { CStr::from_ptr(config) };
+    let s = cstr.to_str()?;
+    let re = regex::Regex::new(r"^ttml=(.+)$").unwrap();
+    if let Some(cap) = re.captures(s) {
+        Ok(Config {
+            ttml: cap.get(1).unwrap().as_str(),
+        })
+    } else {
+        Err(anyhow::anyhow!(s))
+    }
+}
+
+fn read_ttml(ttml: &str) -> anyhow::Result<Document> {
+    let s = fs::read_to_string(ttml)?;
+    document::parse_ttml(&s)
+}
+
+fn new_cairo_context(
+    data: *mut c_uchar,
+    _data_size: usize,
+    width: i32,
+    height: i32,
+    line_size: i32,
+) -> Result<cairo::Context, cairo::Status> {
+    let surface = unsafe {
+        let surface = cairo_sys::cairo_image_surface_create_for_data(
+            data,
+            cairo_sys::FORMAT_A_RGB32,
+            width,
+            height,
+            line_size,
+        );
+
+        cairo::ImageSurface::from_raw_full(surface)?
+    };
+
+    let cr = cairo::Context::new(&surface);
+    cr.set_antialias(cairo::Antialias::Best);
+    Ok(cr)
+}
@@ -0,0 +1,396 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ops::Range;
+
+use crate::document::{Document, Paragraph, Run};
+use crate::style::{Color, DisplayAlign, Length, Style, TextAlign};
+
+// The line height used for `normal`, relative to the largest font size.
+const NORMAL_LINE_HEIGHT: f64 = 1.25;
+
+// Converts lengths to output pixels.
+struct Units {
+    width: f64,
+    height: f64,
+    // Scale of pixel lengths when the document has a `tts:extent`.
+    scale: (f64, f64),
+    cell: (f64, f64),
+}
+
+impl Units {
+    fn x(&self, l: Length) -> f64 {
+        l.resolve(self.width, self.cell.0, self.scale.0)
+    }
+
+    fn y(&self, l: Length) -> f64 {
+        l.resolve(self.height, self.cell.1, self.scale.1)
+    }
+
+    fn font_size(&self, style: &Style) -> f64 {
+        let size = style.font_size.unwrap_or(Length::Cells(1.));
+        size.resolve(self.cell.1, self.cell.1, self.scale.1)
+    }
+}
+
+// A part of a run that isn't broken across lines, a word with its
+// trailing spaces if the run wraps.
+struct Piece<'a> {
+    run: &'a Run,
+    text: &'a str,
+    size: f64,
+    advance: f64,
+    // Advance without the trailing spaces.
+    trimmed: f64,
+    // A line can be broken after the piece.
+    breaks_after: bool,
+}
+
+struct LaidOutRun<'a> {
+    run: &'a Run,
+    text: &'a str,
+    size: f64,
+    advance: f64,
+}
+
+struct Line<'a> {
+    runs: Vec<LaidOutRun<'a>>,
+    width: f64,
+    height: f64,
+    ascent: f64,
+    descent: f64,
+    align: TextAlign,
+    background: Option<Color>,
+}
+
+// Renders the active paragraphs in their regions.
+pub(crate) fn render(
+    doc: &Document,
+    paragraphs: &[&Paragraph],
+    ts_millis: f64,
+    width: f64,
+    height: f64,
+    cr: &cairo::Context,
+) {
+    let units = Units {
+        width,
+        height,
+        scale: doc
+            .extent
+            .map(|(w, h)| (width / w, height / h))
+            .unwrap_or((1., 1.)),
+        cell: (
+            width / doc.cell_resolution.0,
+            height / doc.cell_resolution.1,
+        ),
+    };
+
+    for (i, region) in doc.regions.iter().enumerate() {
+        let (x, y) = (units.x(region.origin.0), units.y(region.origin.1));
+        let (w, h) = (units.x(region.extent.0), units.y(region.extent.1));
+        let mut lines = Vec::new();
+        for p in paragraphs.iter().filter(|p| p.region == i) {
+            for l in &p.lines {
+                lines.extend(layout_line(p, l, ts_millis, w, &units, cr));
+            }
+        }
+
+        if lines.is_empty() {
+            continue;
+        }
+
+        cr.save();
+        cr.rectangle(x, y, w, h);
+        if let Some(color) = region.style.background_color {
+            set_color(cr, color);
+            cr.fill_preserve();
+        }
+
+        cr.clip();
+        let total: f64 = lines.iter().map(|l| l.height).sum();
+        let mut top = match region.style.display_align.unwrap_or(DisplayAlign::Before) {
+            DisplayAlign::Before => y,
+            DisplayAlign::Center => y + (h - total) / 2.,
+            DisplayAlign::After => y + h - total,
+        };
+
+        for line in &lines {
+            let left = match line.align {
+                TextAlign::Left => x,
+                TextAlign::Center => x + (w - line.width) / 2.,
+                TextAlign::Right => x + w - line.width,
+            };
+
+            paint_line(line, left, top, &units, cr);
+            top += line.height;
+        }
+
+        cr.restore();
+    }
+}
+
+// Lays out the active runs of a line of a paragraph, wrapped to
+// `max_width` at the spaces of runs that wrap.
+fn layout_line<'a>(
+    paragraph: &Paragraph,
+    runs: &'a [Run],
+    ts_millis: f64,
+    max_width: f64,
+    units: &Units,
+    cr: &cairo::Context,
+) -> Vec<Line<'a>> {
+    let mut pieces = Vec::new();
+    for run in runs.iter().filter(|r| r.is_active(ts_millis)) {
+        let size = units.font_size(&run.style);
+        set_font(cr, &run.style, size);
+        let wraps = run.style.wrap != Some(false);
+        let texts = if wraps {
+            words(&run.text)
+        } else {
+            vec![run.text.as_str()]
+        };
+
+        for text in texts {
+            pieces.push(Piece {
+                run,
+                text,
+                size,
+                advance: cr.text_extents(text).x_advance,
+                trimmed: cr.text_extents(text.trim_end()).x_advance,
+                breaks_after: wraps && text.ends_with(char::is_whitespace),
+            });
+        }
+    }
+
+    wrap(&pieces, max_width)
+        .into_iter()
+        .map(|range| {
+            let mut line = Line {
+                runs: Vec::new(),
+                width: 0.,
+                height: 0.,
+                ascent: 0.,
+                descent: 0.,
+                align: paragraph.style.text_align.unwrap_or(TextAlign::Center),
+                background: paragraph.style.background_color,
+            };
+
+            let last = range.len() - 1;
+            let mut max_size: f64 = 0.;
+            for (i, piece) in pieces[range].iter().enumerate() {
+                // Trailing spaces don't take up space at the end of a line.
+                let (text, advance) = if i == last {
+                    (piece.text.trim_end(), piece.trimmed)
+                } else {
+                    (piece.text, piece.advance)
+                };
+
+                set_font(cr, &piece.run.style, piece.size);
+                let font = cr.font_extents();
+                line.width += advance;
+                line.ascent = line.ascent.max(font.ascent);
+                line.descent = line.descent.max(font.descent);
+                max_size = max_size.max(piece.size);
+                line.runs.push(LaidOutRun {
+                    run: piece.run,
+                    text,
+                    size: piece.size,
+                    advance,
+                });
+            }
+
+            line.height = match paragraph.style.line_height {
+                Some(Some(l)) => l.resolve(
+                    units.font_size(&paragraph.style),
+                    units.cell.1,
+                    units.scale.1,
+                ),
+                _ => max_size * NORMAL_LINE_HEIGHT,
+            };
+
+            line
+        })
+        .collect()
+}
+
+// Splits text after each sequence of white space.
+fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut space = false;
+    for (i, c) in text.char_indices() {
+        if space && !c.is_whitespace() {
+            words.push(&text[start..i]);
+            start = i;
+        }
+
+        space = c.is_whitespace();
+    }
+
+    words.push(&text[start..]);
+    words
+}
+
+// Breaks the pieces into lines that fit `max_width` where possible, at the
+// last break before a piece that doesn't fit.
+fn wrap(pieces: &[Piece], max_width: f64) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut width = 0.;
+    let mut last_break = None;
+    for (i, piece) in pieces.iter().enumerate() {
+        if let Some(b) = last_break {
+            if width + piece.trimmed > max_width {
+                lines.push(start..b);
+                start = b;
+                width = pieces[b..i].iter().map(|p| p.advance).sum();
+                last_break = None;
+            }
+        }
+
+        width += piece.advance;
+        if piece.breaks_after {
+            last_break = Some(i + 1);
+        }
+    }
+
+    if start < pieces.len() {
+        lines.push(start..pieces.len());
+    }
+
+    lines
+}
+
+fn paint_line(line: &Line, left: f64, top: f64, units: &Units, cr: &cairo::Context) {
+    if let Some(color) = line.background {
+        set_color(cr, color);
+        cr.rectangle(left, top, line.width, line.height);
+        cr.fill();
+    }
+
+    let mut x = left;
+    for r in &line.runs {
+        if let Some(color) = r.run.style.background_color {
+            set_color(cr, color);
+            cr.rectangle(x, top, r.advance, line.height);
+            cr.fill();
+        }
+
+        x += r.advance;
+    }
+
+    let baseline = top + (line.height - line.ascent - line.descent) / 2. + line.ascent;
+    let mut x = left;
+    for r in &line.runs {
+        let style = &r.run.style;
+        let color = style.color.unwrap_or(Color {
+            r: 1.,
+            g: 1.,
+            b: 1.,
+            a: 1.,
+        });
+
+        set_font(cr, style, r.size);
+        cr.move_to(x, baseline);
+        cr.text_path(r.text);
+        if let Some(outline) = style.text_outline {
+            let thickness = outline
+                .thickness
+                .resolve(r.size, units.cell.1, units.scale.1);
+            if thickness > 0. {
+                set_color(cr, outline.color.unwrap_or(color));
+                // The outline is drawn outside of the glyphs.
+                cr.set_line_width(thickness * 2.);
+                cr.set_line_join(cairo::LineJoin::Round);
+                cr.stroke_preserve();
+            }
+        }
+
+        set_color(cr, color);
+        cr.fill();
+
+        if style.underline == Some(true) {
+            cr.rectangle(x, baseline + r.size / 10., r.advance, r.size / 15.);
+            cr.fill();
+        }
+
+        x += r.advance;
+    }
+}
+
+fn set_font(cr: &cairo::Context, style: &Style, size: f64) {
+    let family = style
+        .font_family
+        .as_deref()
+        .and_then(|f| f.split(',').next())
+        .map(|f| f.trim().trim_matches(|c| c == '"' || c == '\''))
+        .unwrap_or("default");
+
+    // The generic TTML families are mapped to fontconfig names.
+    let family = match family {
+        "default" | "sansSerif" | "proportionalSansSerif" => "sans-serif",
+        "serif" | "proportionalSerif" => "serif",
+        "monospace" | "monospaceSansSerif" | "monospaceSerif" => "monospace",
+        f => f,
+    };
+
+    let slant = if style.italic == Some(true) {
+        cairo::FontSlant::Italic
+    } else {
+        cairo::FontSlant::Normal
+    };
+
+    let weight = if style.bold == Some(true) {
+        cairo::FontWeight::Bold
+    } else {
+        cairo::FontWeight::Normal
+    };
+
+    cr.select_font_face(family, slant, weight);
+    cr.set_font_size(size);
+}
+
+fn set_color(cr: &cairo::Context, color: Color) {
+    cr.set_source_rgba(color.r, color.g, color.b, color.a);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::style::Style;
+
+    #[test]
+    fn wrap_words() {
+        assert_eq!(vec!["one ", "two  ", "three"], words("one two  three"));
+        assert_eq!(vec![" ", "one"], words(" one"));
+        assert_eq!(vec![""], words(""));
+
+        let run = Run {
+            text: String::from("one two  three"),
+            style: Style::default(),
+            time_in: 0,
+            time_out: None,
+        };
+
+        // Every character is 10 wide.
+        let pieces = |wraps| {
+            words(&run.text)
+                .into_iter()
+                .map(|text| Piece {
+                    run: &run,
+                    text,
+                    size: 10.,
+                    advance: text.len() as f64 * 10.,
+                    trimmed: text.trim_end().len() as f64 * 10.,
+                    breaks_after: wraps && text.ends_with(' '),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec![0..2, 2..3], wrap(&pieces(true), 80.));
+        assert_eq!(vec![0..1, 1..2, 2..3], wrap(&pieces(true), 40.));
+        assert_eq!(vec![0..3], wrap(&pieces(true), 200.));
+        assert_eq!(vec![0..3], wrap(&pieces(false), 40.));
+    }
+}
@@ -0,0 +1,436 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use roxmltree::Node;
+
+use crate::document::TTS_NS;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Color {
+    pub(crate) r: f64,
+    pub(crate) g: f64,
+    pub(crate) b: f64,
+    pub(crate) a: f64,
+}
+
+impl Color {
+    fn rgba(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color {
+            r: r as f64 / 255.,
+            g: g as f64 / 255.,
+            b: b as f64 / 255.,
+            a: a as f64 / 255.,
+        }
+    }
+
+    fn parse(s: &str) -> Option<Color> {
+        let s = s.trim();
+        if let Some(hex) = s.strip_prefix('#') {
+            let n = u32::from_str_radix(hex, 16).ok()?;
+            let [a, b, c, d] = n.to_be_bytes();
+            return match hex.len() {
+                6 => Some(Color::rgba(b, c, d, 255)),
+                8 => Some(Color::rgba(a, b, c, d)),
+                _ => None,
+            };
+        }
+
+        let args = |name: &str| {
+            let args = s.strip_prefix(name)?.trim().strip_prefix('(')?;
+            args.strip_suffix(')')?
+                .split(',')
+                .map(|n| n.trim().parse::<u8>().ok())
+                .collect::<Option<Vec<u8>>>()
+        };
+
+        if let Some(n) = args("rgba") {
+            return match n[..] {
+                [r, g, b, a] => Some(Color::rgba(r, g, b, a)),
+                _ => None,
+            };
+        }
+
+        if let Some(n) = args("rgb") {
+            return match n[..] {
+                [r, g, b] => Some(Color::rgba(r, g, b, 255)),
+                _ => None,
+            };
+        }
+
+        let (r, g, b, a) = match s {
+            "transparent" => (0, 0, 0, 0),
+            "black" => (0, 0, 0, 255),
+            "silver" => (192, 192, 192, 255),
+            "gray" | "grey" => (128, 128, 128, 255),
+            "white" => (255, 255, 255, 255),
+            "maroon" => (128, 0, 0, 255),
+            "red" => (255, 0, 0, 255),
+            "purple" => (128, 0, 128, 255),
+            "fuchsia" | "magenta" => (255, 0, 255, 255),
+            "green" => (0, 128, 0, 255),
+            "lime" => (0, 255, 0, 255),
+            "olive" => (128, 128, 0, 255),
+            "yellow" => (255, 255, 0, 255),
+            "navy" => (0, 0, 128, 255),
+            "blue" => (0, 0, 255, 255),
+            "teal" => (0, 128, 128, 255),
+            "aqua" | "cyan" => (0, 255, 255, 255),
+            _ => return None,
+        };
+
+        Some(Color::rgba(r, g, b, a))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Length {
+    Px(f64),
+    Percent(f64),
+    // Cells of the cell resolution grid.
+    Cells(f64),
+    Em(f64),
+}
+
+impl Length {
+    fn parse(s: &str) -> Option<Length> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| c.is_ascii_alphabetic() || c == '%')
+            .unwrap_or(s.len());
+        let value: f64 = s[..split].parse().ok().filter(|v: &f64| v.is_finite())?;
+        match &s[split..] {
+            "px" => Some(Length::Px(value)),
+            "%" => Some(Length::Percent(value)),
+            "c" => Some(Length::Cells(value)),
+            "em" => Some(Length::Em(value)),
+            _ => None,
+        }
+    }
+
+    fn parse_pair(s: &str) -> Option<(Length, Length)> {
+        let mut parts = s.split_whitespace().map(Length::parse);
+        let pair = (parts.next()??, parts.next()??);
+        match parts.next() {
+            None => Some(pair),
+            Some(_) => None,
+        }
+    }
+
+    // Returns the length in pixels, percentages and ems are relative to
+    // `relative_to` and pixels are multiplied with `scale`.
+    pub(crate) fn resolve(self, relative_to: f64, cell: f64, scale: f64) -> f64 {
+        match self {
+            Length::Px(v) => v * scale,
+            Length::Percent(p) => p / 100. * relative_to,
+            Length::Cells(c) => c * cell,
+            Length::Em(e) => e * relative_to,
+        }
+    }
+
+    fn scale(self, factor: f64) -> Length {
+        match self {
+            Length::Px(v) => Length::Px(v * factor),
+            Length::Percent(p) => Length::Percent(p * factor),
+            Length::Cells(c) => Length::Cells(c * factor),
+            Length::Em(e) => Length::Em(e * factor),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DisplayAlign {
+    Before,
+    Center,
+    After,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Outline {
+    // Defaults to the text color.
+    pub(crate) color: Option<Color>,
+    pub(crate) thickness: Length,
+}
+
+// The supported `tts:*` style properties, unset properties are None.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Style {
+    pub(crate) color: Option<Color>,
+    pub(crate) background_color: Option<Color>,
+    pub(crate) font_family: Option<String>,
+    pub(crate) font_size: Option<Length>,
+    pub(crate) italic: Option<bool>,
+    pub(crate) bold: Option<bool>,
+    pub(crate) underline: Option<bool>,
+    pub(crate) text_align: Option<TextAlign>,
+    // `none` is an outline with zero thickness.
+    pub(crate) text_outline: Option<Outline>,
+    // `normal` is Some(None).
+    pub(crate) line_height: Option<Option<Length>>,
+    // `tts:wrapOption`, lines are wrapped at spaces to fit the region.
+    pub(crate) wrap: Option<bool>,
+    pub(crate) display_align: Option<DisplayAlign>,
+    pub(crate) origin: Option<(Length, Length)>,
+    pub(crate) extent: Option<(Length, Length)>,
+}
+
+impl Style {
+    // The initial values of the inherited properties.
+    pub(crate) fn initial() -> Style {
+        Style {
+            color: Some(Color::rgba(255, 255, 255, 255)),
+            font_family: Some(String::from("default")),
+            font_size: Some(Length::Cells(1.)),
+            italic: Some(false),
+            bold: Some(false),
+            underline: Some(false),
+            text_align: Some(TextAlign::Center),
+            text_outline: Some(Outline {
+                color: None,
+                thickness: Length::Px(0.),
+            }),
+            line_height: Some(None),
+            wrap: Some(true),
+            ..Style::default()
+        }
+    }
+
+    // Parses the `tts:*` attributes of an element.
+    pub(crate) fn parse(node: &Node) -> anyhow::Result<Style> {
+        let mut style = Style::default();
+        for attr in node.attributes() {
+            if attr.namespace() != Some(TTS_NS) {
+                continue;
+            }
+
+            let value = attr.value().trim();
+            let invalid = || anyhow::anyhow!("invalid tts:{} {}", attr.name(), value);
+            match attr.name() {
+                "color" => style.color = Some(Color::parse(value).ok_or_else(invalid)?),
+                "backgroundColor" => {
+                    style.background_color = Some(Color::parse(value).ok_or_else(invalid)?)
+                }
+
+                "fontFamily" => style.font_family = Some(String::from(value)),
+                "fontSize" => {
+                    // The vertical size is used if two sizes are given.
+                    let size = value.split_whitespace().last().unwrap_or("");
+                    style.font_size = Some(Length::parse(size).ok_or_else(invalid)?)
+                }
+
+                "fontStyle" => style.italic = Some(value != "normal"),
+                "fontWeight" => style.bold = Some(value == "bold"),
+                "textDecoration" => {
+                    style.underline = match value {
+                        "underline" => Some(true),
+                        "noUnderline" | "none" => Some(false),
+                        _ => style.underline,
+                    }
+                }
+
+                "textAlign" => {
+                    style.text_align = Some(match value {
+                        "left" | "start" => TextAlign::Left,
+                        "center" => TextAlign::Center,
+                        "right" | "end" => TextAlign::Right,
+                        _ => return Err(invalid()),
+                    })
+                }
+
+                "textOutline" => {
+                    style.text_outline = Some(if value == "none" {
+                        Outline {
+                            color: None,
+                            thickness: Length::Px(0.),
+                        }
+                    } else {
+                        parse_outline(value).ok_or_else(invalid)?
+                    })
+                }
+
+                "lineHeight" => {
+                    style.line_height = Some(if value == "normal" {
+                        None
+                    } else {
+                        Some(Length::parse(value).ok_or_else(invalid)?)
+                    })
+                }
+
+                "wrapOption" => {
+                    style.wrap = Some(match value {
+                        "wrap" => true,
+                        "noWrap" => false,
+                        _ => return Err(invalid()),
+                    })
+                }
+
+                "displayAlign" => {
+                    style.display_align = Some(match value {
+                        "before" => DisplayAlign::Before,
+                        "center" => DisplayAlign::Center,
+                        "after" => DisplayAlign::After,
+                        _ => return Err(invalid()),
+                    })
+                }
+
+                "origin" => style.origin = Some(Length::parse_pair(value).ok_or_else(invalid)?),
+                "extent" => style.extent = Some(Length::parse_pair(value).ok_or_else(invalid)?),
+                // Other properties are ignored.
+                _ => {}
+            }
+        }
+
+        Ok(style)
+    }
+
+    // Overrides the properties that are set in `other`.
+    pub(crate) fn merge(&mut self, other: &Style) {
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field.clone();
+                })*
+            };
+        }
+
+        merge!(
+            color,
+            background_color,
+            font_family,
+            font_size,
+            italic,
+            bold,
+            underline,
+            text_align,
+            text_outline,
+            line_height,
+            wrap,
+            display_align,
+            origin,
+            extent
+        );
+    }
+
+    // Returns the style with the unset inherited properties taken from
+    // `parent`, relative font sizes are resolved against the parent's.
+    pub(crate) fn inherit(&self, parent: &Style) -> Style {
+        let mut style = self.clone();
+        macro_rules! inherit {
+            ($($field:ident),*) => {
+                $(if style.$field.is_none() {
+                    style.$field = parent.$field.clone();
+                })*
+            };
+        }
+
+        inherit!(
+            color,
+            font_family,
+            italic,
+            bold,
+            underline,
+            text_align,
+            text_outline,
+            line_height,
+            wrap
+        );
+
+        style.font_size = match (self.font_size, parent.font_size) {
+            (Some(Length::Percent(p)), Some(size)) => Some(size.scale(p / 100.)),
+            (Some(Length::Em(e)), Some(size)) => Some(size.scale(e)),
+            (Some(size), _) => Some(size),
+            (None, size) => size,
+        };
+
+        style
+    }
+}
+
+// Parses `[color] thickness [blur]`, the blur radius is ignored.
+fn parse_outline(s: &str) -> Option<Outline> {
+    let parts: Vec<&str> = s.split_whitespace().collect();
+    let (color, rest) = match Color::parse(parts.first()?) {
+        Some(c) => (Some(c), &parts[1..]),
+        None => (None, &parts[..]),
+    };
+
+    let thickness = Length::parse(rest.first()?)?;
+    if rest.len() > 2 {
+        return None;
+    }
+
+    Some(Outline { color, thickness })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Some(Color::rgba(255, 0, 0, 128)), Color::parse("#ff000080"));
+        assert_eq!(
+            Some(Color::rgba(1, 2, 3, 4)),
+            Color::parse("rgba(1, 2, 3, 4)")
+        );
+        assert_eq!(Some(Color::rgba(0, 255, 255, 255)), Color::parse("cyan"));
+        assert_eq!(None, Color::parse("#fff"));
+
+        assert_eq!(Some(Length::Percent(80.)), Length::parse("80%"));
+        assert_eq!(Some(Length::Cells(1.5)), Length::parse("1.5c"));
+        assert_eq!(None, Length::parse("12"));
+
+        let xml = r#"<p xmlns:tts="http://www.w3.org/ns/ttml#styling" tts:color="yellow" tts:fontSize="1c 2c" tts:textOutline="black 5%" tts:textAlign="start" tts:wrapOption="noWrap" tts:origin="10% 80%"/>"#;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let style = Style::parse(&doc.root_element()).unwrap();
+        assert_eq!(Some(Color::rgba(255, 255, 0, 255)), style.color);
+        assert_eq!(Some(Length::Cells(2.)), style.font_size);
+        assert_eq!(
+            Some(Outline {
+                color: Some(Color::rgba(0, 0, 0, 255)),
+                thickness: Length::Percent(5.),
+            }),
+            style.text_outline
+        );
+        assert_eq!(Some(TextAlign::Left), style.text_align);
+        assert_eq!(Some(false), style.wrap);
+        assert_eq!(
+            Some((Length::Percent(10.), Length::Percent(80.))),
+            style.origin
+        );
+    }
+
+    #[test]
+    fn inherit() {
+        let parent = Style {
+            font_size: Some(Length::Percent(50.)),
+            background_color: Some(Color::rgba(0, 0, 0, 255)),
+            ..Style::default()
+        }
+        .inherit(&Style::initial());
+
+        let style = Style {
+            font_size: Some(Length::Percent(200.)),
+            bold: Some(true),
+            ..Style::default()
+        }
+        .inherit(&parent);
+
+        assert_eq!(Some(Length::Cells(1.)), style.font_size);
+        assert_eq!(Some(true), style.bold);
+        assert_eq!(Some(false), style.italic);
+        assert_eq!(None, style.background_color);
+
+        let mut merged = parent.clone();
+        merged.merge(&style);
+        assert_eq!(Some(true), merged.bold);
+        assert_eq!(parent.background_color, merged.background_color);
+    }
+}
@@ -0,0 +1,140 @@
+// SPDX-FileCopyrightText: 2020 Sveriges Television AB
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use roxmltree::Node;
+
+use crate::document::TTP_NS;
+
+// The timing parameters of a TTML document, all time bases are treated as
+// media time.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TimeParams {
+    // Effective frame rate, including the multiplier.
+    frame_rate: f64,
+    tick_rate: f64,
+}
+
+impl TimeParams {
+    pub(crate) fn from_root(root: &Node) -> anyhow::Result<TimeParams> {
+        let frame_rate = root
+            .attribute((TTP_NS, "frameRate"))
+            .map(|v| v.trim().parse::<f64>())
+            .transpose()?;
+
+        let multiplier = match root.attribute((TTP_NS, "frameRateMultiplier")) {
+            Some(v) => {
+                let n = v
+                    .split_whitespace()
+                    .map(|n| n.parse::<f64>())
+                    .collect::<Result<Vec<f64>, _>>()?;
+                match n[..] {
+                    [num, den] if den > 0. => num / den,
+                    _ => return Err(anyhow::anyhow!("invalid frameRateMultiplier {}", v)),
+                }
+            }
+
+            None => 1.,
+        };
+
+        let frame_rate = frame_rate.unwrap_or(30.) * multiplier;
+        let tick_rate = match root.attribute((TTP_NS, "tickRate")) {
+            Some(v) => v.trim().parse::<f64>()?,
+            None if root.has_attribute((TTP_NS, "frameRate")) => frame_rate,
+            None => 1.,
+        };
+
+        if frame_rate <= 0. || tick_rate <= 0. {
+            return Err(anyhow::anyhow!("invalid frame or tick rate"));
+        }
+
+        Ok(TimeParams {
+            frame_rate,
+            tick_rate,
+        })
+    }
+
+    // Parses a clock time, e.g. `00:00:01.500` or `00:00:01:12`, or an
+    // offset time, e.g. `1.5s` or `36f`, into milliseconds.
+    pub(crate) fn parse(&self, s: &str) -> anyhow::Result<f64> {
+        let s = s.trim();
+        let invalid = || anyhow::anyhow!("invalid time expression {}", s);
+        let millis = if s.contains(':') {
+            let parts: Vec<&str> = s.split(':').collect();
+            if parts.len() != 3 && parts.len() != 4 {
+                return Err(invalid());
+            }
+
+            let h: f64 = parts[0].parse().map_err(|_| invalid())?;
+            let m: f64 = parts[1].parse().map_err(|_| invalid())?;
+            let secs: f64 = parts[2].parse().map_err(|_| invalid())?;
+            let frames: f64 = match parts.get(3) {
+                // Sub-frames are ignored.
+                Some(f) => f
+                    .split('.')
+                    .next()
+                    .unwrap()
+                    .parse()
+                    .map_err(|_| invalid())?,
+                None => 0.,
+            };
+
+            ((h * 60. + m) * 60. + secs + frames / self.frame_rate) * 1000.
+        } else {
+            let split = s
+                .find(|c: char| c.is_ascii_alphabetic())
+                .ok_or_else(invalid)?;
+            let value: f64 = s[..split].parse().map_err(|_| invalid())?;
+            match &s[split..] {
+                "h" => value * 3_600_000.,
+                "m" => value * 60_000.,
+                "s" => value * 1000.,
+                "ms" => value,
+                "f" => value * 1000. / self.frame_rate,
+                "t" => value * 1000. / self.tick_rate,
+                _ => return Err(invalid()),
+            }
+        };
+
+        if !millis.is_finite() || millis < 0. {
+            return Err(invalid());
+        }
+
+        Ok(millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(attrs: &str) -> TimeParams {
+        let xml = format!(
+            r#"<tt xmlns="http://www.w3.org/ns/ttml" xmlns:ttp="http://www.w3.org/ns/ttml#parameter" {}/>"#,
+            attrs
+        );
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        TimeParams::from_root(&doc.root_element()).unwrap()
+    }
+
+    #[test]
+    fn times() {
+        let p = params("");
+        assert_eq!(3_723_500., p.parse("01:02:03.500").unwrap());
+        assert_eq!(1500., p.parse("1.5s").unwrap());
+        assert_eq!(120_000., p.parse("2m").unwrap());
+        assert_eq!(250., p.parse("250ms").unwrap());
+        assert_eq!(1000., p.parse("30f").unwrap());
+        assert_eq!(3000., p.parse("3t").unwrap());
+        assert!(p.parse("1.5").is_err());
+        assert!(p.parse("1x").is_err());
+        assert!(p.parse("00:01").is_err());
+
+        let p = params(r#"ttp:frameRate="25" ttp:tickRate="10000000""#);
+        assert_eq!(1480., p.parse("00:00:01:12").unwrap());
+        assert_eq!(1000., p.parse("10000000t").unwrap());
+
+        let p = params(r#"ttp:frameRate="30" ttp:frameRateMultiplier="1000 1001""#);
+        assert_eq!(1001., p.parse("30f").unwrap());
+    }
+}